@@ -0,0 +1,126 @@
+// Compares the input paths, and the tree evaluator with the bytecode program, on generated log data.
+//
+//   cargo bench --bench throughput
+//
+// The data is generated from a fixed pattern, so runs are comparable across machines and commits.
+
+use chaf::core::bytecode::Program;
+use chaf::core::evaluator::{EvalContext, Evaluator};
+use chaf::core::filter::build_filter;
+use chaf::core::parser::parse_query;
+use chaf::core::record::{Record, RecordOptions};
+use chaf::engine::{run_filter_with, run_mmap, run_parallel, RunOptions};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use tempfile::NamedTempFile;
+
+const LINES: usize = 200_000;
+const QUERY: &str = "DEBUG | healthcheck | \"cache hit\"";
+const NESTED_QUERY: &str =
+    "(DEBUG & !\"worker-3\") | (WARN & (timeout | retry) & !id=1) | line in 100..200";
+
+fn generate() -> NamedTempFile {
+    let file = NamedTempFile::new().unwrap();
+    let mut writer = BufWriter::new(file.as_file());
+    let levels = ["INFO", "DEBUG", "WARN", "INFO", "ERROR", "DEBUG"];
+    let messages = [
+        "request handled",
+        "cache hit for key",
+        "healthcheck ok",
+        "upstream timeout after retry",
+        "user login succeeded",
+    ];
+    for i in 0..LINES {
+        writeln!(
+            writer,
+            "2024-05-01T12:{:02}:{:02}.{:03}Z {} [worker-{}] {} id={} dur={}ms",
+            i / 60_000 % 60,
+            i / 1000 % 60,
+            i % 1000,
+            levels[i % levels.len()],
+            i % 16,
+            messages[i * 7 % messages.len()],
+            i * 2_654_435_761 % 1_000_003,
+            i % 977,
+        )
+        .unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    file
+}
+
+fn bench_literal_query(c: &mut Criterion) {
+    let data = generate();
+    let size = data.as_file().metadata().unwrap().len();
+    let ast = parse_query(QUERY).unwrap();
+    let options = RunOptions::default();
+
+    let mut group = c.benchmark_group("literal_query");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(20);
+
+    group.bench_function("read_until", |b| {
+        b.iter(|| {
+            let filter = build_filter(&ast, false).unwrap();
+            let reader = BufReader::new(File::open(data.path()).unwrap());
+            run_filter_with(reader, &mut io::sink(), filter, &options).unwrap()
+        })
+    });
+    group.bench_function("mmap", |b| {
+        b.iter(|| {
+            let filter = build_filter(&ast, false).unwrap();
+            let file = File::open(data.path()).unwrap();
+            run_mmap(file, &mut io::sink(), filter, &options).unwrap()
+        })
+    });
+    group.bench_function("parallel_4", |b| {
+        b.iter(|| {
+            let filter = build_filter(&ast, false).unwrap();
+            let file = File::open(data.path()).unwrap();
+            run_parallel(file, &mut io::sink(), filter, &options, 4).unwrap()
+        })
+    });
+    group.finish();
+}
+
+// The tree-walking evaluator against the bytecode program, on lines already in memory.
+fn bench_evaluator(c: &mut Criterion) {
+    let data = generate();
+    let text = std::fs::read_to_string(data.path()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let ast = parse_query(NESTED_QUERY).unwrap();
+    let options = RecordOptions::default();
+    let tree = Evaluator::compile(&ast, &options, false);
+    let program = Program::compile(&ast, &options, false);
+
+    let mut group = c.benchmark_group("evaluator");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.sample_size(20);
+
+    group.bench_function("tree", |b| {
+        b.iter(|| {
+            let mut matched = 0;
+            for (i, line) in lines.iter().enumerate() {
+                let record = Record::new(line, &options);
+                matched += usize::from(tree.matches(&record, &EvalContext::new(i + 1)));
+            }
+            black_box(matched)
+        })
+    });
+    group.bench_function("bytecode", |b| {
+        b.iter(|| {
+            let mut matched = 0;
+            for (i, line) in lines.iter().enumerate() {
+                let record = Record::new(line, &options);
+                matched += usize::from(program.matches(&record, &EvalContext::new(i + 1)));
+            }
+            black_box(matched)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_literal_query, bench_evaluator);
+criterion_main!(benches);
@@ -1,4 +1,11 @@
-use clap::Parser;
+use crate::core::parser::{QueryLimits, QueryParams};
+use crate::core::record::{Delimiter, Format, InvalidPolicy};
+use crate::core::time::Zone;
+use crate::core::value::{parse_as, Unit};
+use crate::engine::LongLinePolicy;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -6,11 +13,31 @@ use std::path::PathBuf;
     name = "chaf",
     version = env!("CARGO_PKG_VERSION"),
     about = env!("CARGO_PKG_DESCRIPTION"),
-    long_about = None
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 pub struct CliOptions {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // `chaf QUERY [FILE]` is short for `chaf filter QUERY [FILE]`
+    #[command(flatten)]
+    pub filter: FilterOptions,
+}
+
+#[derive(Args, Debug)]
+pub struct FilterOptions {
     #[arg(name = "QUERY")]
-    pub query: String,
+    #[cfg_attr(
+        feature = "serde",
+        arg(required_unless_present_any = ["query_file", "profile", "query_json"])
+    )]
+    #[cfg_attr(
+        not(feature = "serde"),
+        arg(required_unless_present_any = ["query_file", "profile"])
+    )]
+    pub query: Option<String>,
 
     #[arg(name = "FILE")]
     pub input_file: Option<PathBuf>,
@@ -20,8 +47,344 @@ pub struct CliOptions {
 
     #[arg(short, long)]
     pub invert: bool,
+
+    /// Match literals and text comparisons without regard to case
+    #[arg(long)]
+    pub ignore_case: bool,
+
+    /// Stop after N lines have been output
+    #[arg(short = 'm', long, value_name = "N")]
+    pub max_count: Option<usize>,
+
+    /// Stop at the first line matching this query (the line itself is not output)
+    #[arg(long, value_name = "QUERY")]
+    pub stop_when: Option<String>,
+
+    /// Seed for `sample(...)`; runs with the same seed keep the same lines (random by default)
+    #[arg(long, value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// Drop lines that were already output
+    #[arg(long)]
+    pub uniq: bool,
+
+    /// Number of distinct lines `--uniq` remembers; older ones are forgotten
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    pub uniq_cap: usize,
+
+    /// Fold consecutive repeats into one line followed by "(repeated N times)"
+    #[arg(long)]
+    pub collapse: bool,
+
+    /// Mask numbers, UUIDs and hex IDs when comparing lines for `--uniq` / `--collapse`
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Filter a file on N threads (0: one per CPU); stdin and cross-line features use one.
+    /// Like --mmap, maps the file
+    #[arg(short = 'j', long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Read the file through a memory map, which is faster; if the file is truncated while it is
+    /// read (e.g. by logrotate's copytruncate), chaf is killed with SIGBUS
+    #[arg(long)]
+    pub mmap: bool,
+
+    /// Field separator for `$N` and `col(name)`: a character, `tab` or `whitespace`
+    #[arg(short, long, value_name = "DELIM", default_value = "whitespace")]
+    pub delimiter: Delimiter,
+
+    /// Treat the first line as a header naming the columns (it is always output)
+    #[arg(long)]
+    pub header: bool,
+
+    /// Input format: `plain` (default), `jsonl` (paths such as `.level`), `logfmt` (names such as
+    /// `level`), or a preset: `syslog`, `combined`, `journal`, `journal-export`
+    #[arg(short = 'f', long, value_name = "FORMAT")]
+    pub format: Option<Format>,
+
+    /// Lines that fail to parse in FORMAT: `raw` (match literals only), `keep` or `drop`
+    #[arg(long, value_name = "POLICY", default_value = "raw")]
+    pub on_invalid: InvalidPolicy,
+
+    /// Drop lines before this time (`14:00`, `2024-05-01` or a full timestamp)
+    #[arg(long, value_name = "TIME")]
+    pub since: Option<String>,
+
+    /// Drop lines at or after this time
+    #[arg(long, value_name = "TIME")]
+    pub until: Option<String>,
+
+    /// strftime pattern for timestamps (e.g. `%d.%m.%Y %H:%M:%S`); auto-detected if omitted
+    #[arg(long, value_name = "PATTERN")]
+    pub time_format: Option<String>,
+
+    /// Zone for timestamps without an offset: `local`, `UTC` or `+HH:MM`
+    #[arg(long, value_name = "ZONE", default_value = "local")]
+    pub timezone: Zone,
+
+    /// Reject queries that nest parentheses, `!` or function calls deeper than this
+    #[arg(long, value_name = "N", default_value_t = 256, value_parser = limit(QueryLimits::MAX_DEPTH))]
+    pub max_depth: usize,
+
+    /// Reject queries with more terms than this
+    #[arg(long, value_name = "N", default_value_t = 10_000, value_parser = limit(QueryLimits::MAX_TERMS))]
+    pub max_terms: usize,
+
+    /// Longest line (or record) read whole, in bytes (`1048576`, `64MiB`)
+    #[arg(long, value_name = "SIZE", default_value = "64MiB", value_parser = parse_size)]
+    pub max_line_length: usize,
+
+    /// Lines longer than --max-line-length: `truncate` (filter and output the first SIZE bytes),
+    /// `pass` (output unfiltered), `drop` or `error`
+    #[arg(long, value_name = "POLICY", default_value = "error")]
+    pub on_long_line: LongLinePolicy,
+
+    /// Print the program the query compiles to and exit
+    #[arg(long)]
+    pub dump_bytecode: bool,
+
+    /// Read the query from FILE instead of QUERY; lines starting with `#` are comments
+    #[arg(short = 'F', long, value_name = "FILE", conflicts_with = "profile")]
+    pub query_file: Option<PathBuf>,
+
+    /// Use the query and options of a profile in chaf.toml instead of QUERY
+    #[arg(short = 'p', long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Value of the placeholder `$NAME` in the query, taken as a literal (repeatable)
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
+
+    /// Read the query as a JSON syntax tree from FILE (as printed by --emit-json) instead of QUERY
+    #[cfg(feature = "serde")]
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["query_file", "profile"])]
+    pub query_json: Option<PathBuf>,
+
+    /// Print the query as a JSON syntax tree and exit
+    #[cfg(feature = "serde")]
+    #[arg(long)]
+    pub emit_json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Filter lines (the default command)
+    Filter(Box<FilterOptions>),
+    /// Describe in words which lines a query matches
+    Explain(ExplainOptions),
+    /// Print a query in canonical form
+    Fmt(FmtOptions),
+    /// Check queries for errors without reading any input
+    Check(CheckOptions),
+    /// Run the `# keep:` / `# drop:` examples in query files
+    Test(TestOptions),
+    /// Print a completion script for a shell
+    Completions(CompletionsOptions),
+    /// Edit a query interactively over a preview of FILE
+    #[cfg(feature = "tui")]
+    Tui(TuiOptions),
+}
+
+// The query of a command other than `filter`: QUERY or one or more query files.
+#[derive(Args, Debug)]
+pub struct QueryInput {
+    /// Query text
+    #[arg(name = "QUERY", required_unless_present = "query_files")]
+    pub query: Option<String>,
+
+    /// Read queries from these files instead of QUERY; lines starting with `#` are comments
+    #[arg(
+        short = 'F',
+        long = "query-file",
+        value_name = "FILE",
+        conflicts_with = "QUERY"
+    )]
+    pub query_files: Vec<PathBuf>,
+
+    /// Format the query is parsed for (see `chaf filter --help`)
+    #[arg(short = 'f', long, value_name = "FORMAT", default_value = "plain")]
+    pub format: Format,
+}
+
+#[derive(Args, Debug)]
+pub struct ExplainOptions {
+    #[command(flatten)]
+    pub input: QueryInput,
+
+    /// Explain the query as used with `--invert`
+    #[arg(short, long)]
+    pub invert: bool,
+
+    /// Value of the placeholder `$NAME` in the query, taken as a literal (repeatable)
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+pub struct FmtOptions {
+    #[command(flatten)]
+    pub input: QueryInput,
+
+    /// Name each query not in canonical form on stderr and exit with status 1; print no queries
+    #[arg(long)]
+    pub check: bool,
+
+    /// Rewrite the query files in place instead of printing them
+    #[arg(short, long, requires = "query_files", conflicts_with = "check")]
+    pub write: bool,
+
+    /// Break `|` chains into one operand per line past this many columns
+    #[arg(long, value_name = "N", default_value_t = 80)]
+    pub width: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckOptions {
+    #[command(flatten)]
+    pub input: QueryInput,
+
+    /// Check the query for input with a header row, which `col(name)` needs
+    #[arg(long)]
+    pub header: bool,
+
+    /// Value of the placeholder `$NAME` in the query, taken as a literal (repeatable)
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+pub struct TestOptions {
+    /// Query files with `# keep: LINE` and `# drop: LINE` examples
+    #[arg(name = "FILE", required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Format the queries and examples are parsed for
+    #[arg(short = 'f', long, value_name = "FORMAT", default_value = "plain")]
+    pub format: Format,
+
+    /// Test the queries as used with `--invert`: `keep` examples must match
+    #[arg(short, long)]
+    pub invert: bool,
+
+    /// Test the queries as used with `--ignore-case`
+    #[arg(long)]
+    pub ignore_case: bool,
+
+    /// Value of the placeholder `$NAME` in the query, taken as a literal (repeatable)
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args, Debug)]
+pub struct TuiOptions {
+    /// File whose lines are previewed
+    #[arg(name = "FILE")]
+    pub file: PathBuf,
+
+    /// Preview only the first N lines of FILE
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    pub lines: usize,
+
+    /// Query file the query is read from, if it exists, and saved to with Ctrl-S
+    #[arg(short = 'F', long, value_name = "FILE", default_value = "query.chaf")]
+    pub query_file: PathBuf,
+
+    /// Format the query is parsed for (see `chaf filter --help`)
+    #[arg(short = 'f', long, value_name = "FORMAT", default_value = "plain")]
+    pub format: Format,
+
+    /// Preview the query as used with `--invert`
+    #[arg(short, long)]
+    pub invert: bool,
+
+    /// Preview the query as used with `--ignore-case`
+    #[arg(long)]
+    pub ignore_case: bool,
+
+    /// Value of the placeholder `$NAME` in the query, taken as a literal (repeatable)
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub params: Vec<(String, String)>,
 }
 
-pub fn parse_args() -> CliOptions {
-    CliOptions::parse()
+#[derive(Args, Debug)]
+pub struct CompletionsOptions {
+    /// Shell to print the script for
+    #[arg(name = "SHELL")]
+    pub shell: Shell,
+}
+
+// A byte count, with or without a unit.
+fn parse_size(s: &str) -> Result<usize, String> {
+    s.parse::<usize>()
+        .ok()
+        .or_else(|| parse_as(s, Unit::Bytes).map(|n| n as usize))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("Invalid size '{}' (expected e.g. 1048576 or 64MiB)", s))
+}
+
+// A query limit from 1 to `max`; past the maximum the parser's recursion overflows the stack.
+fn limit(max: usize) -> clap::builder::RangedU64ValueParser<usize> {
+    clap::builder::RangedU64ValueParser::new().range(1..=max as u64)
+}
+
+// `name=value` for `--param`.
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("Invalid parameter '{}' (expected NAME=VALUE)", s)),
+    }
+}
+
+// The placeholder values of a query, from `--param` options and the environment.
+pub fn query_params(params: &[(String, String)]) -> QueryParams {
+    params.iter().fold(
+        QueryParams::new().with_process_env(),
+        |acc, (name, value)| acc.with_param(name, value),
+    )
+}
+
+// Parses the command line; without a subcommand, the command is `filter`.
+pub fn parse_args() -> Command {
+    let opts = CliOptions::parse();
+    let mut command = opts
+        .command
+        .unwrap_or(Command::Filter(Box::new(opts.filter)));
+    if let Command::Filter(filter) = &mut command {
+        filter.shift_positionals();
+    }
+    command
+}
+
+impl FilterOptions {
+    // When the query comes from an option, the only positional argument is the input file.
+    fn shift_positionals(&mut self) {
+        let Some(option) = self.query_option() else {
+            return;
+        };
+        if let Some(file) = self.query.take() {
+            if self.input_file.is_some() {
+                CliOptions::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("QUERY cannot be used with {option}"),
+                    )
+                    .exit();
+            }
+            self.input_file = Some(file.into());
+        }
+    }
+
+    // The option the query is read from instead of QUERY, if any.
+    fn query_option(&self) -> Option<&'static str> {
+        #[cfg(feature = "serde")]
+        if self.query_json.is_some() {
+            return Some("--query-json");
+        }
+        if self.profile.is_some() {
+            return Some("--profile");
+        }
+        self.query_file.as_ref().map(|_| "--query-file")
+    }
 }
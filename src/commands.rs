@@ -0,0 +1,288 @@
+use chaf::ast::AstNode;
+#[cfg(feature = "tui")]
+use chaf::cli::TuiOptions;
+use chaf::cli::{
+    query_params, CheckOptions, CliOptions, CompletionsOptions, ExplainOptions, FmtOptions,
+    QueryInput, TestOptions,
+};
+use chaf::core::config::resolve_profiles;
+use chaf::core::evaluator::EvalContext;
+use chaf::core::explain::explain as describe;
+use chaf::core::filter::{build_filter_with, LineFilter};
+use chaf::core::parser::{parse_query_with_params, print_template, QueryLimits, QueryParams};
+use chaf::core::query_file::QueryFile;
+use chaf::core::record::{Delimiter, InvalidPolicy, RecordOptions};
+#[cfg(feature = "tui")]
+use chaf::tui::{App, TuiSettings};
+use chaf::Error;
+use clap::CommandFactory;
+use std::io::{self, Write};
+use tracing::info;
+
+// Each command returns the exit status.
+
+// `chaf explain`: describes QUERY or each query file in words.
+pub fn explain(opts: &ExplainOptions) -> i32 {
+    let mut status = 0;
+    let titled = opts.input.query_files.len() > 1;
+    let params = query_params(&opts.params);
+    for_each_query(&opts.input, &params, |name, ast| match ast {
+        Ok(ast) => {
+            if titled {
+                println!("{name}:");
+            }
+            print!("{}", describe(&ast, opts.invert));
+        }
+        Err(e) => {
+            eprintln!("{name}: {e}");
+            status = 1;
+        }
+    });
+    status
+}
+
+// `chaf fmt`: prints QUERY or each query file in canonical form.
+pub fn fmt(opts: &FmtOptions) -> i32 {
+    let limits = QueryLimits::default();
+    let format = opts.input.format;
+    if let Some(query) = &opts.input.query {
+        let text = match print_template(query, format, limits, |ast| ast.pretty(opts.width)) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Syntax error: {e}");
+                return 1;
+            }
+        };
+        if opts.check {
+            if text != *query {
+                eprintln!("Query is not formatted");
+                return 1;
+            }
+        } else {
+            println!("{text}");
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for path in &opts.input.query_files {
+        let (original, formatted) = match std::fs::read_to_string(path) {
+            Ok(text) => match QueryFile::new(&text).canonical(format, limits, opts.width) {
+                Ok(formatted) => (text, formatted),
+                Err(e @ Error::Syntax { .. }) => {
+                    eprintln!("Syntax error in {}: {e}", path.display());
+                    status = 1;
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Cannot format {}: {e}", path.display());
+                    status = 1;
+                    continue;
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to read {}: {e}", path.display());
+                status = 1;
+                continue;
+            }
+        };
+        if opts.check {
+            if formatted != original {
+                eprintln!("Not formatted: {}", path.display());
+                status = 1;
+            }
+        } else if opts.write {
+            if formatted != original {
+                if let Err(e) = std::fs::write(path, formatted) {
+                    eprintln!("Failed to write {}: {e}", path.display());
+                    status = 1;
+                    continue;
+                }
+                info!("formatted {}", path.display());
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    status
+}
+
+// `chaf check`: parses and compiles QUERY or each query file, reporting errors.
+pub fn check(opts: &CheckOptions) -> i32 {
+    let mut status = 0;
+    let params = query_params(&opts.params);
+    for_each_query(&opts.input, &params, |name, ast| {
+        let options = RecordOptions::new(Delimiter::default(), opts.header)
+            .with_format(opts.input.format, InvalidPolicy::Raw);
+        let ast = ast.and_then(|ast| {
+            resolve_profiles(ast, opts.input.format, QueryLimits::default(), &params)
+        });
+        match ast.and_then(|ast| LineFilter::new(&ast, false, options)) {
+            Ok(_) => println!("{name}: ok"),
+            Err(e) => {
+                eprintln!("{name}: {e}");
+                status = 1;
+            }
+        }
+    });
+    status
+}
+
+// `chaf test`: runs the `# keep:` / `# drop:` examples of each query file. Prints one line per
+// example, in file order and without timings, so runs can be compared with `diff`.
+pub fn test(opts: &TestOptions) -> i32 {
+    let (mut passed, mut failed, mut errors) = (0, 0, 0);
+    let params = query_params(&opts.params);
+    for path in &opts.files {
+        let file = match QueryFile::read(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to read {}: {e}", path.display());
+                errors += 1;
+                continue;
+            }
+        };
+        let options = RecordOptions::new(Delimiter::default(), false)
+            .with_format(opts.format, InvalidPolicy::Raw)
+            .with_ignore_case(opts.ignore_case);
+        let limits = QueryLimits::default();
+        let filter = match parse_query_with_params(file.query(), opts.format, limits, &params)
+            .and_then(|ast| resolve_profiles(ast, opts.format, limits, &params))
+            .and_then(|ast| build_filter_with(&ast, opts.invert, options))
+        {
+            Ok(filter) => filter,
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                errors += 1;
+                continue;
+            }
+        };
+        let examples = file.examples();
+        if examples.is_empty() {
+            eprintln!("{}: no examples", path.display());
+        }
+        // Examples are consecutive lines of one input, for queries that look at earlier lines
+        for (i, example) in examples.iter().enumerate() {
+            let expected = if example.keep { "keep" } else { "drop" };
+            let location = format!("{}:{}", path.display(), example.line);
+            match filter(example.text.as_bytes(), &EvalContext::new(i + 1)) {
+                Ok(kept) if kept == example.keep => {
+                    passed += 1;
+                    println!("PASS {location} {expected}: {}", example.text);
+                }
+                Ok(kept) => {
+                    failed += 1;
+                    let actual = if kept { "kept" } else { "dropped" };
+                    println!(
+                        "FAIL {location} {expected}: {} (the line was {actual})",
+                        example.text
+                    );
+                }
+                Err(e) => {
+                    failed += 1;
+                    println!("FAIL {location} {expected}: {} ({e})", example.text);
+                }
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+    if failed > 0 || errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// `chaf completions`: prints the completion script for a shell.
+pub fn completions(opts: &CompletionsOptions) -> i32 {
+    // `generate` panics if it cannot write, so render the script first
+    let mut script = Vec::new();
+    clap_complete::generate(opts.shell, &mut CliOptions::command(), "chaf", &mut script);
+    match io::stdout().write_all(&script) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Failed to write the completion script: {e}");
+            1
+        }
+    }
+}
+
+// `chaf tui`: edits the query of a query file over a live preview of FILE.
+#[cfg(feature = "tui")]
+pub fn tui(opts: &TuiOptions) -> i32 {
+    let (lines, more) = match read_preview(&opts.file, opts.lines) {
+        Ok(preview) => preview,
+        Err(e) => {
+            eprintln!("Failed to open file: {e} at path: {}", opts.file.display());
+            return 1;
+        }
+    };
+    // The editor is one line, so a query over several lines is joined
+    let query = match QueryFile::read(&opts.query_file) {
+        Ok(file) => file
+            .query()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            eprintln!("Failed to read {}: {e}", opts.query_file.display());
+            return 1;
+        }
+    };
+    let settings = TuiSettings {
+        format: opts.format,
+        invert: opts.invert,
+        ignore_case: opts.ignore_case,
+        params: query_params(&opts.params),
+    };
+    let mut app = App::new(lines, query, opts.query_file.clone(), settings).truncated(more);
+    match chaf::tui::run(&mut app) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Terminal error: {e}");
+            1
+        }
+    }
+}
+
+// The first `max` lines of `path`, and whether it has more.
+#[cfg(feature = "tui")]
+fn read_preview(path: &std::path::Path, max: usize) -> io::Result<(Vec<String>, bool)> {
+    use std::io::BufRead;
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    while lines.len() < max {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok((lines, false));
+        }
+        let line = String::from_utf8_lossy(&buf);
+        lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+    }
+    Ok((lines, !reader.fill_buf()?.is_empty()))
+}
+
+// Parses QUERY, or each query file, and hands `f` a name for messages along with the result.
+fn for_each_query(
+    input: &QueryInput,
+    params: &QueryParams,
+    mut f: impl FnMut(&str, chaf::Result<AstNode>),
+) {
+    let limits = QueryLimits::default();
+    if let Some(query) = &input.query {
+        f(
+            "query",
+            parse_query_with_params(query, input.format, limits, params),
+        );
+    }
+    for path in &input.query_files {
+        let ast = QueryFile::read(path)
+            .map_err(chaf::Error::from)
+            .and_then(|file| parse_query_with_params(file.query(), input.format, limits, params));
+        f(&path.display().to_string(), ast);
+    }
+}
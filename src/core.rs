@@ -1,4 +1,12 @@
-mod ast;
+pub mod ast;
+pub mod bytecode;
+pub mod config;
 pub mod evaluator;
+pub mod explain;
 pub mod filter;
+pub mod formats;
 pub mod parser;
+pub mod query_file;
+pub mod record;
+pub mod time;
+pub mod value;
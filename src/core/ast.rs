@@ -1,16 +1,519 @@
-// Represents a node in the abstract syntax tree (AST) for the logical query DSL.
-#[derive(Debug, Clone)]
+//! The syntax tree of a parsed query.
+//!
+//! Obtained from [`Query::ast`](crate::Query::ast). The enums are `#[non_exhaustive]`, as the query
+//! language grows new constructs; match them with a wildcard arm.
+//!
+//! A tree prints back as query text with `Display`, which parses into the same tree. With the
+//! `serde` feature, the tree can also be written and read as JSON.
+
+use std::fmt;
+use std::ops::Bound;
+use std::time::Duration;
+
+/// A node in the syntax tree of a query.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum AstNode {
+    /// `a & b`
     AndNode(Box<AstNode>, Box<AstNode>),
+    /// `a | b`
     OrNode(Box<AstNode>, Box<AstNode>),
+    /// `!a`
     NotNode(Box<AstNode>),
+    /// A leaf predicate.
     Match(Pattern),
+    /// Matches every line from a line matching the first node through a line matching the second.
+    Between(Box<AstNode>, Box<AstNode>, BlockBounds),
+    /// Matches the node, except for the lines chosen by `Thinning`, which survive the filter.
+    Thin(Box<AstNode>, Thinning),
+    /// eg: `@profile(k8s-noise)` — the query of a profile in `chaf.toml`, resolved before the
+    /// query is compiled
+    Profile(String),
+}
+
+/// How `sample(...)` / `limit(...)` choose which matching lines survive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Thinning {
+    /// eg: `sample(0.01, ...)` — each line survives with this probability
+    Sample(f64),
+    /// eg: `limit(10/s, ...)` — at most `count` lines survive per `per`
+    Limit { count: u64, per: Duration },
 }
 
-// Represents a leaf pattern in the query DSL.
-#[derive(Debug, Clone)]
+/// Whether the start/end marker lines of a `between(...)` block are part of the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum BlockBounds {
+    /// `between(a, b)`
+    Inclusive,
+    /// `between(a, b, exclusive)`
+    Exclusive,
+}
+
+/// A leaf predicate of a query.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum Pattern {
-    Literal(String), // eg: "log"
-                     //    Wildcard(String),     // eg: "*.log"(Planned additions in the future)
-                     //    Regex(regex::Regex),  // Compiled Regular Expressions(Planned additions in the future)
+    /// eg: `log`
+    Literal(String),
+    /// eg: `line<=20`
+    Compare(Key, CompareOp, String),
+    /// eg: `line in 1000..2000`
+    InRange(Key, Bound<String>, Bound<String>),
+    /// eg: `has(.trace_id)`
+    Has(Key),
+    // Wildcard(String),     // eg: "*.log"(Planned additions in the future)
+    // Regex(regex::Regex),  // Compiled Regular Expressions(Planned additions in the future)
+}
+
+/// The per-line value a comparison is evaluated against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Key {
+    /// `line`: the 1-based line number
+    Line,
+    /// `time`: the timestamp found in the line
+    Time,
+    /// eg: `$3` (1-based field; `$0` is the whole line)
+    Index(usize),
+    /// eg: `col(status)` (named by the header row)
+    Column(String),
+    /// eg: `.http.status` (JSON Lines)
+    Path(Vec<String>),
+    /// eg: `level` in `level=debug` (structured formats only)
+    Name(String),
+}
+
+/// The operator of a [`Pattern::Compare`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum CompareOp {
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+    /// `=`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `~`
+    Contains,
+}
+
+impl Pattern {
+    /// Returns the key a field predicate applies to, if any.
+    pub fn key(&self) -> Option<&Key> {
+        match self {
+            Pattern::Literal(_) => None,
+            Pattern::Compare(key, _, _) | Pattern::InRange(key, _, _) | Pattern::Has(key) => {
+                Some(key)
+            }
+        }
+    }
+}
+
+impl AstNode {
+    /// Returns `true` if any leaf pattern in the tree satisfies `f`.
+    // Walks the tree with an explicit stack, so generated queries may nest deeply.
+    pub fn any_pattern(&self, f: &dyn Fn(&Pattern) -> bool) -> bool {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                AstNode::AndNode(lhs, rhs)
+                | AstNode::OrNode(lhs, rhs)
+                | AstNode::Between(lhs, rhs, _) => stack.extend([&**rhs, &**lhs]),
+                AstNode::NotNode(inner) | AstNode::Thin(inner, _) => stack.push(inner),
+                AstNode::Match(pattern) => {
+                    if f(pattern) {
+                        return true;
+                    }
+                }
+                AstNode::Profile(_) => {}
+            }
+        }
+        false
+    }
+
+    // `a | b | c | ...` as the parser builds it: a balanced tree, so a chain of a million terms
+    // is only 20 levels deep for the code that walks it recursively. `None` without operands.
+    pub(crate) fn or_chain(operands: Vec<AstNode>) -> Option<AstNode> {
+        chain(operands, AstNode::OrNode)
+    }
+
+    // `a & b & c & ...`, balanced like `or_chain`.
+    pub(crate) fn and_chain(operands: Vec<AstNode>) -> Option<AstNode> {
+        chain(operands, AstNode::AndNode)
+    }
+
+    /// Returns the names of the profiles the tree refers to with `@profile(...)`, in order.
+    pub fn profiles(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                AstNode::AndNode(lhs, rhs)
+                | AstNode::OrNode(lhs, rhs)
+                | AstNode::Between(lhs, rhs, _) => stack.extend([&**rhs, &**lhs]),
+                AstNode::NotNode(inner) | AstNode::Thin(inner, _) => stack.push(inner),
+                AstNode::Match(_) => {}
+                AstNode::Profile(name) => names.push(name.as_str()),
+            }
+        }
+        names
+    }
+}
+
+// The left half takes the middle operand of an odd number, so `a | b | c` is `(a | b) | c`.
+fn chain(
+    mut operands: Vec<AstNode>,
+    join: fn(Box<AstNode>, Box<AstNode>) -> AstNode,
+) -> Option<AstNode> {
+    match operands.len() {
+        0 => None,
+        1 => operands.pop(),
+        n => {
+            let rhs = operands.split_off(n.div_ceil(2));
+            Some(join(
+                Box::new(chain(operands, join)?),
+                Box::new(chain(rhs, join)?),
+            ))
+        }
+    }
+}
+
+// The operands of the `&` or `|` chain at `node`, if the chain has exactly the shape the parser
+// gives them written one after another. Other shapes print with parentheses.
+fn chain_operands(node: &AstNode) -> Option<Vec<&AstNode>> {
+    let same = |n: &AstNode| std::mem::discriminant(n) == std::mem::discriminant(node);
+    let mut operands = Vec::new();
+    let mut stack = vec![node];
+    while let Some(n) = stack.pop() {
+        match n {
+            AstNode::OrNode(lhs, rhs) | AstNode::AndNode(lhs, rhs) if same(n) => {
+                stack.extend([&**rhs, &**lhs])
+            }
+            _ => operands.push(n),
+        }
+    }
+    // Only descends while the shape matches, so no deeper than the balanced tree
+    fn balanced(node: &AstNode, n: usize, same: &dyn Fn(&AstNode) -> bool) -> bool {
+        match node {
+            _ if n == 1 => !same(node),
+            AstNode::OrNode(lhs, rhs) | AstNode::AndNode(lhs, rhs) if same(node) => {
+                balanced(lhs, n.div_ceil(2), same) && balanced(rhs, n / 2, same)
+            }
+            _ => false,
+        }
+    }
+    balanced(node, operands.len(), &same).then_some(operands)
+}
+
+// Prints the query text for the tree, parenthesized only where precedence requires it.
+// Literals and values are quoted when they could otherwise be read differently.
+impl fmt::Display for AstNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_node(f, self, Precedence::Or)
+    }
+}
+
+impl AstNode {
+    /// Prints the query like `Display`, but a `|` chain that does not fit in `width` columns is
+    /// broken into one operand per line, each continuation line starting with `| `. A chain in
+    /// parentheses becomes an indented block.
+    ///
+    /// ```
+    /// let ast = chaf::Query::parse("DEBUG | healthcheck | x & (a | b)")?.ast().clone();
+    /// assert_eq!(ast.pretty(80), "DEBUG | healthcheck | x & (a | b)");
+    /// assert_eq!(ast.pretty(16), "DEBUG\n| healthcheck\n| x & (a | b)");
+    /// assert_eq!(ast.pretty(8), "DEBUG\n| healthcheck\n| x & (\n    a\n    | b\n)");
+    /// # Ok::<(), chaf::Error>(())
+    /// ```
+    pub fn pretty(&self, width: usize) -> String {
+        let mut out = String::new();
+        layout(&mut out, self, Precedence::Or, 0, width);
+        out
+    }
+}
+
+// Appends `node` to `out` at `indent`, breaking `|` chains that would run past `width`.
+fn layout(out: &mut String, node: &AstNode, min: Precedence, indent: usize, width: usize) {
+    let flat = Flat(node, min).to_string();
+    let column = out[out.rfind('\n').map_or(0, |i| i + 1)..].chars().count();
+    if column + flat.chars().count() <= width {
+        out.push_str(&flat);
+        return;
+    }
+    match node {
+        AstNode::OrNode(..) => {
+            let nested = min > Precedence::Or;
+            let inner = if nested { indent + 4 } else { indent };
+            if nested {
+                out.push_str("(\n");
+                out.extend(std::iter::repeat_n(' ', inner));
+            }
+            for (i, operand) in or_operands(node).into_iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', inner));
+                    out.push_str("| ");
+                }
+                layout(out, operand, Precedence::And, inner, width);
+            }
+            if nested {
+                out.push('\n');
+                out.extend(std::iter::repeat_n(' ', indent));
+                out.push(')');
+            }
+        }
+        AstNode::AndNode(..) if min > Precedence::And => {
+            out.push('(');
+            layout(out, node, Precedence::Or, indent, width);
+            out.push(')');
+        }
+        AstNode::AndNode(lhs, rhs) => {
+            let operands = chain_operands(node).unwrap_or_else(|| vec![&**lhs, &**rhs]);
+            for (i, operand) in operands.into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(" & ");
+                }
+                layout(out, operand, Precedence::Not, indent, width);
+            }
+        }
+        AstNode::NotNode(inner) => {
+            out.push('!');
+            layout(out, inner, Precedence::Not, indent, width);
+        }
+        _ => out.push_str(&flat),
+    }
+}
+
+// The operands of a `|` chain, in order.
+fn or_operands(node: &AstNode) -> Vec<&AstNode> {
+    match node {
+        AstNode::OrNode(lhs, rhs) => chain_operands(node).unwrap_or_else(|| vec![&**lhs, &**rhs]),
+        _ => vec![node],
+    }
+}
+
+// A node printed on one line, as it appears at a position of precedence `min`.
+struct Flat<'a>(&'a AstNode, Precedence);
+
+impl fmt::Display for Flat<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_node(f, self.0, self.1)
+    }
+}
+
+// Binding strength of a position in the query; `|` binds loosest.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    Or,
+    And,
+    Not,
+}
+
+fn write_node(f: &mut fmt::Formatter<'_>, node: &AstNode, min: Precedence) -> fmt::Result {
+    let own = match node {
+        AstNode::OrNode(..) => Precedence::Or,
+        AstNode::AndNode(..) => Precedence::And,
+        _ => Precedence::Not,
+    };
+    if own < min {
+        f.write_str("(")?;
+        write_node(f, node, Precedence::Or)?;
+        return f.write_str(")");
+    }
+    match node {
+        // A chain the parser would not build as written keeps its two sides apart with parentheses
+        AstNode::OrNode(lhs, rhs) | AstNode::AndNode(lhs, rhs) => {
+            let (separator, operand) = match node {
+                AstNode::OrNode(..) => (" | ", Precedence::And),
+                _ => (" & ", Precedence::Not),
+            };
+            let operands = chain_operands(node).unwrap_or_else(|| vec![&**lhs, &**rhs]);
+            for (i, item) in operands.into_iter().enumerate() {
+                if i > 0 {
+                    f.write_str(separator)?;
+                }
+                write_node(f, item, operand)?;
+            }
+            Ok(())
+        }
+        AstNode::NotNode(inner) => {
+            f.write_str("!")?;
+            write_node(f, inner, Precedence::Not)
+        }
+        AstNode::Match(pattern) => write!(f, "{}", pattern),
+        AstNode::Between(start, end, bounds) => {
+            write!(f, "between({}, {}", start, end)?;
+            if *bounds == BlockBounds::Exclusive {
+                f.write_str(", exclusive")?;
+            }
+            f.write_str(")")
+        }
+        AstNode::Thin(inner, Thinning::Sample(rate)) => write!(f, "sample({}, {})", rate, inner),
+        AstNode::Thin(inner, Thinning::Limit { count, per }) => {
+            write!(f, "limit({}/{}, {})", count, DisplayPeriod(*per), inner)
+        }
+        AstNode::Profile(name) => write!(f, "@profile({})", name),
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Literal(text) => write_text(f, text, is_bare_literal(text)),
+            Pattern::Compare(key, op, value) => {
+                write!(f, "{} {} ", key, op)?;
+                write_text(f, value, is_bare_value(value))
+            }
+            Pattern::InRange(key, lower, upper) => {
+                write!(f, "{} in ", key)?;
+                write_range(f, lower, upper)
+            }
+            Pattern::Has(key) => write!(f, "has({})", key),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Line => f.write_str("line"),
+            Key::Time => f.write_str("time"),
+            Key::Index(n) => write!(f, "${}", n),
+            Key::Column(name) => {
+                f.write_str("col(")?;
+                let bare = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                write_text(f, name, bare && !name.is_empty())?;
+                f.write_str(")")
+            }
+            Key::Path(path) => path.iter().try_for_each(|segment| {
+                f.write_str(".")?;
+                let bare = segment
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+                write_text(f, segment, bare && !segment.is_empty())
+            }),
+            Key::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "!=",
+            CompareOp::Contains => "~",
+        })
+    }
+}
+
+// `a..b`, `a..=b`, `a..` and `..b` where possible, interval notation for an excluded lower bound.
+fn write_range(
+    f: &mut fmt::Formatter<'_>,
+    lower: &Bound<String>,
+    upper: &Bound<String>,
+) -> fmt::Result {
+    let value = |f: &mut fmt::Formatter<'_>, v: &str| write_text(f, v, is_bare_value(v));
+    match (lower, upper) {
+        (Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+            f.write_str("(")?;
+            value(f, a)?;
+            f.write_str(", ")?;
+            value(f, b)?;
+            f.write_str(if matches!(upper, Bound::Included(_)) {
+                "]"
+            } else {
+                ")"
+            })
+        }
+        _ => {
+            if let Bound::Included(a) | Bound::Excluded(a) = lower {
+                value(f, a)?;
+            }
+            match upper {
+                Bound::Included(b) => {
+                    f.write_str("..=")?;
+                    value(f, b)
+                }
+                Bound::Excluded(b) => {
+                    f.write_str("..")?;
+                    value(f, b)
+                }
+                Bound::Unbounded => f.write_str(".."),
+            }
+        }
+    }
+}
+
+// Writes `text` as is, or as a quoted string with `"` and `\` escaped.
+fn write_text(f: &mut fmt::Formatter<'_>, text: &str, bare: bool) -> fmt::Result {
+    if bare {
+        return f.write_str(text);
+    }
+    f.write_str("\"")?;
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\"")
+}
+
+// Whether a literal reads back the same without quotes: no whitespace, which the parser drops,
+// and nothing that could start an operator, a comparison, a function call or a placeholder.
+fn is_bare_literal(text: &str) -> bool {
+    !text.is_empty()
+        && !text
+            .chars()
+            .any(|c| c.is_whitespace() || "&|()!\",\\<>=~[]#".contains(c))
+        && !text.strip_prefix('$').is_some_and(|rest| {
+            rest.starts_with(|c: char| c.is_ascii_alphabetic() || "_{".contains(c))
+        })
+}
+
+// Whether a comparison value or range bound reads back the same without quotes.
+fn is_bare_value(text: &str) -> bool {
+    is_bare_literal(text) && !text.contains("..") && !text.starts_with('.') && !text.ends_with('.')
+}
+
+// A `limit(...)` period in whole days, hours, minutes or seconds if it has no fraction of a
+// second, otherwise in seconds with as many decimals as it takes.
+pub(crate) struct DisplayPeriod(pub(crate) Duration);
+
+impl fmt::Display for DisplayPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = self.0;
+        if period.subsec_nanos() != 0 {
+            return write!(f, "{}s", period.as_secs_f64());
+        }
+        let secs = period.as_secs();
+        let (n, unit) = [(86_400, "d"), (3600, "h"), (60, "m"), (1, "s")]
+            .into_iter()
+            .find(|&(size, _)| secs.is_multiple_of(size))
+            .map(|(size, unit)| (secs / size, unit))
+            .unwrap_or((secs, "s"));
+        match n {
+            1 => f.write_str(unit),
+            _ => write!(f, "{}{}", n, unit),
+        }
+    }
 }
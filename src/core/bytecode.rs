@@ -0,0 +1,594 @@
+use crate::core::ast::{AstNode, BlockBounds, Key, Thinning};
+use crate::core::evaluator::{
+    child_pos, thin_seed, time_in_range, values_equal, BlockState, EvalContext, Evaluator,
+    ThinState, TimeState, ROOT_POS,
+};
+use crate::core::record::{Record, RecordOptions};
+use crate::core::time::{TimeBound, Zone};
+use crate::core::value::{parse_as, Unit};
+use chrono::DateTime;
+use memchr::memmem::Finder;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// A query compiled into a flat instruction array.
+//
+// Instructions work on a single boolean register: predicates set it, `Not` flips it, and the
+// conditional jumps implement the short-circuit of `&` and `|`. Each code section ends with `Return`.
+// Section 0 is the query itself; the marker expressions of `between(...)` and the inner expression
+// of `sample(...)` / `limit(...)` get sections of their own, run once per line by `observe`.
+//
+// Neither compiling nor running a program recurses over the query, so deeply nested generated
+// queries don't exhaust the stack.
+pub struct Program {
+    code: Vec<Op>,
+    observers: Vec<Observer>,
+    time: Option<TimeState>, // The last timestamp seen, if the query uses `time`
+}
+
+enum Op {
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    Not,
+    Return,
+    Contains(Box<Finder<'static>>), // Boxed to keep instructions small
+    LineRange(Bound<usize>, Bound<usize>),
+    FieldEquals(Key, String),
+    FieldContains(Key, String),
+    FieldRange(Key, Unit, Bound<f64>, Bound<f64>),
+    Has(Key),
+    Const(bool),
+    InTime(Bound<TimeBound>, Bound<TimeBound>, Zone),
+    InBlock(usize), // Index into `observers`
+    Thinned(usize), // Index into `observers`
+}
+
+// Cross-line state, updated by `observe` from the result of its sections.
+enum Observer {
+    Block {
+        start: usize,
+        end: usize,
+        state: BlockState,
+    },
+    Thin {
+        inner: usize,
+        matched: AtomicBool, // Whether the inner section matched the line last observed
+        state: ThinState,
+    },
+}
+
+impl Program {
+    // Updates cross-line state and evaluates the line in one step.
+    pub fn evaluate(&self, line: &str, ctx: &EvalContext) -> bool {
+        let options = RecordOptions::default();
+        let record = Record::new(line, &options);
+        self.observe(&record, ctx);
+        self.matches(&record, ctx)
+    }
+
+    // Evaluates the line against state already updated by `observe`.
+    pub fn matches(&self, record: &Record, ctx: &EvalContext) -> bool {
+        self.run(0, record, ctx)
+    }
+
+    // Feeds the line to every stateful node. Must be called exactly once per line, in input order.
+    pub fn observe(&self, record: &Record, ctx: &EvalContext) {
+        if let Some(time) = &self.time {
+            time.update(record.timestamp());
+        }
+        // Nested observers come after their parent, and must see the line first
+        for observer in self.observers.iter().rev() {
+            match observer {
+                Observer::Block { start, end, state } => {
+                    state.advance(self.run(*start, record, ctx), self.run(*end, record, ctx));
+                }
+                Observer::Thin {
+                    inner,
+                    matched,
+                    state,
+                } => {
+                    let inner_matched = self.run(*inner, record, ctx);
+                    matched.store(inner_matched, Ordering::Relaxed);
+                    state.observe(record, inner_matched);
+                }
+            }
+        }
+    }
+
+    // Returns `true` if the result for a line depends on previous lines.
+    pub fn is_stateful(&self) -> bool {
+        self.time.is_some() || !self.observers.is_empty()
+    }
+
+    pub fn from_ast(ast: &AstNode) -> Self {
+        Self::compile(ast, &RecordOptions::default(), false)
+    }
+
+    // See `Evaluator::compile` for `options` and `invert`.
+    pub fn compile(ast: &AstNode, options: &RecordOptions, invert: bool) -> Self {
+        let mut compiler = Compiler {
+            options,
+            invert,
+            code: Vec::new(),
+            observers: Vec::new(),
+            pending: Vec::new(),
+            uses_time: false,
+        };
+        compiler.section(ast, ROOT_POS);
+        while let Some((node, pos, id, slot)) = compiler.pending.pop() {
+            let start = compiler.section(node, pos);
+            match (&mut compiler.observers[id], slot) {
+                (Observer::Block { start: at, .. }, Slot::Start)
+                | (Observer::Block { end: at, .. }, Slot::End)
+                | (Observer::Thin { inner: at, .. }, Slot::Inner) => *at = start,
+                _ => unreachable!("section does not belong to this observer"),
+            }
+        }
+
+        let mut code = compiler.code;
+        thread_jumps(&mut code);
+        Program {
+            code,
+            observers: compiler.observers,
+            time: compiler.uses_time.then(TimeState::default),
+        }
+    }
+
+    fn run(&self, start: usize, record: &Record, ctx: &EvalContext) -> bool {
+        let mut acc = false;
+        let mut pc = start;
+        loop {
+            match &self.code[pc] {
+                Op::Return => return acc,
+                Op::JumpIfFalse(target) if !acc => {
+                    pc = *target;
+                    continue;
+                }
+                Op::JumpIfTrue(target) if acc => {
+                    pc = *target;
+                    continue;
+                }
+                Op::JumpIfFalse(_) | Op::JumpIfTrue(_) => {}
+                Op::Not => acc = !acc,
+                Op::Contains(finder) => acc = finder.find(record.haystack().as_bytes()).is_some(),
+                Op::LineRange(lower, upper) => acc = (*lower, *upper).contains(&ctx.line_no),
+                Op::FieldEquals(key, s) => {
+                    acc = record.field_text(key).is_some_and(|f| values_equal(&f, s))
+                }
+                Op::FieldContains(key, s) => {
+                    acc = record
+                        .field_text(key)
+                        .is_some_and(|f| f.contains(s.as_str()))
+                }
+                Op::FieldRange(key, unit, lower, upper) => {
+                    acc = record
+                        .field(key)
+                        .and_then(|f| parse_as(&f, *unit))
+                        .is_some_and(|n| (*lower, *upper).contains(&n))
+                }
+                Op::Has(key) => acc = record.has(key),
+                Op::Const(value) => acc = *value,
+                Op::InTime(lower, upper, zone) => {
+                    acc = self
+                        .time
+                        .as_ref()
+                        .and_then(TimeState::get)
+                        .is_some_and(|time| time_in_range(*lower, *upper, *zone, &time))
+                }
+                Op::InBlock(id) => {
+                    acc = matches!(&self.observers[*id], Observer::Block { state, .. } if state.is_current())
+                }
+                Op::Thinned(id) => {
+                    acc = matches!(
+                        &self.observers[*id],
+                        Observer::Thin { matched, state, .. }
+                            if matched.load(Ordering::Relaxed) && state.matches()
+                    )
+                }
+            }
+            pc += 1;
+        }
+    }
+}
+
+// Which section of an observer a pending expression compiles to.
+#[derive(Clone, Copy)]
+enum Slot {
+    Start,
+    End,
+    Inner,
+}
+
+enum Task<'a> {
+    Node(&'a AstNode, u64), // A node and its position, from `child_pos`
+    Jump { if_true: bool },
+    Patch, // Points the innermost unpatched jump at the next instruction
+    Not,
+}
+
+struct Compiler<'a> {
+    options: &'a RecordOptions,
+    invert: bool,
+    code: Vec<Op>,
+    observers: Vec<Observer>,
+    pending: Vec<(&'a AstNode, u64, usize, Slot)>,
+    uses_time: bool,
+}
+
+impl<'a> Compiler<'a> {
+    // Compiles `ast`, at position `pos` in the query, into a new section and returns its start.
+    // Stateful nodes register an observer and queue their expressions in `pending`.
+    fn section(&mut self, ast: &'a AstNode, pos: u64) -> usize {
+        let start = self.code.len();
+        let mut tasks = vec![Task::Node(ast, pos)];
+        let mut jumps = Vec::new();
+
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Node(AstNode::AndNode(lhs, rhs), pos) => tasks.extend([
+                    Task::Patch,
+                    Task::Node(rhs, child_pos(pos, 1)),
+                    Task::Jump { if_true: false },
+                    Task::Node(lhs, child_pos(pos, 0)),
+                ]),
+                Task::Node(AstNode::OrNode(lhs, rhs), pos) => tasks.extend([
+                    Task::Patch,
+                    Task::Node(rhs, child_pos(pos, 1)),
+                    Task::Jump { if_true: true },
+                    Task::Node(lhs, child_pos(pos, 0)),
+                ]),
+                Task::Node(AstNode::NotNode(inner), pos) => {
+                    tasks.extend([Task::Not, Task::Node(inner, child_pos(pos, 0))])
+                }
+                Task::Node(AstNode::Between(start, end, bounds), pos) => {
+                    let id = self.observers.len();
+                    self.observers.push(Observer::Block {
+                        start: 0,
+                        end: 0,
+                        state: BlockState::new(*bounds),
+                    });
+                    self.pending
+                        .push((start, child_pos(pos, 0), id, Slot::Start));
+                    self.pending.push((end, child_pos(pos, 1), id, Slot::End));
+                    self.code.push(Op::InBlock(id));
+                }
+                Task::Node(AstNode::Thin(inner, thinning), pos) => {
+                    let id = self.observers.len();
+                    self.observers.push(Observer::Thin {
+                        inner: 0,
+                        matched: AtomicBool::new(false),
+                        state: ThinState::new(*thinning, self.invert, thin_seed(pos, self.options)),
+                    });
+                    self.pending
+                        .push((inner, child_pos(pos, 0), id, Slot::Inner));
+                    self.code.push(Op::Thinned(id));
+                }
+                Task::Node(leaf @ (AstNode::Match(_) | AstNode::Profile(_)), _) => {
+                    // Leaves are lowered exactly as the tree evaluator does
+                    self.lower(Evaluator::compile(leaf, self.options, self.invert));
+                }
+                Task::Jump { if_true } => {
+                    jumps.push(self.code.len());
+                    self.code.push(if if_true {
+                        Op::JumpIfTrue(usize::MAX)
+                    } else {
+                        Op::JumpIfFalse(usize::MAX)
+                    });
+                }
+                Task::Patch => {
+                    let at = jumps.pop().expect("patch without a jump");
+                    let next = self.code.len();
+                    if let Op::JumpIfFalse(target) | Op::JumpIfTrue(target) = &mut self.code[at] {
+                        *target = next;
+                    }
+                }
+                Task::Not => self.code.push(Op::Not),
+            }
+        }
+        self.code.push(Op::Return);
+        start
+    }
+
+    fn lower(&mut self, evaluator: Evaluator) {
+        let op = match evaluator {
+            Evaluator::Not(inner) => {
+                self.lower(*inner);
+                Op::Not
+            }
+            Evaluator::Contains(s) => {
+                Op::Contains(Box::new(Finder::new(s.as_bytes()).into_owned()))
+            }
+            Evaluator::LineRange(lower, upper) => Op::LineRange(lower, upper),
+            Evaluator::FieldEquals(key, s) => Op::FieldEquals(key, s),
+            Evaluator::FieldContains(key, s) => Op::FieldContains(key, s),
+            Evaluator::FieldRange(key, unit, lower, upper) => {
+                Op::FieldRange(key, unit, lower, upper)
+            }
+            Evaluator::Has(key) => Op::Has(key),
+            Evaluator::Const(value) => Op::Const(value),
+            Evaluator::TimeRange(lower, upper, zone, _) => {
+                self.uses_time = true;
+                Op::InTime(lower, upper, zone)
+            }
+            Evaluator::And(..)
+            | Evaluator::Or(..)
+            | Evaluator::Between(..)
+            | Evaluator::Thin(..) => {
+                unreachable!("a pattern compiles to a leaf")
+            }
+        };
+        self.code.push(op);
+    }
+}
+
+// A jump landing on a jump of the same kind leaves the register unchanged, so it can go straight
+// to the second jump's target (`a & b & c` jumps once on a false `a`, not twice).
+//
+// Jumps only go forward, so walking backwards finds every target already threaded: one step
+// per jump, however long the chain.
+fn thread_jumps(code: &mut [Op]) {
+    for at in (0..code.len()).rev() {
+        let target = match (&code[at], code.get(jump_target(&code[at]))) {
+            (Op::JumpIfFalse(_), Some(Op::JumpIfFalse(next)))
+            | (Op::JumpIfTrue(_), Some(Op::JumpIfTrue(next))) => *next,
+            _ => continue,
+        };
+        if let Op::JumpIfFalse(t) | Op::JumpIfTrue(t) = &mut code[at] {
+            *t = target;
+        }
+    }
+}
+
+// The target of a jump, or an address past the end for any other instruction.
+fn jump_target(op: &Op) -> usize {
+    match op {
+        Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => *target,
+        _ => usize::MAX,
+    }
+}
+
+// Lists the instructions with their addresses, followed by the observers; for `--dump-bytecode`.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (at, op) in self.code.iter().enumerate() {
+            writeln!(f, "{:04}  {}", at, op)?;
+        }
+        for (id, observer) in self.observers.iter().enumerate() {
+            match observer {
+                Observer::Block { start, end, state } => writeln!(
+                    f,
+                    "block {}: start @{:04}, end @{:04}{}",
+                    id,
+                    start,
+                    end,
+                    match state.bounds() {
+                        BlockBounds::Inclusive => "",
+                        BlockBounds::Exclusive => ", exclusive",
+                    }
+                )?,
+                Observer::Thin { inner, state, .. } => match state.thinning() {
+                    Thinning::Sample(rate) => {
+                        writeln!(f, "thin {}: inner @{:04}, sample {}", id, inner, rate)?
+                    }
+                    Thinning::Limit { count, per } => writeln!(
+                        f,
+                        "thin {}: inner @{:04}, limit {}/{}ms",
+                        id,
+                        inner,
+                        count,
+                        per.as_millis()
+                    )?,
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::JumpIfFalse(target) => write!(f, "jump_if_false @{:04}", target),
+            Op::JumpIfTrue(target) => write!(f, "jump_if_true @{:04}", target),
+            Op::Not => write!(f, "not"),
+            Op::Return => write!(f, "return"),
+            Op::Contains(finder) => {
+                write!(f, "contains {:?}", String::from_utf8_lossy(finder.needle()))
+            }
+            Op::LineRange(lower, upper) => write!(f, "line in {}", Range(lower, upper)),
+            Op::FieldEquals(key, s) => write!(f, "field {} = {:?}", key, s),
+            Op::FieldContains(key, s) => write!(f, "field {} ~ {:?}", key, s),
+            Op::FieldRange(key, unit, lower, upper) => {
+                write!(f, "field {} in {} ({:?})", key, Range(lower, upper), unit)
+            }
+            Op::Has(key) => write!(f, "has {}", key),
+            Op::Const(value) => write!(f, "const {}", value),
+            Op::InTime(lower, upper, _) => write!(
+                f,
+                "time in {}",
+                Range(&lower.map(DisplayTime), &upper.map(DisplayTime))
+            ),
+            Op::InBlock(id) => write!(f, "in_block {}", id),
+            Op::Thinned(id) => write!(f, "thinned {}", id),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DisplayTime(TimeBound);
+
+impl fmt::Display for DisplayTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            TimeBound::Instant(ms) => match DateTime::from_timestamp_millis(ms) {
+                Some(time) => write!(f, "{}", time.to_rfc3339()),
+                None => write!(f, "{}ms", ms),
+            },
+            TimeBound::TimeOfDay(time) => write!(f, "{}", time),
+        }
+    }
+}
+
+// A range in interval notation, `*` standing for an open end: `[10, 20)`, `(500, *)`.
+struct Range<'a, T>(&'a Bound<T>, &'a Bound<T>);
+
+impl<T: fmt::Display> fmt::Display for Range<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Bound::Included(v) => write!(f, "[{}, ", v)?,
+            Bound::Excluded(v) => write!(f, "({}, ", v)?,
+            Bound::Unbounded => write!(f, "(*, ")?,
+        }
+        match self.1 {
+            Bound::Included(v) => write!(f, "{}]", v),
+            Bound::Excluded(v) => write!(f, "{})", v),
+            Bound::Unbounded => write!(f, "*)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::parser::parse_query;
+
+    // Runs both the program and the tree evaluator over `lines`, checking they agree.
+    fn run_both(query: &str, lines: &[&str]) -> Vec<bool> {
+        let ast = parse_query(query).unwrap();
+        let program = Program::from_ast(&ast);
+        let tree = Evaluator::from_ast(&ast);
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let ctx = EvalContext::new(i + 1);
+                let result = program.evaluate(line, &ctx);
+                assert_eq!(result, tree.evaluate(line, &ctx), "{query} on {line:?}");
+                result
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let lines = ["a b", "a", "b", "c", "a c", "b c"];
+        assert_eq!(
+            run_both("a & b | c", &lines),
+            vec![true, false, false, true, true, true]
+        );
+        assert_eq!(
+            run_both("a & (b | c)", &lines),
+            vec![true, false, false, false, true, false]
+        );
+        assert_eq!(
+            run_both("!(a | b) & !c", &lines),
+            vec![false, false, false, false, false, false]
+        );
+        assert_eq!(
+            run_both("!a | line != 2", &lines),
+            vec![true, false, true, true, true, true]
+        );
+    }
+
+    #[test]
+    fn test_stateful_sections() {
+        let lines = [
+            "x", "BEGIN", "x", "END", "x", "BEGIN", "BEGIN", "x", "END", "x",
+        ];
+        assert_eq!(
+            run_both("x & between(BEGIN, END)", &lines),
+            vec![false, false, true, false, false, false, false, true, false, true]
+        );
+        // Markers that are themselves stateful
+        assert_eq!(
+            run_both("between(between(BEGIN, END) & x, END)", &lines),
+            vec![false, false, true, true, false, false, false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn test_equal_samples_draw_independent_streams() {
+        let lines: Vec<&str> = std::iter::repeat_n("x", 1000).collect();
+        let result = run_both("sample(50%, x) & sample(50%, x)", &lines);
+        // Both match half the lines, but not the same half
+        let both = result.iter().filter(|m| **m).count();
+        assert!((180..320).contains(&both), "{both}");
+    }
+
+    #[test]
+    fn test_jumps_are_threaded() {
+        let program = Program::from_ast(&parse_query("a & b & c & d").unwrap());
+        let dump = program.to_string();
+        // Every jump goes straight to the final `return`
+        assert_eq!(dump.matches("jump_if_false @0007").count(), 3, "{dump}");
+        assert!(dump.ends_with("0007  return\n"), "{dump}");
+    }
+
+    #[test]
+    fn test_long_chains_thread_in_linear_time() {
+        // The code of a left-deep `|` chain: every jump lands on the next one
+        let n = 100_000;
+        let mut code = Vec::new();
+        for i in 0..n {
+            code.push(Op::Const(false));
+            code.push(Op::JumpIfTrue((2 * i + 3).min(2 * n)));
+        }
+        code.push(Op::Return);
+        let start = std::time::Instant::now();
+        thread_jumps(&mut code);
+        // Following each chain to its end takes minutes at this length
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(code
+            .iter()
+            .all(|op| !matches!(op, Op::JumpIfTrue(t) if *t != 2 * n)));
+    }
+
+    #[test]
+    fn test_dump() {
+        let ast = parse_query("sample(1%, between(BEGIN, END)) | $2 >= 500ms").unwrap();
+        let dump = Program::from_ast(&ast).to_string();
+        assert_eq!(
+            dump,
+            "0000  thinned 0\n\
+             0001  jump_if_true @0003\n\
+             0002  field $2 in [0.5, *) (Duration)\n\
+             0003  return\n\
+             0004  in_block 1\n\
+             0005  return\n\
+             0006  contains \"END\"\n\
+             0007  return\n\
+             0008  contains \"BEGIN\"\n\
+             0009  return\n\
+             thin 0: inner @0004, sample 0.01\n\
+             block 1: start @0008, end @0006\n"
+        );
+    }
+
+    #[test]
+    fn test_deep_query_does_not_recurse() {
+        let mut ast = parse_query("t0").unwrap();
+        for i in 1..10_000 {
+            let term = parse_query(&format!("!t{}", i)).unwrap();
+            ast = AstNode::OrNode(Box::new(ast), Box::new(term));
+            ast = AstNode::NotNode(Box::new(ast));
+        }
+        // A stack this small only fits a loop, not 20,000 nested calls
+        let result = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(64 << 10)
+                .spawn_scoped(scope, || {
+                    let program = Program::from_ast(&ast);
+                    let ctx = EvalContext::new(1);
+                    (
+                        program.evaluate("t0", &ctx),
+                        program.evaluate("t9999", &ctx),
+                    )
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        assert_eq!(result, (false, true));
+    }
+}
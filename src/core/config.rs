@@ -0,0 +1,558 @@
+use crate::core::ast::{AstNode, Pattern};
+use crate::core::parser::{parse_query_with_params, QueryLimits, QueryParams};
+use crate::core::record::Format;
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use toml::{Table, Value};
+
+pub const CONFIG_FILE: &str = "chaf.toml";
+
+// A named set of filter options from `chaf.toml`:
+//
+//     [profiles.k8s-noise]
+//     query = "kube-probe | healthz"
+//     format = "logfmt"
+//     ignore-case = true
+//     invert = false
+//     patterns = ["noise.txt"]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub query: Option<String>,
+    pub format: Option<Format>,
+    pub ignore_case: bool,
+    pub invert: bool,
+    // Files with one literal per line (blank lines and `#` comments are skipped), matched in
+    // addition to `query`. Relative paths are resolved against the directory of `chaf.toml`.
+    pub patterns: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    // Reads `chaf.toml` from `dir` or the nearest directory above it, and from
+    // `$XDG_CONFIG_HOME/chaf/` (`~/.config/chaf/` if unset). A profile in the project's file
+    // replaces one of the same name in the user's. Missing files are not an error.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let user = user_config_dir().map(|dir| dir.join(CONFIG_FILE));
+        let project = dir
+            .ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file());
+        let mut config = Config::default();
+        for path in [user, project].into_iter().flatten() {
+            if path.is_file() {
+                config.profiles.extend(Config::read(&path)?.profiles);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        parse_config(&text, base)
+            .map_err(|e| Error::InvalidOption(format!("{}: {}", path.display(), e)))
+    }
+
+    // Parses the text of a `chaf.toml` whose relative paths are relative to `base`.
+    pub fn from_toml(text: &str, base: &Path) -> Result<Self> {
+        parse_config(text, base).map_err(Error::InvalidOption)
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| Error::InvalidOption(format!("Unknown profile '{}'", name)))
+    }
+
+    // The query of a profile, matched together with its pattern files, with `@profile(...)`
+    // references resolved. It is parsed for `format`, which must be the profile's own format if
+    // it has one, with the placeholder values of `params`.
+    pub fn profile_query(
+        &self,
+        name: &str,
+        format: Format,
+        limits: QueryLimits,
+        params: &QueryParams,
+    ) -> Result<AstNode> {
+        let parse = Parse { limits, params };
+        self.resolve_in(AstNode::Profile(name.to_string()), format, &parse, 0)
+    }
+
+    // Replaces each `@profile(name)` in `ast` with the query of that profile. The result is held
+    // to `limits` as a whole, as if each reference were replaced by its query in parentheses.
+    pub fn resolve(
+        &self,
+        ast: AstNode,
+        format: Format,
+        limits: QueryLimits,
+        params: &QueryParams,
+    ) -> Result<AstNode> {
+        if ast.profiles().is_empty() {
+            return Ok(ast);
+        }
+        let parse = Parse { limits, params };
+        self.resolve_in(ast, format, &parse, 1)
+    }
+
+    // Expands the tree in place without recursion, since profiles can refer to each other as
+    // deeply as the limits allow. Depth and terms are counted the way the parser counts them:
+    // `!`, function arguments, parentheses around a chain of the other operator and the query
+    // of a profile nest one level deeper, and patterns and function calls are terms.
+    fn resolve_in(
+        &self,
+        mut ast: AstNode,
+        format: Format,
+        parse: &Parse,
+        depth: usize,
+    ) -> Result<AstNode> {
+        let limits = parse.limits;
+        let mut terms = 0;
+        let mut nodes = vec![(&mut ast, depth, None)];
+        while let Some((node, depth, link)) = nodes.pop() {
+            if depth > limits.max_depth {
+                return Err(Error::InvalidOption(format!(
+                    "Query nests too deeply once its profiles are expanded (limit {})",
+                    limits.max_depth
+                )));
+            }
+            // Only a chain of the other operator needed parentheses
+            let is_and = matches!(node, AstNode::AndNode(..));
+            match node {
+                AstNode::AndNode(lhs, rhs) | AstNode::OrNode(lhs, rhs) => {
+                    let nested = |node: &AstNode| match node {
+                        AstNode::AndNode(..) => depth + usize::from(!is_and),
+                        AstNode::OrNode(..) => depth + usize::from(is_and),
+                        _ => depth,
+                    };
+                    let (lhs_depth, rhs_depth) = (nested(lhs), nested(rhs));
+                    nodes.push((rhs, rhs_depth, link.clone()));
+                    nodes.push((lhs, lhs_depth, link));
+                }
+                AstNode::NotNode(inner) => nodes.push((inner, depth + 1, link)),
+                AstNode::Between(start, end, _) => {
+                    terms += 1;
+                    nodes.push((end, depth + 1, link.clone()));
+                    nodes.push((start, depth + 1, link));
+                }
+                AstNode::Thin(inner, _) => {
+                    terms += 1;
+                    nodes.push((inner, depth + 1, link));
+                }
+                AstNode::Match(_) => terms += 1,
+                AstNode::Profile(name) => {
+                    let link = Rc::new(Link {
+                        name: std::mem::take(name),
+                        parent: link,
+                    });
+                    *node = self.expand(&link, format, parse, terms)?;
+                    nodes.push((node, depth + 1, Some(link)));
+                }
+            }
+            if terms > limits.max_terms {
+                return Err(Error::InvalidOption(format!(
+                    "Query has too many terms once its profiles are expanded (limit {})",
+                    limits.max_terms
+                )));
+            }
+        }
+        drop(nodes);
+        Ok(ast)
+    }
+
+    // The query and patterns of the profile at the end of `link`, with its own references left
+    // in place. `terms` is the number of terms the whole query has so far.
+    fn expand(&self, link: &Link, format: Format, parse: &Parse, terms: usize) -> Result<AstNode> {
+        let name = link.name.as_str();
+        if let Some(cycle) = link.cycle() {
+            return Err(Error::InvalidOption(format!(
+                "Profiles refer to each other in a cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        let profile = self.profile(name)?;
+        // Records are parsed in the format of the run, so a query written for another format
+        // would quietly never match
+        if profile.format.is_some_and(|own| own != format) {
+            return Err(Error::InvalidOption(format!(
+                "Profile '{}' is for another format (run it with -p {})",
+                name, name
+            )));
+        }
+
+        let mut operands = Vec::new();
+        if let Some(text) = &profile.query {
+            let ast = parse_query_with_params(text, format, parse.limits, parse.params)
+                .map_err(|e| Error::InvalidOption(format!("In profile '{}': {}", name, e)))?;
+            operands.push(ast);
+        }
+        // Each pattern is a term of the query, so a huge pattern file is rejected like a huge
+        // query instead of building a tree too large to walk
+        let mut terms = terms;
+        for path in &profile.patterns {
+            for pattern in read_patterns(path)? {
+                terms += 1;
+                if terms > parse.limits.max_terms {
+                    return Err(Error::InvalidOption(format!(
+                        "Pattern files of profile '{}' have too many patterns (limit {})",
+                        name, parse.limits.max_terms
+                    )));
+                }
+                operands.push(AstNode::Match(Pattern::Literal(pattern)));
+            }
+        }
+        AstNode::or_chain(operands).ok_or_else(|| {
+            Error::InvalidOption(format!("Profile '{}' has no query or patterns", name))
+        })
+    }
+}
+
+// Resolves the `@profile(...)` references in `ast`, reading the configuration for the current
+// directory only if there are any.
+pub fn resolve_profiles(
+    ast: AstNode,
+    format: Format,
+    limits: QueryLimits,
+    params: &QueryParams,
+) -> Result<AstNode> {
+    if ast.profiles().is_empty() {
+        return Ok(ast);
+    }
+    Config::load(&std::env::current_dir()?)?.resolve(ast, format, limits, params)
+}
+
+// How the queries of profiles are parsed.
+struct Parse<'a> {
+    limits: QueryLimits,
+    params: &'a QueryParams,
+}
+
+// A profile being expanded, linked to the profile whose query refers to it.
+struct Link {
+    name: String,
+    parent: Option<Rc<Link>>,
+}
+
+impl Link {
+    // The profiles from an earlier expansion of this one down to this one, if there is one.
+    fn cycle(&self) -> Option<Vec<&str>> {
+        let mut cycle = vec![self.name.as_str()];
+        let mut link = self.parent.as_deref();
+        while let Some(outer) = link {
+            cycle.push(&outer.name);
+            if outer.name == self.name {
+                cycle.reverse();
+                return Some(cycle);
+            }
+            link = outer.parent.as_deref();
+        }
+        None
+    }
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("chaf")),
+        _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/chaf")),
+    }
+}
+
+fn parse_config(text: &str, base: &Path) -> std::result::Result<Config, String> {
+    let table: Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let mut config = Config::default();
+    for (key, value) in table {
+        let Value::Table(profiles) = value else {
+            return Err(format!("Expected a table of profiles at '{}'", key));
+        };
+        if key != "profiles" {
+            return Err(format!("Unknown table '{}' (expected: profiles)", key));
+        }
+        for (name, value) in profiles {
+            let Value::Table(table) = value else {
+                return Err(format!("Expected a table for profile '{}'", name));
+            };
+            let profile =
+                parse_profile(table, base).map_err(|e| format!("In profile '{}': {}", name, e))?;
+            config.profiles.insert(name, profile);
+        }
+    }
+    Ok(config)
+}
+
+fn parse_profile(table: Table, base: &Path) -> std::result::Result<Profile, String> {
+    let mut profile = Profile::default();
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("query", Value::String(query)) => profile.query = Some(query),
+            ("format", Value::String(format)) => {
+                profile.format = Some(format.parse().map_err(|e: Error| e.to_string())?)
+            }
+            ("ignore-case", Value::Boolean(b)) => profile.ignore_case = b,
+            ("invert", Value::Boolean(b)) => profile.invert = b,
+            ("patterns", Value::Array(files)) => {
+                for file in files {
+                    let Value::String(file) = file else {
+                        return Err("Expected file names in 'patterns'".to_string());
+                    };
+                    profile.patterns.push(base.join(file));
+                }
+            }
+            ("query" | "format" | "ignore-case" | "invert" | "patterns", value) => {
+                return Err(format!("Unexpected {} for '{}'", value.type_str(), key))
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown key '{}' (expected: query, format, ignore-case, invert, patterns)",
+                    key
+                ))
+            }
+        }
+    }
+    Ok(profile)
+}
+
+fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Error::InvalidOption(format!(
+            "Failed to read pattern file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::parser::parse_query;
+
+    fn config(text: &str) -> Config {
+        Config::from_toml(text, Path::new("/etc/chaf")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = config(
+            r#"
+            [profiles.k8s-noise]
+            query = "kube-probe | healthz"
+            format = "logfmt"
+            ignore-case = true
+            patterns = ["noise.txt"]
+
+            [profiles.errors]
+            query = "level=error"
+            invert = true
+            "#,
+        );
+        assert_eq!(
+            config.profile("k8s-noise").unwrap(),
+            &Profile {
+                query: Some("kube-probe | healthz".to_string()),
+                format: Some(Format::Logfmt),
+                ignore_case: true,
+                invert: false,
+                patterns: vec![PathBuf::from("/etc/chaf/noise.txt")],
+            }
+        );
+        assert!(config.profile("errors").unwrap().invert);
+        let err = config.profile("missing").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown profile 'missing'");
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let err = |text| {
+            Config::from_toml(text, Path::new("."))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err("[profiles.a]\nqueri = \"x\"").contains("In profile 'a': Unknown key 'queri'"));
+        assert!(err("[profiles.a]\ninvert = \"yes\"").contains("Unexpected string for 'invert'"));
+        assert!(err("[profiles.a]\nformat = \"xml\"").contains("Unknown format 'xml'"));
+        assert!(err("[profile.a]\nquery = \"x\"").contains("Unknown table 'profile'"));
+        assert!(err("[profiles.a\n").contains("TOML parse error"));
+    }
+
+    #[test]
+    fn test_resolve_references() {
+        let config = config(
+            r#"
+            profiles.noise.query = "@profile(probes) | heartbeat"
+            profiles.probes.query = "kube-probe | healthz"
+            "#,
+        );
+        let ast = parse_query("@profile(noise) & !ERROR").unwrap();
+        let resolved = config
+            .resolve(
+                ast,
+                Format::Plain,
+                QueryLimits::default(),
+                &QueryParams::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            resolved,
+            parse_query("((kube-probe | healthz) | heartbeat) & !ERROR").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_cycle() {
+        let config = config(
+            r#"
+            profiles.a.query = "x | @profile(b)"
+            profiles.b.query = "@profile(c)"
+            profiles.c.query = "y & @profile(b)"
+            "#,
+        );
+        let err = config
+            .profile_query(
+                "a",
+                Format::Plain,
+                QueryLimits::default(),
+                &QueryParams::default(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Profiles refer to each other in a cycle: b -> c -> b"
+        );
+
+        // The same profile twice is not a cycle
+        let config = self::config(
+            r#"
+            profiles.a.query = "@profile(b) & !@profile(b)"
+            profiles.b.query = "x"
+            "#,
+        );
+        assert!(config
+            .profile_query(
+                "a",
+                Format::Plain,
+                QueryLimits::default(),
+                &QueryParams::default()
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_resolve_limits() {
+        let resolve = |config: &str| {
+            self::config(config)
+                .resolve(
+                    parse_query("@profile(p0)").unwrap(),
+                    Format::Plain,
+                    QueryLimits::default(),
+                    &QueryParams::default(),
+                )
+                .map_err(|e| e.to_string())
+        };
+        // Each profile nests the next one level deeper than its own query does
+        let chain = |query: &str, len: usize| {
+            let mut text = (0..len)
+                .map(|i| {
+                    format!(
+                        "profiles.p{}.query = \"{}\"\n",
+                        i,
+                        query.replace('N', &(i + 1).to_string())
+                    )
+                })
+                .collect::<String>();
+            text.push_str(&format!("profiles.p{}.query = \"x\"\n", len));
+            text
+        };
+        assert!(resolve(&chain("!(@profile(pN))", 100)).is_ok());
+        assert_eq!(
+            resolve(&chain("!(@profile(pN))", 3000)).unwrap_err(),
+            "Query nests too deeply once its profiles are expanded (limit 256)"
+        );
+        assert_eq!(
+            resolve(&chain("a | @profile(pN)", 300)).unwrap_err(),
+            "Query nests too deeply once its profiles are expanded (limit 256)"
+        );
+        // Each profile doubles the size of the query
+        assert!(resolve(&chain("@profile(pN) & !@profile(pN)", 10)).is_ok());
+        assert_eq!(
+            resolve(&chain("@profile(pN) & !@profile(pN)", 40)).unwrap_err(),
+            "Query has too many terms once its profiles are expanded (limit 10000)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_format() {
+        let config = config(
+            r#"
+            profiles.err.query = "level=error"
+            profiles.err.format = "logfmt"
+            profiles.any.query = "@profile(err) | timeout"
+            "#,
+        );
+        let resolve = |format| {
+            config.resolve(
+                parse_query("@profile(any)").unwrap(),
+                format,
+                QueryLimits::default(),
+                &QueryParams::default(),
+            )
+        };
+        assert!(resolve(Format::Logfmt).is_ok());
+        for format in [Format::Plain, Format::Jsonl] {
+            assert_eq!(
+                resolve(format).unwrap_err().to_string(),
+                "Profile 'err' is for another format (run it with -p err)"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pattern_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("noise.txt"),
+            "# probes\nkube-probe\n\n  GET /healthz  \n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[profiles.noise]\nquery = \"DEBUG\"\npatterns = [\"noise.txt\"]\n",
+        )
+        .unwrap();
+        let config = Config::read(&dir.path().join(CONFIG_FILE)).unwrap();
+        let ast = config
+            .profile_query(
+                "noise",
+                Format::Plain,
+                QueryLimits::default(),
+                &QueryParams::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            ast,
+            parse_query("DEBUG | kube-probe | \"GET /healthz\"").unwrap()
+        );
+
+        let limits = QueryLimits {
+            max_terms: 1,
+            ..QueryLimits::default()
+        };
+        let err = config
+            .profile_query("noise", Format::Plain, limits, &QueryParams::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Pattern files of profile 'noise' have too many patterns (limit 1)"
+        );
+    }
+}
@@ -1,37 +1,492 @@
-use crate::core::ast::{AstNode, Pattern};
+use crate::core::ast::{AstNode, BlockBounds, CompareOp, Key, Pattern, Thinning};
+use crate::core::record::{Record, RecordOptions};
+use crate::core::time::{TimeBound, Zone};
+use crate::core::value::{parse_as, parse_scalar, Unit};
+use chrono::{DateTime, FixedOffset};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+// Per-line metadata supplied by the caller (e.g. `run_filter`) alongside the line text.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EvalContext {
+    pub line_no: usize, // 1-based
+}
+
+impl EvalContext {
+    pub fn new(line_no: usize) -> Self {
+        Self { line_no }
+    }
+}
+
+// The tree-walking evaluator. Filters run the flat `bytecode::Program` compiled from the same AST;
+// this stays as the reference implementation it is tested and benchmarked against.
 pub enum Evaluator {
     And(Box<Evaluator>, Box<Evaluator>),
     Or(Box<Evaluator>, Box<Evaluator>),
     Not(Box<Evaluator>),
     Contains(String),
+    LineRange(Bound<usize>, Bound<usize>),
+    FieldEquals(Key, String),
+    FieldContains(Key, String),
+    FieldRange(Key, Unit, Bound<f64>, Bound<f64>), // Typed comparison; values not in `Unit` never match
+    Has(Key),
+    Const(bool),
+    Between(Box<Evaluator>, Box<Evaluator>, BlockState),
+    TimeRange(Bound<TimeBound>, Bound<TimeBound>, Zone, TimeState),
+    Thin(Box<Evaluator>, ThinState),
+}
+
+// Tracks whether the stream is currently inside a `between(...)` block.
+//
+// - Each start marker inside an open block opens a nested block; the outer block ends at its matching end marker
+// - An end marker outside any block is ignored
+// - A block that is never terminated extends to the end of the input
+// - When start and end markers are the same, the line ends an open block rather than nesting
+pub struct BlockState {
+    bounds: BlockBounds,
+    depth: AtomicUsize,
+    current: AtomicBool, // Whether the line last passed to `observe` is part of a block
+}
+
+impl BlockState {
+    pub(crate) fn new(bounds: BlockBounds) -> Self {
+        Self {
+            bounds,
+            depth: AtomicUsize::new(0),
+            current: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn advance(&self, is_start: bool, is_end: bool) {
+        let depth = self.depth.load(Ordering::Relaxed);
+        let inclusive = self.bounds == BlockBounds::Inclusive;
+        let (depth, current) = if depth == 0 {
+            if is_start {
+                (1, inclusive)
+            } else {
+                (0, false)
+            }
+        } else if is_end {
+            (depth - 1, depth > 1 || inclusive)
+        } else if is_start {
+            (depth + 1, true)
+        } else {
+            (depth, true)
+        };
+        self.depth.store(depth, Ordering::Relaxed);
+        self.current.store(current, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_current(&self) -> bool {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bounds(&self) -> BlockBounds {
+        self.bounds
+    }
+}
+
+// The timestamp of the most recent line that had one.
+//
+// Lines without a timestamp (stack traces, wrapped messages) belong to the entry above them,
+// so they are compared using the last timestamp seen. Lines before the first timestamp never match.
+#[derive(Default)]
+pub struct TimeState {
+    last: Mutex<Option<DateTime<FixedOffset>>>,
+}
+
+impl TimeState {
+    pub(crate) fn update(&self, time: Option<DateTime<FixedOffset>>) {
+        if let Some(time) = time {
+            *self.last.lock().unwrap() = Some(time);
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<DateTime<FixedOffset>> {
+        *self.last.lock().unwrap()
+    }
+}
+
+// Chooses which lines matching a `sample(...)` / `limit(...)` node survive the filter.
+//
+// A surviving line is output whether or not the filter is inverted: without `invert` the node
+// matches (and so excludes) the other lines, with `invert` it matches only the survivors.
+pub struct ThinState {
+    thinning: Thinning,
+    invert: bool,
+    rng: AtomicU64,            // splitmix64 state for `Sample`
+    window: Mutex<(i64, u64)>, // Start (ms) and count of the current `Limit` window
+    time: TimeState,           // The last log timestamp, for lines without one
+    clock: OnceLock<Clock>,    // Chosen by the first line that reaches a `Limit` window
+    survivor: AtomicBool,      // Whether the line last passed to `observe` survives
+}
+
+// The time `limit(...)` counts its windows in. A run sticks to one clock, so that windows are
+// never compared across clocks.
+enum Clock {
+    Log,  // The line's timestamp, or the last one above it
+    Wall, // The log had no timestamp yet when the first line was limited
+}
+
+impl ThinState {
+    pub(crate) fn new(thinning: Thinning, invert: bool, seed: u64) -> Self {
+        Self {
+            thinning,
+            invert,
+            rng: AtomicU64::new(seed),
+            window: Mutex::new((i64::MIN, 0)),
+            time: TimeState::default(),
+            clock: OnceLock::new(),
+            survivor: AtomicBool::new(false),
+        }
+    }
+
+    // Feeds a line to the state; `matched` is whether the inner node matched it. Every line is
+    // fed, so that a line without a timestamp is limited at the time of the entry it belongs to.
+    pub(crate) fn observe(&self, record: &Record, matched: bool) {
+        if let Thinning::Limit { .. } = self.thinning {
+            self.time.update(record.timestamp());
+        }
+        if matched {
+            self.advance(|| self.now());
+        }
+    }
+
+    // Decides whether a line matching the inner node survives. `now` is its time in milliseconds.
+    fn advance(&self, now: impl FnOnce() -> i64) {
+        let survivor = match self.thinning {
+            Thinning::Sample(rate) => self.next_random() < rate,
+            Thinning::Limit { count, per } => {
+                let mut window = self.window.lock().unwrap();
+                let now = now();
+                if now.saturating_sub(window.0) >= per.as_millis() as i64 || now < window.0 {
+                    *window = (now, 0);
+                }
+                window.1 += 1;
+                window.1 <= count
+            }
+        };
+        self.survivor.store(survivor, Ordering::Relaxed);
+    }
+
+    pub(crate) fn thinning(&self) -> Thinning {
+        self.thinning
+    }
+
+    // Whether the line last passed to `advance` should match, given that the inner node matched.
+    pub(crate) fn matches(&self) -> bool {
+        self.survivor.load(Ordering::Relaxed) == self.invert
+    }
+
+    // The time of the line last observed, in milliseconds.
+    fn now(&self) -> i64 {
+        let time = self.time.get();
+        let clock = self.clock.get_or_init(|| match time {
+            Some(_) => Clock::Log,
+            None => Clock::Wall,
+        });
+        match (clock, time) {
+            (Clock::Log, Some(time)) => time.timestamp_millis(),
+            _ => wall_clock_millis(),
+        }
+    }
+
+    // A uniform value in [0, 1) from splitmix64, so a given seed always picks the same lines.
+    fn next_random(&self) -> f64 {
+        let z = splitmix64(self.rng.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed));
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
 
 impl Evaluator {
-    pub fn evaluate(&self, line: &str) -> bool {
+    // Updates cross-line state and evaluates the line in one step.
+    pub fn evaluate(&self, line: &str, ctx: &EvalContext) -> bool {
+        let options = RecordOptions::default();
+        let record = Record::new(line, &options);
+        self.observe(&record, ctx);
+        self.matches(&record, ctx)
+    }
+
+    // Evaluates the line against state already updated by `observe`.
+    pub fn matches(&self, record: &Record, ctx: &EvalContext) -> bool {
+        match self {
+            Evaluator::And(lhs, rhs) => lhs.matches(record, ctx) && rhs.matches(record, ctx),
+            Evaluator::Or(lhs, rhs) => lhs.matches(record, ctx) || rhs.matches(record, ctx),
+            Evaluator::Not(inner) => !inner.matches(record, ctx),
+            Evaluator::Contains(s) => record.haystack().contains(s),
+            Evaluator::LineRange(lower, upper) => (*lower, *upper).contains(&ctx.line_no),
+            Evaluator::FieldEquals(key, s) => {
+                record.field_text(key).is_some_and(|f| values_equal(&f, s))
+            }
+            Evaluator::FieldContains(key, s) => record
+                .field_text(key)
+                .is_some_and(|f| f.contains(s.as_str())),
+            Evaluator::FieldRange(key, unit, lower, upper) => record
+                .field(key)
+                .and_then(|f| parse_as(&f, *unit))
+                .is_some_and(|n| (*lower, *upper).contains(&n)),
+            Evaluator::Has(key) => record.has(key),
+            Evaluator::Const(value) => *value,
+            Evaluator::Between(_, _, state) => state.is_current(),
+            Evaluator::TimeRange(lower, upper, zone, state) => state
+                .get()
+                .is_some_and(|time| time_in_range(*lower, *upper, *zone, &time)),
+            Evaluator::Thin(inner, state) => inner.matches(record, ctx) && state.matches(),
+        }
+    }
+
+    // Feeds the line to every stateful node. Must be called exactly once per line, in input order,
+    // so that state is not skipped when `matches` short-circuits.
+    pub fn observe(&self, record: &Record, ctx: &EvalContext) {
         match self {
-            Evaluator::And(lhs, rhs) => lhs.evaluate(line) && rhs.evaluate(line),
-            Evaluator::Or(lhs, rhs) => lhs.evaluate(line) || rhs.evaluate(line),
-            Evaluator::Not(inner) => !inner.evaluate(line),
-            Evaluator::Contains(s) => line.contains(s),
+            Evaluator::And(lhs, rhs) | Evaluator::Or(lhs, rhs) => {
+                lhs.observe(record, ctx);
+                rhs.observe(record, ctx);
+            }
+            Evaluator::Not(inner) => inner.observe(record, ctx),
+            Evaluator::Between(start, end, state) => {
+                start.observe(record, ctx);
+                end.observe(record, ctx);
+                state.advance(start.matches(record, ctx), end.matches(record, ctx));
+            }
+            Evaluator::TimeRange(_, _, _, state) => state.update(record.timestamp()),
+            Evaluator::Thin(inner, state) => {
+                inner.observe(record, ctx);
+                state.observe(record, inner.matches(record, ctx));
+            }
+            _ => {}
+        }
+    }
+
+    // Returns `true` if the result for a line depends on previous lines.
+    pub fn is_stateful(&self) -> bool {
+        match self {
+            Evaluator::And(lhs, rhs) | Evaluator::Or(lhs, rhs) => {
+                lhs.is_stateful() || rhs.is_stateful()
+            }
+            Evaluator::Not(inner) => inner.is_stateful(),
+            Evaluator::Between(_, _, _)
+            | Evaluator::TimeRange(_, _, _, _)
+            | Evaluator::Thin(_, _) => true,
+            _ => false,
         }
     }
 
     // Converts an AST node into an Evaluator structure
     pub fn from_ast(ast: &AstNode) -> Self {
+        Self::compile(ast, &RecordOptions::default(), false)
+    }
+
+    // Like `from_ast`, but interprets time bounds in the zone configured in `options` and seeds
+    // `sample(...)` with `options.seed`. Records passed to the evaluator should be built with the
+    // same options. `invert` must match the filter's, so that `sample(...)` / `limit(...)`
+    // survivors are output in both modes.
+    pub fn compile(ast: &AstNode, options: &RecordOptions, invert: bool) -> Self {
+        Self::compile_at(ast, options, invert, ROOT_POS)
+    }
+
+    // `pos` is the position of `ast` in the whole query, from `child_pos`.
+    fn compile_at(ast: &AstNode, options: &RecordOptions, invert: bool, pos: u64) -> Self {
+        let zone = options.time.zone;
+        let child = |node: &AstNode, index| {
+            Box::new(Self::compile_at(
+                node,
+                options,
+                invert,
+                child_pos(pos, index),
+            ))
+        };
         match ast {
-            AstNode::AndNode(lhs, rhs) => {
-                Evaluator::And(Box::new(Self::from_ast(lhs)), Box::new(Self::from_ast(rhs)))
+            AstNode::AndNode(lhs, rhs) => Evaluator::And(child(lhs, 0), child(rhs, 1)),
+            AstNode::OrNode(lhs, rhs) => Evaluator::Or(child(lhs, 0), child(rhs, 1)),
+            AstNode::NotNode(inner) => Evaluator::Not(child(inner, 0)),
+            AstNode::Between(start, end, bounds) => {
+                Evaluator::Between(child(start, 0), child(end, 1), BlockState::new(*bounds))
+            }
+            AstNode::Thin(inner, thinning) => Evaluator::Thin(
+                child(inner, 0),
+                ThinState::new(*thinning, invert, thin_seed(pos, options)),
+            ),
+            // Profiles are resolved before compiling; `LineFilter` rejects any left over
+            AstNode::Profile(_) => Evaluator::Const(false),
+            AstNode::Match(Pattern::Literal(s)) => {
+                Evaluator::Contains(options.fold(s).into_owned())
+            }
+            AstNode::Match(Pattern::Compare(Key::Line, op, value)) => {
+                let n = parse_line_no(value);
+                match op {
+                    CompareOp::Ne => Evaluator::Not(Box::new(Evaluator::LineRange(
+                        Bound::Included(n),
+                        Bound::Included(n),
+                    ))),
+                    CompareOp::Contains => Evaluator::Const(false),
+                    _ => {
+                        let (lower, upper) = op_bounds(*op, n);
+                        Evaluator::LineRange(lower, upper)
+                    }
+                }
+            }
+            AstNode::Match(Pattern::Compare(Key::Time, op, value)) => {
+                // Rejected by the parser: not a valid time
+                let Some(bound) = TimeBound::parse(value, zone) else {
+                    return Evaluator::Const(false);
+                };
+                let state = TimeState::default();
+                match op {
+                    CompareOp::Ne => Evaluator::Not(Box::new(Evaluator::TimeRange(
+                        Bound::Included(bound),
+                        Bound::Included(bound),
+                        zone,
+                        state,
+                    ))),
+                    CompareOp::Contains => Evaluator::Const(false),
+                    _ => {
+                        let (lower, upper) = op_bounds(*op, bound);
+                        Evaluator::TimeRange(lower, upper, zone, state)
+                    }
+                }
             }
-            AstNode::OrNode(lhs, rhs) => {
-                Evaluator::Or(Box::new(Self::from_ast(lhs)), Box::new(Self::from_ast(rhs)))
+            AstNode::Match(Pattern::Compare(key, op, value)) => match op {
+                CompareOp::Eq => {
+                    Evaluator::FieldEquals(key.clone(), options.fold(value).into_owned())
+                }
+                CompareOp::Ne => Evaluator::Not(Box::new(Evaluator::FieldEquals(
+                    key.clone(),
+                    options.fold(value).into_owned(),
+                ))),
+                CompareOp::Contains => {
+                    Evaluator::FieldContains(key.clone(), options.fold(value).into_owned())
+                }
+                CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge => {
+                    match parse_scalar(value) {
+                        Some((unit, n)) => {
+                            let (lower, upper) = op_bounds(*op, n);
+                            Evaluator::FieldRange(key.clone(), unit, lower, upper)
+                        }
+                        // Rejected by the parser: ordering needs a typed value
+                        None => Evaluator::Const(false),
+                    }
+                }
+            },
+            AstNode::Match(Pattern::InRange(Key::Line, lower, upper)) => Evaluator::LineRange(
+                lower.as_ref().map(|v| parse_line_no(v)),
+                upper.as_ref().map(|v| parse_line_no(v)),
+            ),
+            AstNode::Match(Pattern::InRange(Key::Time, lower, upper)) => {
+                let bound = |b: &Bound<String>| match b {
+                    Bound::Included(v) => TimeBound::parse(v, zone).map(Bound::Included),
+                    Bound::Excluded(v) => TimeBound::parse(v, zone).map(Bound::Excluded),
+                    Bound::Unbounded => Some(Bound::Unbounded),
+                };
+                match (bound(lower), bound(upper)) {
+                    (Some(lower), Some(upper)) => {
+                        Evaluator::TimeRange(lower, upper, zone, TimeState::default())
+                    }
+                    // Rejected by the parser: not a valid time
+                    _ => Evaluator::Const(false),
+                }
             }
-            AstNode::NotNode(inner) => Evaluator::Not(Box::new(Self::from_ast(inner))),
-            AstNode::Match(Pattern::Literal(s)) => Evaluator::Contains(s.clone()),
+            AstNode::Match(Pattern::InRange(key, lower, upper)) => {
+                // The parser guarantees both bounds share a unit
+                let unit = [lower, upper]
+                    .into_iter()
+                    .find_map(|b| match b {
+                        Bound::Included(v) | Bound::Excluded(v) => parse_scalar(v),
+                        Bound::Unbounded => None,
+                    })
+                    .map_or(Unit::Number, |(unit, _)| unit);
+                let bound =
+                    |b: &Bound<String>| b.as_ref().map(|v| parse_as(v, unit).unwrap_or(f64::NAN));
+                Evaluator::FieldRange(key.clone(), unit, bound(lower), bound(upper))
+            }
+            AstNode::Match(Pattern::Has(key)) => Evaluator::Has(key.clone()),
+        }
+    }
+}
+
+// The position of the root of a query; see `child_pos`.
+pub(crate) const ROOT_POS: u64 = 0;
+
+// The position of the `index`th child of the node at `pos`: a hash of the path from the root, so
+// equal expressions in different places of a query get different positions.
+pub(crate) fn child_pos(pos: u64, index: u64) -> u64 {
+    splitmix64(pos ^ index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+// Each `sample(...)` / `limit(...)` draws its own stream, fixed by `--seed` and its position in
+// the query.
+pub(crate) fn thin_seed(pos: u64, options: &RecordOptions) -> u64 {
+    splitmix64(options.seed ^ pos)
+}
+
+fn splitmix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Converts an ordering or equality operator into the range of values it accepts.
+fn op_bounds<T>(op: CompareOp, value: T) -> (Bound<T>, Bound<T>)
+where
+    T: Copy,
+{
+    match op {
+        CompareOp::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+        CompareOp::Le => (Bound::Unbounded, Bound::Included(value)),
+        CompareOp::Gt => (Bound::Excluded(value), Bound::Unbounded),
+        CompareOp::Ge => (Bound::Included(value), Bound::Unbounded),
+        _ => (Bound::Included(value), Bound::Included(value)),
+    }
+}
+
+// Field values are equal if they are the same text or the same typed value
+// (so `500` matches `500.0` and `1s` matches `1000ms`).
+pub(crate) fn values_equal(field: &str, value: &str) -> bool {
+    field == value
+        || parse_scalar(value)
+            .is_some_and(|(unit, expected)| parse_as(field, unit) == Some(expected))
+}
+
+// A time-of-day range whose lower bound is after its upper bound wraps around midnight
+// (`time in [22:00, 06:00)` is the night shift).
+pub(crate) fn time_in_range(
+    lower: Bound<TimeBound>,
+    upper: Bound<TimeBound>,
+    zone: Zone,
+    time: &DateTime<FixedOffset>,
+) -> bool {
+    let bound = match (lower, upper) {
+        (Bound::Included(b) | Bound::Excluded(b), _)
+        | (_, Bound::Included(b) | Bound::Excluded(b)) => b,
+        (Bound::Unbounded, Bound::Unbounded) => return true,
+    };
+    let key = bound.key(time, zone);
+    let lower = lower.map(|b| b.value());
+    let upper = upper.map(|b| b.value());
+    match (lower, upper) {
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b))
+            if a > b && matches!(bound, TimeBound::TimeOfDay(_)) =>
+        {
+            (lower, Bound::Unbounded).contains(&key) || (Bound::Unbounded, upper).contains(&key)
         }
+        _ => (lower, upper).contains(&key),
     }
 }
 
+fn wall_clock_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+// Line numbers are validated by the parser, so anything unparsable here can never match.
+fn parse_line_no(value: &str) -> usize {
+    value.parse().unwrap_or(usize::MAX)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,36 +496,371 @@ mod tests {
         AstNode::Match(Pattern::Literal(s.to_string()))
     }
 
+    fn ctx() -> EvalContext {
+        EvalContext::new(1)
+    }
+
     #[test]
     fn test_literal_match() {
         let ast = literal("ERROR");
         let eval = Evaluator::from_ast(&ast);
-        assert!(eval.evaluate("this is ERROR"));
-        assert!(!eval.evaluate("this is OK"));
+        assert!(eval.evaluate("this is ERROR", &ctx()));
+        assert!(!eval.evaluate("this is OK", &ctx()));
     }
 
     #[test]
     fn test_and_match() {
         let ast = AstNode::AndNode(Box::new(literal("foo")), Box::new(literal("bar")));
         let eval = Evaluator::from_ast(&ast);
-        assert!(eval.evaluate("foo bar"));
-        assert!(!eval.evaluate("foo only"));
+        assert!(eval.evaluate("foo bar", &ctx()));
+        assert!(!eval.evaluate("foo only", &ctx()));
     }
 
     #[test]
     fn test_or_match() {
         let ast = AstNode::OrNode(Box::new(literal("foo")), Box::new(literal("bar")));
         let eval = Evaluator::from_ast(&ast);
-        assert!(eval.evaluate("contains foo"));
-        assert!(eval.evaluate("contains bar"));
-        assert!(!eval.evaluate("neither"));
+        assert!(eval.evaluate("contains foo", &ctx()));
+        assert!(eval.evaluate("contains bar", &ctx()));
+        assert!(!eval.evaluate("neither", &ctx()));
     }
 
     #[test]
     fn test_not_match() {
         let ast = AstNode::NotNode(Box::new(literal("DEBUG")));
         let eval = Evaluator::from_ast(&ast);
-        assert!(!eval.evaluate("DEBUG line"));
-        assert!(eval.evaluate("INFO line"));
+        assert!(!eval.evaluate("DEBUG line", &ctx()));
+        assert!(eval.evaluate("INFO line", &ctx()));
+    }
+
+    #[test]
+    fn test_line_comparison() {
+        let ast = AstNode::Match(Pattern::Compare(Key::Line, CompareOp::Le, "2".to_string()));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("header", &EvalContext::new(1)));
+        assert!(eval.evaluate("header", &EvalContext::new(2)));
+        assert!(!eval.evaluate("body", &EvalContext::new(3)));
+    }
+
+    #[test]
+    fn test_line_not_equal() {
+        let ast = AstNode::Match(Pattern::Compare(Key::Line, CompareOp::Ne, "2".to_string()));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("a", &EvalContext::new(1)));
+        assert!(!eval.evaluate("b", &EvalContext::new(2)));
+    }
+
+    #[test]
+    fn test_line_range() {
+        let ast = AstNode::Match(Pattern::InRange(
+            Key::Line,
+            Bound::Included("10".to_string()),
+            Bound::Excluded("20".to_string()),
+        ));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(!eval.evaluate("x", &EvalContext::new(9)));
+        assert!(eval.evaluate("x", &EvalContext::new(10)));
+        assert!(eval.evaluate("x", &EvalContext::new(19)));
+        assert!(!eval.evaluate("x", &EvalContext::new(20)));
+    }
+
+    fn run_lines(eval: &Evaluator, lines: &[&str]) -> Vec<bool> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| eval.evaluate(line, &EvalContext::new(i + 1)))
+            .collect()
+    }
+
+    fn between(bounds: BlockBounds) -> Evaluator {
+        let ast = AstNode::Between(Box::new(literal("BEGIN")), Box::new(literal("END")), bounds);
+        Evaluator::from_ast(&ast)
+    }
+
+    #[test]
+    fn test_between_inclusive() {
+        let eval = between(BlockBounds::Inclusive);
+        let result = run_lines(&eval, &["a", "BEGIN", "b", "END", "c"]);
+        assert_eq!(result, vec![false, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_between_exclusive() {
+        let eval = between(BlockBounds::Exclusive);
+        let result = run_lines(&eval, &["a", "BEGIN", "b", "END", "c"]);
+        assert_eq!(result, vec![false, false, true, false, false]);
+    }
+
+    #[test]
+    fn test_between_nested_and_unterminated() {
+        let eval = between(BlockBounds::Exclusive);
+        let result = run_lines(
+            &eval,
+            &[
+                "BEGIN", "BEGIN", "x", "END", "y", "END", "END", "BEGIN", "z",
+            ],
+        );
+        // Nested markers stay inside the outer block; a stray END is ignored; an open block runs to EOF
+        assert_eq!(
+            result,
+            vec![false, true, true, true, true, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_between_same_marker() {
+        let ast = AstNode::Between(
+            Box::new(literal("---")),
+            Box::new(literal("---")),
+            BlockBounds::Inclusive,
+        );
+        let eval = Evaluator::from_ast(&ast);
+        let result = run_lines(&eval, &["a", "---", "b", "---", "c"]);
+        assert_eq!(result, vec![false, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_between_state_survives_short_circuit() {
+        // The left side is false on the marker lines, so `matches` alone would never see them
+        let ast = AstNode::AndNode(
+            Box::new(literal("x")),
+            Box::new(AstNode::Between(
+                Box::new(literal("BEGIN")),
+                Box::new(literal("END")),
+                BlockBounds::Inclusive,
+            )),
+        );
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.is_stateful());
+        let result = run_lines(&eval, &["x", "BEGIN", "x", "END", "x"]);
+        assert_eq!(result, vec![false, false, true, false, false]);
+    }
+
+    #[test]
+    fn test_field_predicates() {
+        let ast = AstNode::AndNode(
+            Box::new(AstNode::Match(Pattern::Compare(
+                Key::Index(2),
+                CompareOp::Eq,
+                "500".to_string(),
+            ))),
+            Box::new(AstNode::Match(Pattern::Compare(
+                Key::Index(3),
+                CompareOp::Contains,
+                "time".to_string(),
+            ))),
+        );
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("GET 500 timeout\n", &ctx()));
+        assert!(!eval.evaluate("GET 5000 timeout\n", &ctx()));
+        assert!(!eval.evaluate("GET 500 refused timeout\n", &ctx()));
+        assert!(!eval.evaluate("GET 500\n", &ctx()));
+    }
+
+    #[test]
+    fn test_numeric_field_comparison() {
+        let ast = AstNode::Match(Pattern::Compare(
+            Key::Index(2),
+            CompareOp::Ge,
+            "500".to_string(),
+        ));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("GET 503\n", &ctx()));
+        assert!(!eval.evaluate("GET 200\n", &ctx()));
+        assert!(!eval.evaluate("GET -\n", &ctx())); // Not numeric → false
+    }
+
+    #[test]
+    fn test_field_equals_numerically() {
+        let ast = AstNode::Match(Pattern::Compare(
+            Key::Index(1),
+            CompareOp::Eq,
+            "500".to_string(),
+        ));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("500.0\n", &ctx()));
+        assert!(!eval.evaluate("5000\n", &ctx()));
+    }
+
+    fn compare(key: Key, op: CompareOp, value: &str) -> Evaluator {
+        Evaluator::from_ast(&AstNode::Match(Pattern::Compare(
+            key,
+            op,
+            value.to_string(),
+        )))
+    }
+
+    #[test]
+    fn test_duration_and_size_comparison() {
+        let slow = compare(Key::Index(2), CompareOp::Gt, "100ms");
+        assert!(slow.evaluate("GET 1.5s\n", &ctx()));
+        assert!(slow.evaluate("GET 1m2s\n", &ctx()));
+        assert!(!slow.evaluate("GET 12ms\n", &ctx()));
+        assert!(!slow.evaluate("GET 500\n", &ctx())); // Unitless → not a duration → false
+        assert!(!slow.evaluate("GET n/a\n", &ctx()));
+
+        let big = compare(Key::Index(1), CompareOp::Ge, "1MiB");
+        assert!(big.evaluate("2MB upload\n", &ctx()));
+        assert!(!big.evaluate("512KiB upload\n", &ctx()));
+    }
+
+    #[test]
+    fn test_typed_equality() {
+        let eval = compare(Key::Index(1), CompareOp::Eq, "1s");
+        assert!(eval.evaluate("1000ms\n", &ctx()));
+        assert!(!eval.evaluate("2s\n", &ctx()));
+    }
+
+    #[test]
+    fn test_typed_range() {
+        let ast = AstNode::Match(Pattern::InRange(
+            Key::Index(1),
+            Bound::Included("100ms".to_string()),
+            Bound::Excluded("1s".to_string()),
+        ));
+        let eval = Evaluator::from_ast(&ast);
+        assert!(eval.evaluate("250ms\n", &ctx()));
+        assert!(!eval.evaluate("1s\n", &ctx()));
+    }
+
+    fn time_window(lower: &str, upper: &str) -> (Evaluator, RecordOptions) {
+        let options = RecordOptions::default().with_time(crate::core::time::TimeOptions {
+            pattern: None,
+            zone: Zone::Utc,
+        });
+        let ast = AstNode::Match(Pattern::InRange(
+            Key::Time,
+            Bound::Included(lower.to_string()),
+            Bound::Excluded(upper.to_string()),
+        ));
+        (Evaluator::compile(&ast, &options, false), options)
+    }
+
+    fn run_records(eval: &Evaluator, options: &RecordOptions, lines: &[&str]) -> Vec<bool> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let record = Record::new(line, options);
+                let ctx = EvalContext::new(i + 1);
+                eval.observe(&record, &ctx);
+                eval.matches(&record, &ctx)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_time_window_inherits_timestamp() {
+        let (eval, options) = time_window("14:00", "14:30");
+        assert!(eval.is_stateful());
+        let result = run_records(
+            &eval,
+            &options,
+            &[
+                "  continuation before any timestamp",
+                "2024-05-01T13:59:59Z start",
+                "2024-05-01T14:00:00Z error",
+                "  at frame 1",
+                "2024-05-01T14:30:00Z done",
+            ],
+        );
+        assert_eq!(result, vec![false, false, true, true, false]);
+    }
+
+    #[test]
+    fn test_time_of_day_window_wraps_midnight() {
+        let (eval, options) = time_window("22:00", "06:00");
+        let result = run_records(
+            &eval,
+            &options,
+            &[
+                "2024-05-01T21:00:00Z",
+                "2024-05-01T23:00:00Z",
+                "2024-05-02T05:59:00Z",
+                "2024-05-02T06:00:00Z",
+            ],
+        );
+        assert_eq!(result, vec![false, true, true, false]);
+    }
+
+    #[test]
+    fn test_absolute_time_window() {
+        let (eval, options) = time_window("2024-05-01", "2024-05-02T12:00:00Z");
+        let result = run_records(
+            &eval,
+            &options,
+            &[
+                "1714521599 late",     // 2024-04-30T23:59:59Z
+                "1714521600 midnight", // 2024-05-01T00:00:00Z
+                "1714651200000 noon",  // 2024-05-02T12:00:00Z
+            ],
+        );
+        assert_eq!(result, vec![false, true, false]);
+    }
+
+    fn thin(thinning: Thinning, invert: bool, seed: u64) -> (Evaluator, RecordOptions) {
+        let options = RecordOptions::default()
+            .with_time(crate::core::time::TimeOptions {
+                pattern: None,
+                zone: Zone::Utc,
+            })
+            .with_seed(seed);
+        let ast = AstNode::Thin(Box::new(literal("miss")), thinning);
+        (Evaluator::compile(&ast, &options, invert), options)
+    }
+
+    #[test]
+    fn test_sample_is_seeded() {
+        let lines: Vec<&str> = std::iter::repeat_n("cache miss", 1000).collect();
+        let run = |seed| {
+            let (eval, options) = thin(Thinning::Sample(0.1), false, seed);
+            run_records(&eval, &options, &lines)
+        };
+        let first = run(7);
+        // Unmatched lines are the survivors without invert
+        let survivors = first.iter().filter(|m| !**m).count();
+        assert!((60..140).contains(&survivors), "{survivors}");
+        assert_eq!(first, run(7));
+        assert_ne!(first, run(8));
+
+        let (eval, options) = thin(Thinning::Sample(0.1), false, 7);
+        assert_eq!(run_records(&eval, &options, &["hit"]), vec![false]);
+    }
+
+    #[test]
+    fn test_limit_uses_line_timestamps() {
+        let limit = Thinning::Limit {
+            count: 2,
+            per: std::time::Duration::from_secs(1),
+        };
+        let lines = [
+            "2024-05-01T00:00:00.000Z miss",
+            "2024-05-01T00:00:00.300Z miss",
+            "2024-05-01T00:00:00.600Z miss",
+            "2024-05-01T00:00:00.700Z hit",
+            "2024-05-01T00:00:01.100Z miss",
+        ];
+        // With invert, only survivors match
+        let (eval, options) = thin(limit, true, 0);
+        assert_eq!(
+            run_records(&eval, &options, &lines),
+            vec![true, true, false, false, true]
+        );
+
+        // A line without a timestamp counts at the time of the entry above it
+        let limit = Thinning::Limit {
+            count: 1,
+            per: std::time::Duration::from_secs(1),
+        };
+        let lines = [
+            "2024-05-01T00:00:00.000Z miss",
+            "  miss continued",
+            "2024-05-01T00:00:00.100Z miss",
+        ];
+        let (eval, options) = thin(limit, true, 0);
+        assert_eq!(
+            run_records(&eval, &options, &lines),
+            vec![true, false, false]
+        );
     }
 }
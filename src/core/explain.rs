@@ -0,0 +1,191 @@
+use crate::core::ast::{AstNode, BlockBounds, CompareOp, DisplayPeriod, Key, Pattern, Thinning};
+use std::fmt::{self, Write};
+use std::ops::Bound;
+use std::time::Duration;
+
+// Describes in words which lines a query matches: an outline with one condition per line, the
+// operands of `&` and `|` indented below "all of" / "any of".
+pub fn explain(ast: &AstNode, invert: bool) -> String {
+    let mut out = String::from(if invert {
+        "Keeps only the lines that match:\n"
+    } else {
+        "Drops the lines that match:\n"
+    });
+    describe(&mut out, ast, 1).expect("writing to a String cannot fail");
+    out
+}
+
+fn describe(out: &mut String, node: &AstNode, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    match node {
+        AstNode::OrNode(..) | AstNode::AndNode(..) => {
+            let any = matches!(node, AstNode::OrNode(..));
+            writeln!(out, "{indent}{} of:", if any { "any" } else { "all" })?;
+            for operand in operands(node) {
+                describe(out, operand, depth + 1)?;
+            }
+            Ok(())
+        }
+        AstNode::NotNode(inner) => {
+            writeln!(out, "{indent}not:")?;
+            describe(out, inner, depth + 1)
+        }
+        AstNode::Match(pattern) => writeln!(out, "{indent}{}", describe_pattern(pattern)),
+        AstNode::Profile(name) => writeln!(out, "{indent}the query of profile {:?}", name),
+        AstNode::Between(start, end, bounds) => {
+            writeln!(out, "{indent}every line from a line matching:")?;
+            describe(out, start, depth + 1)?;
+            writeln!(out, "{indent}through the next line matching:")?;
+            describe(out, end, depth + 1)?;
+            if *bounds == BlockBounds::Exclusive {
+                writeln!(out, "{indent}but not those two lines")?;
+            }
+            Ok(())
+        }
+        AstNode::Thin(inner, thinning) => {
+            match thinning {
+                Thinning::Sample(rate) => writeln!(
+                    out,
+                    "{indent}all but a random {}% of the lines matching:",
+                    rate * 100.0
+                )?,
+                Thinning::Limit { count, per } => writeln!(
+                    out,
+                    "{indent}all but {} per {} of the lines matching:",
+                    count,
+                    describe_period(*per)
+                )?,
+            }
+            describe(out, inner, depth + 1)
+        }
+    }
+}
+
+// `5m` as in `limit(10/5m, ...)`, but a single unit in words.
+fn describe_period(per: Duration) -> String {
+    match DisplayPeriod(per).to_string().as_str() {
+        "s" => "second".to_string(),
+        "m" => "minute".to_string(),
+        "h" => "hour".to_string(),
+        "d" => "day".to_string(),
+        period => period.to_string(),
+    }
+}
+
+// The operands of a chain of the same operator, however it is parenthesized.
+fn operands(node: &AstNode) -> Vec<&AstNode> {
+    let same = |n: &AstNode| std::mem::discriminant(n) == std::mem::discriminant(node);
+    let mut operands = Vec::new();
+    let mut stack = vec![node];
+    while let Some(n) = stack.pop() {
+        match n {
+            AstNode::OrNode(lhs, rhs) | AstNode::AndNode(lhs, rhs) if same(n) => {
+                stack.extend([&**rhs, &**lhs])
+            }
+            _ => operands.push(n),
+        }
+    }
+    operands
+}
+
+fn describe_pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Literal(text) => format!("the line contains {:?}", text),
+        Pattern::Compare(key, op, value) => {
+            format!(
+                "{} {} {:?}",
+                describe_key(key),
+                describe_op(key, *op),
+                value
+            )
+        }
+        Pattern::InRange(key, lower, upper) => {
+            let lower = match lower {
+                Bound::Included(v) => Some((CompareOp::Ge, v)),
+                Bound::Excluded(v) => Some((CompareOp::Gt, v)),
+                Bound::Unbounded => None,
+            };
+            let upper = match upper {
+                Bound::Included(v) => Some((CompareOp::Le, v)),
+                Bound::Excluded(v) => Some((CompareOp::Lt, v)),
+                Bound::Unbounded => None,
+            };
+            let bounds = lower
+                .into_iter()
+                .chain(upper)
+                .map(|(op, v)| format!("{} {:?}", describe_op(key, op), v))
+                .collect::<Vec<_>>();
+            format!("{} {}", describe_key(key), bounds.join(" and "))
+        }
+        Pattern::Has(key) => format!("{} is present", describe_key(key)),
+    }
+}
+
+fn describe_key(key: &Key) -> String {
+    match key {
+        Key::Line => "the line number".to_string(),
+        Key::Time => "the time of the line".to_string(),
+        Key::Index(0) => "the whole line".to_string(),
+        Key::Column(name) => format!("column {:?}", name),
+        _ => format!("field {}", key),
+    }
+}
+
+fn describe_op(key: &Key, op: CompareOp) -> &'static str {
+    match (key, op) {
+        (Key::Time, CompareOp::Lt) => "is before",
+        (Key::Time, CompareOp::Le) => "is at or before",
+        (Key::Time, CompareOp::Gt) => "is after",
+        (Key::Time, CompareOp::Ge) => "is at or after",
+        (_, CompareOp::Lt) => "is less than",
+        (_, CompareOp::Le) => "is at most",
+        (_, CompareOp::Gt) => "is greater than",
+        (_, CompareOp::Ge) => "is at least",
+        (_, CompareOp::Eq) => "is",
+        (_, CompareOp::Ne) => "is not",
+        (_, CompareOp::Contains) => "contains",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::parser::parse_query;
+
+    #[test]
+    fn test_explain_outline() {
+        let ast = parse_query("DEBUG | (healthcheck | ping) & !line in 1..=10").unwrap();
+        assert_eq!(
+            explain(&ast, false),
+            "Drops the lines that match:
+  any of:
+    the line contains \"DEBUG\"
+    all of:
+      any of:
+        the line contains \"healthcheck\"
+        the line contains \"ping\"
+      not:
+        the line number is at least \"1\" and is at most \"10\"
+"
+        );
+    }
+
+    #[test]
+    fn test_explain_functions() {
+        let ast =
+            parse_query("limit(10/s, between(BEGIN, END, exclusive)) & time < 14:00").unwrap();
+        assert_eq!(
+            explain(&ast, true),
+            "Keeps only the lines that match:
+  all of:
+    all but 10 per second of the lines matching:
+      every line from a line matching:
+        the line contains \"BEGIN\"
+      through the next line matching:
+        the line contains \"END\"
+      but not those two lines
+    the time of the line is before \"14:00\"
+"
+        );
+    }
+}
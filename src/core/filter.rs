@@ -1,40 +1,125 @@
 use crate::core::ast::AstNode;
-use crate::core::evaluator::Evaluator;
-use anyhow::{Context, Result};
+use crate::core::ast::Key;
+use crate::core::bytecode::Program;
+use crate::core::evaluator::EvalContext;
+use crate::core::record::{Format, InvalidPolicy, Record, RecordOptions};
+use crate::error::{Error, Result};
 
 // Converts an AST node into an executable filter closure
 pub fn build_filter(
     ast: &AstNode,
     invert: bool,
-) -> Result<impl Fn(&[u8]) -> Result<bool> + Send + Sync + 'static> {
-    let evaluator = Evaluator::from_ast(ast);
-
-    Ok(move |input: &[u8]| {
-        let text = std::str::from_utf8(input).with_context(|| "Input is not valid UTF-8")?;
-        let matched = evaluator.evaluate(text);
-        Ok(if invert { matched } else { !matched })
-    })
+) -> Result<impl Fn(&[u8], &EvalContext) -> anyhow::Result<bool> + Send + Sync + 'static> {
+    build_filter_with(ast, invert, RecordOptions::default())
+}
+
+// Same as `build_filter`, but field predicates split lines as described by `options`.
+//
+// With `options.header`, the first line is always output and provides the column names for `col(...)`.
+// With a structured `options.format`, lines that fail to parse are handled as `options.on_invalid` says;
+// `Keep`/`Drop` apply regardless of `invert`.
+pub fn build_filter_with(
+    ast: &AstNode,
+    invert: bool,
+    options: RecordOptions,
+) -> Result<impl Fn(&[u8], &EvalContext) -> anyhow::Result<bool> + Send + Sync + 'static> {
+    let filter = LineFilter::new(ast, invert, options)?;
+    Ok(move |input: &[u8], ctx: &EvalContext| Ok(filter.keep(input, ctx)?))
+}
+
+// A compiled query together with the options it was built for; `build_filter_with` as a type.
+pub struct LineFilter {
+    program: Program,
+    options: RecordOptions,
+    invert: bool,
+    stateful: bool,
+    check_invalid: bool,
+}
+
+impl LineFilter {
+    pub fn new(ast: &AstNode, invert: bool, options: RecordOptions) -> Result<Self> {
+        if let Some(name) = ast.profiles().first() {
+            return Err(Error::Unsupported(format!(
+                "@profile({}) must be resolved from chaf.toml first",
+                name
+            )));
+        }
+        if !options.header && ast.any_pattern(&|p| matches!(p.key(), Some(Key::Column(_)))) {
+            return Err(Error::Unsupported(
+                "col(...) requires a header row (--header)".to_string(),
+            ));
+        }
+        if options.format != Format::Jsonl
+            && ast.any_pattern(&|p| matches!(p.key(), Some(Key::Path(_))))
+        {
+            return Err(Error::Unsupported(
+                "JSON paths such as '.level' require --format=jsonl".to_string(),
+            ));
+        }
+        let uses_parsed =
+            ast.any_pattern(&|p| matches!(p.key(), Some(Key::Path(_) | Key::Name(_))));
+
+        let program = Program::compile(ast, &options, invert);
+        Ok(Self {
+            stateful: program.is_stateful(),
+            check_invalid: uses_parsed && options.on_invalid != InvalidPolicy::Raw,
+            program,
+            options,
+            invert,
+        })
+    }
+
+    // Returns `true` if the line is output. Lines must be passed in order when the query is stateful.
+    pub fn keep(&self, input: &[u8], ctx: &EvalContext) -> Result<bool> {
+        let text = std::str::from_utf8(input).map_err(|_| Error::InvalidUtf8)?;
+        if self.options.header && ctx.line_no == 1 {
+            self.options.set_header(text);
+            return Ok(true);
+        }
+        let record = Record::new(text, &self.options);
+        // A block marker or timestamp on a line that fails to parse still counts
+        if self.stateful {
+            self.program.observe(&record, ctx);
+        }
+        if self.check_invalid && !record.parse() {
+            return Ok(self.options.on_invalid == InvalidPolicy::Keep);
+        }
+        let matched = self.program.matches(&record, ctx);
+        Ok(if self.invert { matched } else { !matched })
+    }
+}
+
+// Returns `true` if the result for a line depends on previous lines (`between(...)`, `time`,
+// `sample(...)`, `limit(...)`), so lines must be filtered one after another.
+pub fn is_stateful(ast: &AstNode) -> bool {
+    Program::from_ast(ast).is_stateful()
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
-    use crate::core::ast::{AstNode, Pattern};
+    use crate::core::ast::{AstNode, CompareOp, Pattern};
+    use crate::core::record::Delimiter;
 
     fn lit(s: &str) -> AstNode {
         AstNode::Match(Pattern::Literal(s.to_string()))
     }
 
+    fn ctx() -> EvalContext {
+        EvalContext::new(1)
+    }
+
     #[test]
     fn test_match_without_invert() {
         let ast = lit("ERROR");
         let filter = build_filter(&ast, false).expect("filter build failed");
 
         // Contains "ERROR" → matched=true → invert=false → returns false (excluded)
-        assert_eq!(filter(b"this is ERROR").unwrap(), false);
+        assert_eq!(filter(b"this is ERROR", &ctx()).unwrap(), false);
 
         // Does not contain → matched=false → invert=false → returns true (included)
-        assert_eq!(filter(b"all good").unwrap(), true);
+        assert_eq!(filter(b"all good", &ctx()).unwrap(), true);
     }
 
     #[test]
@@ -43,10 +128,10 @@ mod tests {
         let filter = build_filter(&ast, true).expect("filter build failed");
 
         // Contains "ERROR" → matched=true → invert=true → returns true (included)
-        assert_eq!(filter(b"this is ERROR").unwrap(), true);
+        assert_eq!(filter(b"this is ERROR", &ctx()).unwrap(), true);
 
         // Does not contain → matched=false → invert=true → returns false (excluded)
-        assert_eq!(filter(b"all good").unwrap(), false);
+        assert_eq!(filter(b"all good", &ctx()).unwrap(), false);
     }
 
     #[test]
@@ -56,11 +141,11 @@ mod tests {
         let f1 = build_filter(&ast, false).unwrap();
         let f2 = build_filter(&ast, true).unwrap();
 
-        assert_eq!(f1(b"foo bar").unwrap(), false); // matched → invert=false → false
-        assert_eq!(f1(b"foo only").unwrap(), true); // not matched → true
+        assert_eq!(f1(b"foo bar", &ctx()).unwrap(), false); // matched → invert=false → false
+        assert_eq!(f1(b"foo only", &ctx()).unwrap(), true); // not matched → true
 
-        assert_eq!(f2(b"foo bar").unwrap(), true); // matched → invert=true → true
-        assert_eq!(f2(b"foo only").unwrap(), false); // not matched → invert=true → false
+        assert_eq!(f2(b"foo bar", &ctx()).unwrap(), true); // matched → invert=true → true
+        assert_eq!(f2(b"foo only", &ctx()).unwrap(), false); // not matched → invert=true → false
     }
 
     #[test]
@@ -70,11 +155,11 @@ mod tests {
         let f = build_filter(&ast, false).unwrap();
         let g = build_filter(&ast, true).unwrap();
 
-        assert_eq!(f(b"DEBUG").unwrap(), true); // matched=false → ! → false → invert=false → true
-        assert_eq!(f(b"INFO").unwrap(), false); // matched=true → invert=false → false
+        assert_eq!(f(b"DEBUG", &ctx()).unwrap(), true); // matched=false → ! → false → invert=false → true
+        assert_eq!(f(b"INFO", &ctx()).unwrap(), false); // matched=true → invert=false → false
 
-        assert_eq!(g(b"DEBUG").unwrap(), false); // matched=false → ! → false → invert=true → false
-        assert_eq!(g(b"INFO").unwrap(), true); // matched=true → invert=true → true
+        assert_eq!(g(b"DEBUG", &ctx()).unwrap(), false); // matched=false → ! → false → invert=true → false
+        assert_eq!(g(b"INFO", &ctx()).unwrap(), true); // matched=true → invert=true → true
     }
 
     #[test]
@@ -82,8 +167,115 @@ mod tests {
         let ast = lit("foo");
         let filter = build_filter(&ast, false).unwrap();
 
-        let result = filter(&[0xff, 0xfe, 0xfd]);
+        let result = filter(&[0xff, 0xfe, 0xfd], &ctx());
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("UTF-8"));
     }
+
+    #[test]
+    fn test_header_line_is_kept_and_names_columns() {
+        let ast = AstNode::Match(Pattern::Compare(
+            Key::Column("status".into()),
+            CompareOp::Eq,
+            "500".into(),
+        ));
+        let options = RecordOptions::new(Delimiter::Char(','), true);
+        let filter = build_filter_with(&ast, false, options).unwrap();
+
+        assert_eq!(filter(b"id,status\n", &EvalContext::new(1)).unwrap(), true);
+        assert_eq!(filter(b"1,500\n", &EvalContext::new(2)).unwrap(), false);
+        assert_eq!(filter(b"2,200\n", &EvalContext::new(3)).unwrap(), true);
+    }
+
+    #[test]
+    fn test_column_without_header_is_rejected() {
+        let ast = AstNode::Match(Pattern::Compare(
+            Key::Column("status".into()),
+            CompareOp::Eq,
+            "500".into(),
+        ));
+        let err = build_filter(&ast, false).err().unwrap();
+        assert!(err.to_string().contains("--header"));
+    }
+
+    fn json_options(on_invalid: InvalidPolicy) -> RecordOptions {
+        RecordOptions::default().with_format(Format::Jsonl, on_invalid)
+    }
+
+    fn level_is_debug() -> AstNode {
+        AstNode::Match(Pattern::Compare(
+            Key::Path(vec!["level".into()]),
+            CompareOp::Eq,
+            "debug".into(),
+        ))
+    }
+
+    #[test]
+    fn test_json_path_filter() {
+        let filter =
+            build_filter_with(&level_is_debug(), false, json_options(InvalidPolicy::Raw)).unwrap();
+
+        assert_eq!(filter(br#"{"level":"debug"}"#, &ctx()).unwrap(), false);
+        assert_eq!(filter(br#"{"level":"info"}"#, &ctx()).unwrap(), true);
+        assert_eq!(filter(b"level debug, not json", &ctx()).unwrap(), true); // Raw: path is false
+    }
+
+    #[test]
+    fn test_invalid_json_policy() {
+        let keep =
+            build_filter_with(&level_is_debug(), true, json_options(InvalidPolicy::Keep)).unwrap();
+        let drop =
+            build_filter_with(&level_is_debug(), false, json_options(InvalidPolicy::Drop)).unwrap();
+
+        assert_eq!(keep(b"garbage", &ctx()).unwrap(), true);
+        assert_eq!(drop(b"garbage", &ctx()).unwrap(), false);
+        assert_eq!(drop(br#"{"level":"info"}"#, &ctx()).unwrap(), true);
+    }
+
+    #[test]
+    fn test_invalid_lines_still_mark_blocks() {
+        let ast = crate::core::parser::parse_query_for(
+            "between(BEGIN, END) | .level=debug",
+            Format::Jsonl,
+        )
+        .unwrap();
+        let drop = build_filter_with(&ast, false, json_options(InvalidPolicy::Drop)).unwrap();
+        let lines: [&[u8]; 5] = [
+            br#"{"level":"info"}"#,
+            b"BEGIN",
+            br#"{"level":"info"}"#,
+            b"END",
+            br#"{"level":"info"}"#,
+        ];
+        let kept = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| drop(line, &EvalContext::new(i + 1)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [true, false, false, false, true]);
+    }
+
+    #[test]
+    fn test_json_path_requires_jsonl_format() {
+        let err = build_filter(&level_is_debug(), false).err().unwrap();
+        assert!(err.to_string().contains("--format=jsonl"));
+    }
+
+    #[test]
+    fn test_logfmt_filter_keeps_literal_semantics() {
+        let ast =
+            crate::core::parser::parse_query_for("level=debug | timeout", Format::Logfmt).unwrap();
+        let options = RecordOptions::default().with_format(Format::Logfmt, InvalidPolicy::Raw);
+        let filter = build_filter_with(&ast, false, options).unwrap();
+
+        assert_eq!(filter(b"level=debug msg=tick\n", &ctx()).unwrap(), false);
+        assert_eq!(
+            filter(b"level=info msg=\"request timeout\"\n", &ctx()).unwrap(),
+            false
+        );
+        assert_eq!(
+            filter(b"level=info msg=ok debug=1\n", &ctx()).unwrap(),
+            true
+        );
+    }
 }
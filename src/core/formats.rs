@@ -0,0 +1,539 @@
+use serde_json::Value;
+use std::borrow::Cow;
+
+// A parsed `key=value` pair. Values are unquoted and unescaped.
+pub type Pair<'a> = (Cow<'a, str>, Cow<'a, str>);
+
+// Parses a logfmt line such as `level=info msg="hello \"you\"" dur=12ms ok`.
+//
+// - Values may be bare (up to the next whitespace) or double-quoted with `\"`, `\\`, `\n`, `\t` escapes
+// - A key without `=` is a flag with an empty value
+//
+// Returns `None` if the line is not valid logfmt (an empty key or an unterminated quote).
+pub fn parse_logfmt(line: &str) -> Option<Vec<Pair<'_>>> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        if key.is_empty() || key.contains('"') {
+            return None;
+        }
+        rest = &rest[key_end..];
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) if after.starts_with('"') => {
+                let (value, len) = unquote(&after[1..])?;
+                rest = &after[1 + len..];
+                value
+            }
+            Some(after) => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                rest = &after[end..];
+                Cow::Borrowed(&after[..end])
+            }
+            None => Cow::Borrowed(""),
+        };
+        pairs.push((Cow::Borrowed(key), value));
+        rest = rest.trim_start();
+    }
+
+    Some(pairs)
+}
+
+// Syslog severities, most severe first; the index is the numeric severity.
+pub const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+// Parses an RFC 5424 or RFC 3164 syslog line.
+//
+// - RFC 5424: `<PRI>1 TIMESTAMP HOST APP PROCID MSGID [SD] MSG`; `-` marks a missing field and
+//   structured data parameters become fields of their own
+// - RFC 3164: `[<PRI>]TIMESTAMP HOST TAG[PID]: MSG`, with a BSD (`Oct 11 22:14:15`) or ISO timestamp,
+//   as written to `/var/log/syslog` by most daemons
+//
+// Fields: `facility`, `severity` (when a PRI is present), `timestamp`, `host`, `program`, `pid`,
+// `msgid`, `message`.
+pub fn parse_syslog(line: &str) -> Option<Vec<Pair<'_>>> {
+    let mut pairs = Vec::new();
+    let mut rest = line;
+
+    if let Some(after) = rest.strip_prefix('<') {
+        let end = after.find('>')?;
+        let pri: usize = after[..end].parse().ok().filter(|&p| p < 192)?;
+        pairs.push((
+            Cow::Borrowed("facility"),
+            Cow::Borrowed(FACILITIES[pri / 8]),
+        ));
+        pairs.push((
+            Cow::Borrowed("severity"),
+            Cow::Borrowed(SEVERITIES[pri % 8]),
+        ));
+        rest = &after[end + 1..];
+        if let Some(after) = rest.strip_prefix("1 ") {
+            parse_rfc5424(after, &mut pairs)?;
+            return Some(pairs);
+        }
+    }
+
+    let (timestamp, after) = split_syslog_timestamp(rest)?;
+    push_field(&mut pairs, "timestamp", timestamp);
+    let (host, after) = next_word(after)?;
+    push_field(&mut pairs, "host", host);
+
+    // `program[pid]: message`; lines without a tag are all message
+    let tag_end = after
+        .find(": ")
+        .filter(|&i| !after[..i].contains(char::is_whitespace));
+    let message = match tag_end {
+        Some(i) => {
+            let tag = &after[..i];
+            match tag.split_once('[') {
+                Some((program, pid)) => {
+                    push_field(&mut pairs, "program", program);
+                    push_field(&mut pairs, "pid", pid.trim_end_matches(']'));
+                }
+                None => push_field(&mut pairs, "program", tag),
+            }
+            &after[i + 2..]
+        }
+        None => after,
+    };
+    pairs.push((Cow::Borrowed("message"), Cow::Borrowed(message)));
+    Some(pairs)
+}
+
+fn parse_rfc5424<'a>(line: &'a str, pairs: &mut Vec<Pair<'a>>) -> Option<()> {
+    let mut rest = line;
+    for name in ["timestamp", "host", "program", "pid", "msgid"] {
+        let (value, after) = next_word(rest)?;
+        push_field(pairs, name, value);
+        rest = after;
+    }
+
+    // Structured data: `-` or one or more `[id name="value" ...]` elements
+    rest = match rest.strip_prefix('-') {
+        Some(after) => after,
+        None => {
+            while let Some(after) = rest.strip_prefix('[') {
+                rest = parse_sd_element(after, pairs)?;
+            }
+            rest
+        }
+    };
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    // A UTF-8 byte order mark may precede the message
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+    if !message.is_empty() {
+        pairs.push((Cow::Borrowed("message"), Cow::Borrowed(message)));
+    }
+    Some(())
+}
+
+// Reads one structured data element after its `[`. Returns the input after the closing `]`.
+fn parse_sd_element<'a>(input: &'a str, pairs: &mut Vec<Pair<'a>>) -> Option<&'a str> {
+    let id_end = input.find([' ', ']'])?;
+    let mut rest = &input[id_end..];
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if let Some(after) = rest.strip_prefix(']') {
+            return Some(after);
+        }
+        let (name, after) = rest.split_once("=\"")?;
+        let (value, len) = unquote(after)?;
+        pairs.push((Cow::Borrowed(name), value));
+        rest = &after[len..];
+    }
+}
+
+// Splits a BSD (`Oct 11 22:14:15`, `Oct  1 22:14:15`) or ISO 8601 timestamp from the front of `line`.
+fn split_syslog_timestamp(line: &str) -> Option<(&str, &str)> {
+    let bsd = line.get(..15).filter(|t| {
+        let b = t.as_bytes();
+        b[..3].iter().all(u8::is_ascii_alphabetic)
+            && b[3] == b' '
+            && b[6] == b' '
+            && b[9] == b':'
+            && b[12] == b':'
+    });
+    match bsd {
+        Some(timestamp) => Some((timestamp, line[15..].strip_prefix(' ')?)),
+        None => {
+            let (timestamp, rest) = next_word(line)?;
+            let iso = timestamp.len() >= 19
+                && timestamp.as_bytes()[4] == b'-'
+                && timestamp.as_bytes()[10] == b'T';
+            iso.then_some((timestamp, rest))
+        }
+    }
+}
+
+// Parses an Apache/nginx access log line in the combined (or common) log format:
+// `host ident user [time] "method path protocol" status bytes "referer" "user agent"`.
+//
+// Fields: `host`, `ident`, `user`, `time`, `request`, `method`, `path`, `protocol`, `status`,
+// `bytes`, `referer`, `user_agent`. Fields logged as `-` are missing. Anything after the user agent
+// (such as nginx's `$request_time`) is ignored.
+pub fn parse_combined(line: &str) -> Option<Vec<Pair<'_>>> {
+    let mut pairs = Vec::new();
+    let mut rest = line.trim_end();
+
+    for name in ["host", "ident", "user"] {
+        let (value, after) = next_word(rest)?;
+        push_field(&mut pairs, name, value);
+        rest = after;
+    }
+
+    let after = rest.strip_prefix('[')?;
+    let end = after.find(']')?;
+    pairs.push((Cow::Borrowed("time"), Cow::Borrowed(&after[..end])));
+    rest = after[end + 1..].strip_prefix(" \"")?;
+
+    let (request, len) = unquote(rest)?;
+    rest = &rest[len..];
+    let parts: Vec<&str> = request.split(' ').collect();
+    if let [method, path, protocol] = parts[..] {
+        for (name, value) in [("method", method), ("path", path), ("protocol", protocol)] {
+            pairs.push((Cow::Borrowed(name), Cow::Owned(value.to_string())));
+        }
+    }
+    if request != "-" {
+        pairs.push((Cow::Borrowed("request"), request));
+    }
+
+    for name in ["status", "bytes"] {
+        let (value, after) = next_word(rest.trim_start()).or(Some((rest.trim(), "")))?;
+        if value.is_empty() {
+            return None;
+        }
+        push_field(&mut pairs, name, value);
+        rest = after;
+    }
+
+    // The common log format ends here
+    for name in ["referer", "user_agent"] {
+        let Some(after) = rest.strip_prefix('"') else {
+            break;
+        };
+        let (value, len) = unquote(after)?;
+        if value != "-" {
+            pairs.push((Cow::Borrowed(name), value));
+        }
+        rest = after[len..].trim_start();
+    }
+
+    Some(pairs)
+}
+
+// Parses one entry of `journalctl -o json` (one JSON object per line).
+//
+// All journal fields (`MESSAGE`, `_SYSTEMD_UNIT`, ...) are available by name, along with
+// the aliases described in `journal_aliases`.
+pub fn parse_journal_json(line: &str) -> Option<Vec<Pair<'static>>> {
+    let Value::Object(map) = serde_json::from_str(line).ok()? else {
+        return None;
+    };
+    let mut pairs: Vec<Pair> = map
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::Null => return None,
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            Some((Cow::Owned(key), Cow::Owned(value)))
+        })
+        .collect();
+    journal_aliases(&mut pairs);
+    Some(pairs)
+}
+
+// Parses one entry of `journalctl -o export`: `FIELD=value` lines ending with a blank line.
+//
+// Binary fields (a field name followed by a length-prefixed value) are not supported and make
+// the entry invalid.
+pub fn parse_journal_export(entry: &str) -> Option<Vec<Pair<'_>>> {
+    let mut pairs = Vec::new();
+    for line in entry.lines().filter(|l| !l.is_empty()) {
+        let (key, value) = line.split_once('=')?;
+        pairs.push((Cow::Borrowed(key), Cow::Borrowed(value)));
+    }
+    if pairs.is_empty() {
+        return None;
+    }
+    journal_aliases(&mut pairs);
+    Some(pairs)
+}
+
+// Adds the fields shared with the syslog presets:
+// `host`, `program`, `pid`, `unit`, `message`, `severity` and `facility`.
+fn journal_aliases(pairs: &mut Vec<Pair<'_>>) {
+    let find = |pairs: &[Pair], names: &[&str]| {
+        names.iter().find_map(|name| {
+            pairs
+                .iter()
+                .rev()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone().into_owned())
+        })
+    };
+    let aliases: [(&str, &[&str]); 5] = [
+        ("host", &["_HOSTNAME"]),
+        ("program", &["SYSLOG_IDENTIFIER", "_COMM"]),
+        ("pid", &["_PID", "SYSLOG_PID"]),
+        ("unit", &["_SYSTEMD_UNIT"]),
+        ("message", &["MESSAGE"]),
+    ];
+    for (alias, names) in aliases {
+        if let Some(value) = find(pairs, names) {
+            pairs.push((Cow::Borrowed(alias), Cow::Owned(value)));
+        }
+    }
+    // Journal priorities and facilities are numeric
+    fn named(table: &'static [&'static str], value: Option<String>) -> Option<&'static str> {
+        table.get(value?.parse::<usize>().ok()?).copied()
+    }
+    if let Some(severity) = named(&SEVERITIES, find(pairs, &["PRIORITY"])) {
+        pairs.push((Cow::Borrowed("severity"), Cow::Borrowed(severity)));
+    }
+    if let Some(facility) = named(&FACILITIES, find(pairs, &["SYSLOG_FACILITY"])) {
+        pairs.push((Cow::Borrowed("facility"), Cow::Borrowed(facility)));
+    }
+}
+
+// Splits the first space-separated word from `input`.
+fn next_word(input: &str) -> Option<(&str, &str)> {
+    let (word, rest) = input.split_once(' ')?;
+    (!word.is_empty()).then_some((word, rest))
+}
+
+// Pushes a field unless it is the nil value `-`.
+fn push_field<'a>(pairs: &mut Vec<Pair<'a>>, name: &'static str, value: &'a str) {
+    if value != "-" && !value.is_empty() {
+        pairs.push((Cow::Borrowed(name), Cow::Borrowed(value)));
+    }
+}
+
+// Reads a quoted value up to its closing quote (which must already be past the opening one).
+// Returns the value and the number of bytes consumed, including the closing quote.
+fn unquote(input: &str) -> Option<(Cow<'_, str>, usize)> {
+    let mut owned: Option<String> = None;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let value = match owned {
+                    Some(s) => Cow::Owned(s),
+                    None => Cow::Borrowed(&input[..i]),
+                };
+                return Some((value, i + 1));
+            }
+            '\\' => {
+                let buf = owned.get_or_insert_with(|| input[..i].to_string());
+                let (_, escaped) = chars.next()?;
+                buf.push(match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    other => other,
+                });
+            }
+            _ => {
+                if let Some(buf) = owned.as_mut() {
+                    buf.push(c);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(line: &str) -> Vec<(String, String)> {
+        parse_logfmt(line)
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_logfmt_pairs() {
+        let parsed = pairs(r#"level=info msg="hello \"you\"" dur=12ms debug"#);
+        assert_eq!(
+            parsed,
+            vec![
+                ("level".to_string(), "info".to_string()),
+                ("msg".to_string(), "hello \"you\"".to_string()),
+                ("dur".to_string(), "12ms".to_string()),
+                ("debug".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logfmt_empty_and_spaced_values() {
+        let parsed = pairs("a= b=\"x y\"  c=\"\"\n");
+        assert_eq!(
+            parsed,
+            vec![
+                ("a".to_string(), String::new()),
+                ("b".to_string(), "x y".to_string()),
+                ("c".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logfmt_invalid() {
+        assert!(parse_logfmt(r#"msg="unterminated"#).is_none());
+        assert!(parse_logfmt("=value").is_none());
+    }
+
+    fn fields(parsed: Option<Vec<Pair<'_>>>) -> Vec<(String, String)> {
+        parsed
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect()
+    }
+
+    fn get<'f>(fields: &'f [(String, String)], name: &str) -> Option<&'f str> {
+        fields
+            .iter()
+            .rev()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_syslog_rfc3164() {
+        let f = fields(parse_syslog(
+            "<38>Oct 11 22:14:15 mymachine sshd[4242]: Accepted publickey for root",
+        ));
+        assert_eq!(get(&f, "facility"), Some("auth"));
+        assert_eq!(get(&f, "severity"), Some("info"));
+        assert_eq!(get(&f, "timestamp"), Some("Oct 11 22:14:15"));
+        assert_eq!(get(&f, "host"), Some("mymachine"));
+        assert_eq!(get(&f, "program"), Some("sshd"));
+        assert_eq!(get(&f, "pid"), Some("4242"));
+        assert_eq!(get(&f, "message"), Some("Accepted publickey for root"));
+
+        // No PRI and an ISO timestamp, as in /var/log/syslog
+        let f = fields(parse_syslog(
+            "2024-05-01T14:03:07.123+00:00 web1 CRON: (root) CMD (run-parts)",
+        ));
+        assert_eq!(get(&f, "severity"), None);
+        assert_eq!(get(&f, "program"), Some("CRON"));
+        assert_eq!(get(&f, "pid"), None);
+
+        assert!(parse_syslog("not a syslog line").is_none());
+    }
+
+    #[test]
+    fn test_syslog_rfc5424() {
+        let f = fields(parse_syslog(
+            r#"<165>1 2003-10-11T22:14:15.003Z host.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="App\"lication"] An application event"#,
+        ));
+        assert_eq!(get(&f, "facility"), Some("local4"));
+        assert_eq!(get(&f, "severity"), Some("notice"));
+        assert_eq!(get(&f, "host"), Some("host.example.com"));
+        assert_eq!(get(&f, "program"), Some("evntslog"));
+        assert_eq!(get(&f, "pid"), None);
+        assert_eq!(get(&f, "msgid"), Some("ID47"));
+        assert_eq!(get(&f, "eventSource"), Some("App\"lication"));
+        assert_eq!(get(&f, "message"), Some("An application event"));
+
+        let f = fields(parse_syslog(
+            "<34>1 2003-10-11T22:14:15Z mymachine su 123 - -",
+        ));
+        assert_eq!(get(&f, "severity"), Some("crit"));
+        assert_eq!(get(&f, "pid"), Some("123"));
+        assert_eq!(get(&f, "message"), None);
+    }
+
+    #[test]
+    fn test_combined_log() {
+        let f = fields(parse_combined(
+            r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)""#,
+        ));
+        assert_eq!(get(&f, "host"), Some("127.0.0.1"));
+        assert_eq!(get(&f, "ident"), None);
+        assert_eq!(get(&f, "user"), Some("frank"));
+        assert_eq!(get(&f, "time"), Some("10/Oct/2000:13:55:36 -0700"));
+        assert_eq!(get(&f, "method"), Some("GET"));
+        assert_eq!(get(&f, "path"), Some("/apache_pb.gif"));
+        assert_eq!(get(&f, "status"), Some("200"));
+        assert_eq!(get(&f, "bytes"), Some("2326"));
+        assert_eq!(
+            get(&f, "user_agent"),
+            Some("Mozilla/4.08 [en] (Win98; I ;Nav)")
+        );
+
+        // Common log format, no body
+        let f = fields(parse_combined(
+            r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "-" 400 -"#,
+        ));
+        assert_eq!(get(&f, "status"), Some("400"));
+        assert_eq!(get(&f, "bytes"), None);
+        assert_eq!(get(&f, "request"), None);
+
+        assert!(parse_combined("GET /index.html 200").is_none());
+    }
+
+    #[test]
+    fn test_journal_entries() {
+        let f = fields(parse_journal_json(
+            r#"{"MESSAGE":"Started Daily apt.","PRIORITY":"6","_HOSTNAME":"web1","SYSLOG_IDENTIFIER":"systemd","_PID":"1","_SYSTEMD_UNIT":"init.scope"}"#,
+        ));
+        assert_eq!(get(&f, "MESSAGE"), Some("Started Daily apt."));
+        assert_eq!(get(&f, "message"), Some("Started Daily apt."));
+        assert_eq!(get(&f, "severity"), Some("info"));
+        assert_eq!(get(&f, "program"), Some("systemd"));
+        assert_eq!(get(&f, "unit"), Some("init.scope"));
+
+        let f = fields(parse_journal_export(
+            "_COMM=cron\nPRIORITY=3\nSYSLOG_FACILITY=9\nMESSAGE=a=b\n\n",
+        ));
+        assert_eq!(get(&f, "program"), Some("cron"));
+        assert_eq!(get(&f, "severity"), Some("err"));
+        assert_eq!(get(&f, "facility"), Some("cron"));
+        assert_eq!(get(&f, "message"), Some("a=b"));
+
+        assert!(parse_journal_json("[1, 2]").is_none());
+        assert!(parse_journal_export("\n").is_none());
+    }
+}
@@ -1,38 +1,325 @@
-use crate::core::ast::{AstNode, Pattern};
-use anyhow::{bail, Result};
+use crate::core::ast::{AstNode, BlockBounds, CompareOp, Key, Pattern, Thinning};
+use crate::core::record::Format;
+use crate::core::time::{TimeBound, Zone};
+use crate::core::value::{parse_as, parse_scalar, Unit};
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::time::Duration;
+
+// Returns an `Error::Syntax` at `pos` from the enclosing function.
+macro_rules! syntax_error {
+    ($pos:expr, $($arg:tt)+) => {
+        return Err(Error::syntax($pos, format!($($arg)+)))
+    };
+}
+
+/// Bounds on the size of a query, so a generated or hostile query is rejected with an error
+/// instead of exhausting the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    /// How deeply parentheses, `!` and function arguments may nest (default 256).
+    pub max_depth: usize,
+    /// How many terms (patterns and function calls) the query may contain (default 10 000).
+    pub max_terms: usize,
+}
+
+impl QueryLimits {
+    /// The largest `max_depth` the parser and the walkers over its tree are known to handle
+    /// on the main thread's stack.
+    pub const MAX_DEPTH: usize = 1000;
+    /// The largest `max_terms` accepted by the command line.
+    pub const MAX_TERMS: usize = 1_000_000;
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            max_terms: 10_000,
+        }
+    }
+}
+
+/// Values for the placeholders in a query: `$name` for a parameter, and `${VAR}` for an
+/// environment variable.
+///
+/// Environment variables are only read when asked for with [`with_process_env`] or
+/// [`with_env`], so a query from an untrusted source cannot read the process environment.
+///
+/// [`with_process_env`]: QueryParams::with_process_env
+/// [`with_env`]: QueryParams::with_env
+///
+/// A placeholder stands for a whole pattern or comparison value, and its value is always taken
+/// literally: `&`, `|` or quotes in it are never parsed as query syntax.
+///
+/// ```
+/// use chaf::ast::{AstNode, Pattern};
+/// use chaf::{Format, Query, QueryLimits, QueryParams};
+///
+/// let params = QueryParams::new().with_param("svc", "api | web");
+/// let query = Query::parse_with_params("$svc", Format::Plain, QueryLimits::default(), &params)?;
+/// assert_eq!(query.ast(), &AstNode::Match(Pattern::Literal("api | web".into())));
+/// # Ok::<(), chaf::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryParams {
+    params: HashMap<String, String>,
+    env: Env,
+}
+
+// Where `${VAR}` is read from.
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Env {
+    #[default]
+    None,
+    Process,
+    Vars(HashMap<String, String>),
+}
+
+impl QueryParams {
+    /// No parameters and no environment variables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of `$name`.
+    pub fn with_param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(name.into(), value.into());
+        self
+    }
+
+    /// Reads `${VAR}` from the environment of the process.
+    pub fn with_process_env(mut self) -> Self {
+        self.env = Env::Process;
+        self
+    }
+
+    /// Reads `${VAR}` from `vars`.
+    pub fn with_env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env = Env::Vars(
+            vars.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        );
+        self
+    }
+
+    fn param(&self, name: &str) -> std::result::Result<String, String> {
+        self.params
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("No value for parameter '${}'", name))
+    }
+
+    fn var(&self, name: &str) -> std::result::Result<String, String> {
+        match &self.env {
+            Env::None => {
+                return Err(format!(
+                    "Environment variable '{}' is not available to this query",
+                    name
+                ))
+            }
+            Env::Process => std::env::var(name),
+            Env::Vars(env) => env.get(name).cloned().ok_or(std::env::VarError::NotPresent),
+        }
+        .map_err(|e| match e {
+            std::env::VarError::NotPresent => {
+                format!("Environment variable '{}' is not set", name)
+            }
+            std::env::VarError::NotUnicode(_) => {
+                format!("Environment variable '{}' is not valid UTF-8", name)
+            }
+        })
+    }
+}
 
 // Parses a DSL string and constructs an AST (Abstract Syntax Tree)
 pub fn parse_query(query: &str) -> Result<AstNode> {
+    parse_query_for(query, Format::Plain)
+}
+
+// Same as `parse_query`, but for input in `format`.
+//
+// In structured formats (e.g. logfmt), a bare name followed by an operator (`level=debug`)
+// is a field predicate. In plain text it stays a literal, so existing queries keep their meaning.
+pub fn parse_query_for(query: &str, format: Format) -> Result<AstNode> {
+    parse_query_with(query, format, QueryLimits::default())
+}
+
+// Same as `parse_query_for`, with explicit limits on the size of the query.
+pub fn parse_query_with(query: &str, format: Format, limits: QueryLimits) -> Result<AstNode> {
+    parse_query_with_params(query, format, limits, &QueryParams::default())
+}
+
+// Same as `parse_query_with`, with the values of `$name` placeholders and where `${VAR}` is read.
+// The other functions have no values for either.
+pub fn parse_query_with_params(
+    query: &str,
+    format: Format,
+    limits: QueryLimits,
+    params: &QueryParams,
+) -> Result<AstNode> {
     if query.trim().is_empty() {
-        bail!("Query is empty");
+        return Err(Error::syntax(0, "Query is empty"));
     }
 
-    let mut parser = Parser::new(query);
-    let ast = parser.parse_expr()?;
+    let mut parser = Parser::new(query, params);
+    parser.named_fields = format.has_named_fields();
+    parser.limits = limits;
+    parser.parse_query()
+}
 
-    parser.consume_whitespace();
-    if parser.peek().is_some() {
-        bail!(
-            "Syntax error: unexpected trailing tokens at pos={}",
-            parser.pos
-        );
+// Parses `query` and prints it with `print`, with its placeholders written as they are instead
+// of substituted, so that templates can be formatted. Placeholder values are not checked.
+pub fn print_template(
+    query: &str,
+    format: Format,
+    limits: QueryLimits,
+    print: impl FnOnce(&AstNode) -> String,
+) -> Result<String> {
+    let params = QueryParams::new();
+    let mut parser = Parser::new(query, &params);
+    parser.named_fields = format.has_named_fields();
+    parser.limits = limits;
+    parser.kept = Some(Vec::new());
+    let ast = parser.parse_query()?;
+    let mut text = print(&ast);
+    for (token, placeholder) in parser.kept.iter().flatten() {
+        text = text.replace(token.as_str(), placeholder);
+    }
+    Ok(text)
+}
+
+// Checks a syntax tree built elsewhere (e.g. read from JSON) by printing it as query text and
+// parsing that again, so it is held to the same rules as a query typed in. Returns the text.
+pub fn query_from_ast(ast: &AstNode, format: Format, limits: QueryLimits) -> Result<String> {
+    let text = ast.to_string();
+    match parse_query_with(&text, format, limits) {
+        Ok(parsed) if parsed == *ast => Ok(text),
+        Ok(_) => Err(Error::Unsupported(format!(
+            "The syntax tree cannot be written as a query: {}",
+            text
+        ))),
+        Err(e) => Err(Error::Unsupported(format!(
+            "The syntax tree is not a valid query ({}): {}",
+            e, text
+        ))),
     }
+}
 
-    Ok(ast)
+// Restricts a query to the time window given by `--since` / `--until` (`until` is exclusive).
+//
+// Lines outside the window, including lines before the first timestamp, are always dropped:
+// without `invert` they are added to the lines the query excludes, and with `invert` the kept
+// lines must also fall inside the window.
+pub fn with_time_window(
+    ast: AstNode,
+    since: Option<&str>,
+    until: Option<&str>,
+    invert: bool,
+) -> Result<AstNode> {
+    let mut inside = None;
+    for (option, value, op) in [
+        ("--since", since, CompareOp::Ge),
+        ("--until", until, CompareOp::Lt),
+    ] {
+        let Some(value) = value else { continue };
+        if TimeBound::parse(value, Zone::Utc).is_none() {
+            return Err(Error::InvalidOption(format!(
+                "Invalid time '{}' for {}",
+                value, option
+            )));
+        }
+        let node = AstNode::Match(Pattern::Compare(Key::Time, op, value.to_string()));
+        inside = Some(match inside {
+            Some(prev) => AstNode::AndNode(Box::new(prev), Box::new(node)),
+            None => node,
+        });
+    }
+    let Some(inside) = inside else {
+        return Ok(ast);
+    };
+    Ok(if invert {
+        AstNode::AndNode(Box::new(ast), Box::new(inside))
+    } else {
+        AstNode::OrNode(Box::new(ast), Box::new(AstNode::NotNode(Box::new(inside))))
+    })
 }
 
 struct Parser<'a> {
     input: &'a str,
     pos: usize,
+    arg_depth: usize, // > 0 while parsing function arguments, where ',' ends a term
+    named_fields: bool, // Whether bare names such as `level` can be field keys
+    limits: QueryLimits,
+    depth: usize, // Current nesting of expressions and `!`
+    terms: usize, // Patterns parsed so far
+    params: &'a QueryParams,
+    // For `print_template`: the token each placeholder was parsed as, and the placeholder
+    kept: Option<Vec<(String, String)>>,
 }
 
 impl<'a> Parser<'a> {
-    fn new(input: &'a str) -> Self {
-        Self { input, pos: 0 }
+    fn new(input: &'a str, params: &'a QueryParams) -> Self {
+        Self {
+            input,
+            params,
+            pos: 0,
+            arg_depth: 0,
+            named_fields: false,
+            limits: QueryLimits::default(),
+            depth: 0,
+            terms: 0,
+            kept: None,
+        }
+    }
+
+    fn parse_query(&mut self) -> Result<AstNode> {
+        if self.input.trim().is_empty() {
+            return Err(Error::syntax(0, "Query is empty"));
+        }
+        let ast = self.parse_expr()?;
+
+        self.consume_whitespace();
+        if self.peek().is_some() {
+            syntax_error!(self.pos, "Unexpected trailing tokens");
+        }
+        Ok(ast)
+    }
+
+    // Called on entering a nested expression; `leave` must follow once it is parsed.
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            syntax_error!(
+                self.pos,
+                "Query nests too deeply (limit {})",
+                self.limits.max_depth
+            );
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
     }
 
     fn parse_expr(&mut self) -> Result<AstNode> {
-        let mut node = self.parse_and()?;
+        self.enter()?;
+        let node = self.parse_or()?;
+        self.leave();
+        Ok(node)
+    }
+
+    // Chains are built balanced rather than left-deep, so the recursive code that walks syntax
+    // trees (`Drop`, `Display`, ...) copes with as many terms as `QueryLimits` allows.
+    fn parse_or(&mut self) -> Result<AstNode> {
+        let mut operands = vec![self.parse_and()?];
 
         loop {
             self.consume_whitespace();
@@ -41,15 +328,14 @@ impl<'a> Parser<'a> {
             }
 
             self.consume_whitespace();
-            let rhs = self.parse_and()?;
-            node = AstNode::OrNode(Box::new(node), Box::new(rhs));
+            operands.push(self.parse_and()?);
         }
 
-        Ok(node)
+        Ok(AstNode::or_chain(operands).expect("a chain has an operand"))
     }
 
     fn parse_and(&mut self) -> Result<AstNode> {
-        let mut node = self.parse_not()?;
+        let mut operands = vec![self.parse_not()?];
 
         loop {
             self.consume_whitespace();
@@ -58,18 +344,19 @@ impl<'a> Parser<'a> {
             }
 
             self.consume_whitespace();
-            let rhs = self.parse_not()?;
-            node = AstNode::AndNode(Box::new(node), Box::new(rhs));
+            operands.push(self.parse_not()?);
         }
 
-        Ok(node)
+        Ok(AstNode::and_chain(operands).expect("a chain has an operand"))
     }
 
     fn parse_not(&mut self) -> Result<AstNode> {
         self.consume_whitespace();
 
         if self.consume_char('!') {
+            self.enter()?;
             let node = self.parse_not()?;
+            self.leave();
             Ok(AstNode::NotNode(Box::new(node)))
         } else {
             self.parse_primary()
@@ -82,7 +369,7 @@ impl<'a> Parser<'a> {
         if self.consume_char('(') {
             let expr = self.parse_expr()?;
             if !self.consume_char(')') {
-                bail!("Unmatched parenthesis: pos={}", self.pos);
+                syntax_error!(self.pos, "Unmatched parenthesis");
             }
             Ok(expr)
         } else {
@@ -92,10 +379,54 @@ impl<'a> Parser<'a> {
 
     fn parse_term(&mut self) -> Result<AstNode> {
         self.consume_whitespace();
+        self.terms += 1;
+        if self.terms > self.limits.max_terms {
+            syntax_error!(
+                self.pos,
+                "Query has too many terms (limit {})",
+                self.limits.max_terms
+            );
+        }
+
+        if self.peek_is('"') {
+            let pattern = self.parse_quoted()?;
+            if pattern.is_empty() {
+                syntax_error!(self.pos, "Empty pattern");
+            }
+            return Ok(AstNode::Match(Pattern::Literal(pattern)));
+        }
+        if let Some(node) = self.parse_profile()? {
+            return Ok(node);
+        }
+        if let Some(node) = self.parse_function()? {
+            return Ok(node);
+        }
+        if let Some(pattern) = self.parse_comparison()? {
+            return Ok(AstNode::Match(pattern));
+        }
+        let start = self.pos;
+        if let Some(value) = self.parse_placeholder()? {
+            let end = self.pos;
+            self.consume_whitespace();
+            match self.peek() {
+                None | Some('&' | '|' | ')') => {}
+                Some(',') if self.arg_depth > 0 => {}
+                Some(_) => syntax_error!(
+                    self.pos,
+                    "Expected an operator after '{}'",
+                    &self.input[start..end]
+                ),
+            }
+            if value.is_empty() {
+                syntax_error!(start, "Empty value for '{}'", &self.input[start..end]);
+            }
+            return Ok(AstNode::Match(Pattern::Literal(value)));
+        }
+
         let mut pattern = String::new();
 
         while let Some(c) = self.peek() {
-            if c == '&' || c == '|' || c == ')' {
+            if c == '&' || c == '|' || c == ')' || (c == ',' && self.arg_depth > 0) {
                 break;
             }
             if c.is_whitespace() {
@@ -107,13 +438,539 @@ impl<'a> Parser<'a> {
         }
 
         if pattern.is_empty() {
-            bail!("Empty pattern: pos={}", self.pos);
+            syntax_error!(self.pos, "Empty pattern");
         }
 
         // Currently only supports Literal (reserved for future extension)
         Ok(AstNode::Match(Pattern::Literal(pattern)))
     }
 
+    // Parses a double-quoted string. Whitespace and operators are kept as-is; `\"` and `\\` are escapes.
+    fn parse_quoted(&mut self) -> Result<String> {
+        let start = self.pos;
+        self.advance();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.advance();
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    self.advance();
+                    match self.peek() {
+                        Some(c) => {
+                            value.push(c);
+                            self.advance();
+                        }
+                        None => break,
+                    }
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.advance();
+                }
+                None => break,
+            }
+        }
+        syntax_error!(start, "Unterminated string")
+    }
+
+    // Parses `$name` or `${VAR}` into its value. Returns `None` (without consuming input) for
+    // anything else, such as the field `$3`.
+    fn parse_placeholder(&mut self) -> Result<Option<String>> {
+        let start = self.pos;
+        let Some(rest) = self.input[start..].strip_prefix('$') else {
+            return Ok(None);
+        };
+        let value = if let Some(body) = rest.strip_prefix('{') {
+            let Some(end) = body.find('}') else {
+                syntax_error!(start, "Unterminated '${{'");
+            };
+            let name = &body[..end];
+            if !is_placeholder_name(name) {
+                syntax_error!(start, "Expected a variable name in ${{...}}");
+            }
+            self.pos = start + "${".len() + end + 1;
+            self.params.var(name)
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..len];
+            if !is_placeholder_name(name) {
+                return Ok(None);
+            }
+            self.pos = start + "$".len() + len;
+            self.params.param(name)
+        };
+        if let Some(kept) = &mut self.kept {
+            // A run of a private-use character absent from the query, as long as the placeholder
+            // so that line widths don't change
+            let placeholder = &self.input[start..self.pos];
+            let Some(c) = ('\u{e000}'..='\u{f8ff}')
+                .filter(|&c| !self.input.contains(c))
+                .nth(kept.len())
+            else {
+                syntax_error!(start, "Too many placeholders");
+            };
+            let token = c.to_string().repeat(placeholder.len());
+            kept.push((token.clone(), placeholder.to_string()));
+            return Ok(Some(token));
+        }
+        value
+            .map(Some)
+            .map_err(|message| Error::syntax(start, message))
+    }
+
+    // Parses a built-in function call such as `between("BEGIN", "END")`.
+    // Returns `None` (without consuming input) for any other term.
+    fn parse_function(&mut self) -> Result<Option<AstNode>> {
+        let start = self.pos;
+        let name = self.parse_ident();
+        if !matches!(
+            name.as_deref(),
+            Some("between" | "has" | "sample" | "limit")
+        ) || !self.consume_char('(')
+        {
+            self.pos = start;
+            return Ok(None);
+        }
+
+        if name.as_deref() == Some("has") {
+            self.consume_whitespace();
+            let pos = self.pos;
+            let key = match self.parse_key()? {
+                Some(key @ (Key::Path(_) | Key::Column(_) | Key::Index(_) | Key::Name(_))) => key,
+                _ => syntax_error!(pos, "Expected a field in has(...)"),
+            };
+            self.consume_whitespace();
+            if !self.consume_char(')') {
+                syntax_error!(self.pos, "Unmatched parenthesis");
+            }
+            return Ok(Some(AstNode::Match(Pattern::Has(key))));
+        }
+
+        if let Some(name @ ("sample" | "limit")) = name.as_deref() {
+            self.consume_whitespace();
+            let pos = self.pos;
+            let arg = self.parse_arg_token();
+            let thinning = match name {
+                "sample" => parse_sample_rate(arg).map(Thinning::Sample),
+                _ => parse_limit_rate(arg),
+            };
+            let Some(thinning) = thinning else {
+                match name {
+                    "sample" => syntax_error!(pos, "Expected a rate such as 0.01 or 1%"),
+                    _ => syntax_error!(pos, "Expected a rate such as 10/s or 100/5m"),
+                }
+            };
+            self.arg_depth += 1;
+            self.expect_arg_separator()?;
+            let inner = self.parse_expr()?;
+            self.consume_whitespace();
+            if !self.consume_char(')') {
+                syntax_error!(self.pos, "Unmatched parenthesis");
+            }
+            self.arg_depth -= 1;
+            return Ok(Some(AstNode::Thin(Box::new(inner), thinning)));
+        }
+
+        self.arg_depth += 1;
+        let block_start = self.parse_expr()?;
+        self.expect_arg_separator()?;
+        let block_end = self.parse_expr()?;
+        let mut bounds = BlockBounds::Inclusive;
+        self.consume_whitespace();
+        if self.consume_char(',') {
+            self.consume_whitespace();
+            let pos = self.pos;
+            bounds = match self.parse_ident().as_deref() {
+                Some("inclusive") => BlockBounds::Inclusive,
+                Some("exclusive") => BlockBounds::Exclusive,
+                _ => syntax_error!(pos, "Expected 'inclusive' or 'exclusive'"),
+            };
+            self.consume_whitespace();
+        }
+        if !self.consume_char(')') {
+            syntax_error!(self.pos, "Unmatched parenthesis");
+        }
+        self.arg_depth -= 1;
+
+        Ok(Some(AstNode::Between(
+            Box::new(block_start),
+            Box::new(block_end),
+            bounds,
+        )))
+    }
+
+    // Parses `@profile(name)`. Returns `None` (without consuming input) for any other term.
+    fn parse_profile(&mut self) -> Result<Option<AstNode>> {
+        const PREFIX: &str = "@profile(";
+        if !self.input[self.pos..].starts_with(PREFIX) {
+            return Ok(None);
+        }
+        self.pos += PREFIX.len();
+        self.consume_whitespace();
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            self.advance();
+        }
+        if start == self.pos {
+            syntax_error!(start, "Expected a profile name in @profile(...)");
+        }
+        let name = self.input[start..self.pos].to_string();
+        self.consume_whitespace();
+        if !self.consume_char(')') {
+            syntax_error!(self.pos, "Unmatched parenthesis");
+        }
+        Ok(Some(AstNode::Profile(name)))
+    }
+
+    // Reads a bare function argument up to whitespace, ',' or ')'.
+    fn parse_arg_token(&mut self) -> &'a str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !(c.is_whitespace() || c == ',' || c == ')'))
+        {
+            self.advance();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn expect_arg_separator(&mut self) -> Result<()> {
+        self.consume_whitespace();
+        if !self.consume_char(',') {
+            syntax_error!(self.pos, "Expected ',' between arguments");
+        }
+        Ok(())
+    }
+
+    // Parses `KEY OP VALUE` or `KEY in RANGE`.
+    // Returns `None` (without consuming input) if the term is not a comparison, so it is read as a literal.
+    fn parse_comparison(&mut self) -> Result<Option<Pattern>> {
+        let start = self.pos;
+        let key = match self.parse_key()? {
+            Some(key) => key,
+            None => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+
+        let key_end = self.pos;
+        self.consume_whitespace();
+        let op_pos = self.pos;
+        if let Some(op) = self.parse_compare_op() {
+            let op_end = self.pos;
+            self.consume_whitespace();
+            let value_pos = self.pos;
+            // A single word such as `line=foo` or `time~x` was a literal before line numbers and
+            // times could be compared, so it stays one unless it is a valid comparison
+            if matches!(key, Key::Line | Key::Time)
+                && op_pos == key_end
+                && value_pos == op_end
+                && !self.peek().is_some_and(|c| c == '"' || c == '$')
+            {
+                let end = self.input[value_pos..]
+                    .find(is_value_end)
+                    .map_or(self.input.len(), |i| value_pos + i);
+                let value = &self.input[value_pos..end];
+                let valid = match key {
+                    _ if op == CompareOp::Contains => false,
+                    Key::Line => value.parse::<usize>().is_ok(),
+                    _ => TimeBound::parse(value, Zone::Utc).is_some(),
+                };
+                if !valid {
+                    self.pos = start;
+                    return Ok(None);
+                }
+            }
+            if op == CompareOp::Contains && matches!(key, Key::Line | Key::Time) {
+                syntax_error!(op_pos, "'~' is not supported for line numbers or times");
+            }
+            let value = self.parse_value(&key)?;
+            if matches!(
+                op,
+                CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge
+            ) && !self.is_kept(&value)
+            {
+                check_ordered(&key, &value, value_pos)?;
+            }
+            return Ok(Some(Pattern::Compare(key, op, value)));
+        }
+        if self.consume_keyword("in") {
+            self.consume_whitespace();
+            let range_pos = self.pos;
+            let (lower, upper) = self.parse_range(&key)?;
+            if let (
+                Bound::Included(a) | Bound::Excluded(a),
+                Bound::Included(b) | Bound::Excluded(b),
+            ) = (&lower, &upper)
+            {
+                let same = self.is_kept(a)
+                    || self.is_kept(b)
+                    || match key {
+                        Key::Time => {
+                            let bound = |v: &str| TimeBound::parse(v, Zone::Utc);
+                            matches!((bound(a), bound(b)), (Some(a), Some(b)) if a.same_kind(&b))
+                        }
+                        _ => {
+                            let unit = |v: &str| parse_scalar(v).map(|(unit, _)| unit);
+                            unit(a) == unit(b)
+                        }
+                    };
+                if !same {
+                    syntax_error!(range_pos, "Range bounds must use the same unit");
+                }
+            }
+            return Ok(Some(Pattern::InRange(key, lower, upper)));
+        }
+
+        self.pos = start;
+        Ok(None)
+    }
+
+    fn parse_key(&mut self) -> Result<Option<Key>> {
+        if self.peek_is('.') {
+            return self.parse_path();
+        }
+        if self.consume_char('$') {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.advance();
+            }
+            return Ok(self.input[start..self.pos].parse().ok().map(Key::Index));
+        }
+        let key = match self.parse_ident().as_deref() {
+            Some("line") => Some(Key::Line),
+            Some("time") => Some(Key::Time),
+            Some("col") if self.consume_char('(') => {
+                self.consume_whitespace();
+                let pos = self.pos;
+                let name = if self.peek_is('"') {
+                    self.parse_quoted()?
+                } else {
+                    self.parse_ident().unwrap_or_default()
+                };
+                self.consume_whitespace();
+                if name.is_empty() || !self.consume_char(')') {
+                    syntax_error!(pos, "Expected a column name in col(...)");
+                }
+                Some(Key::Column(name))
+            }
+            Some(ident) if self.named_fields => {
+                // Names such as `http.status` or `user-agent` are common in logfmt
+                let mut name = ident.to_string();
+                while let Some(c) = self
+                    .peek()
+                    .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+                {
+                    name.push(c);
+                    self.advance();
+                }
+                Some(Key::Name(name))
+            }
+            _ => None,
+        };
+        Ok(key)
+    }
+
+    // Parses a JSON path such as `.http.status`, `.items.0` or `."key with spaces"`.
+    fn parse_path(&mut self) -> Result<Option<Key>> {
+        let mut path = Vec::new();
+        while self.consume_char('.') {
+            let segment = if self.peek_is('"') {
+                self.parse_quoted()?
+            } else {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.advance();
+                }
+                self.input[start..self.pos].to_string()
+            };
+            if segment.is_empty() {
+                return Ok(None);
+            }
+            path.push(segment);
+        }
+        Ok(Some(Key::Path(path)))
+    }
+
+    fn parse_ident(&mut self) -> Option<String> {
+        let mut ident = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            ident.push(c);
+            self.advance();
+        }
+        if ident.is_empty() {
+            None
+        } else {
+            Some(ident)
+        }
+    }
+
+    fn parse_compare_op(&mut self) -> Option<CompareOp> {
+        let ops = [
+            ("<=", CompareOp::Le),
+            (">=", CompareOp::Ge),
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::Ne),
+            ("<", CompareOp::Lt),
+            (">", CompareOp::Gt),
+            ("=", CompareOp::Eq),
+            ("~", CompareOp::Contains),
+        ];
+        for (token, op) in ops {
+            if self.input[self.pos..].starts_with(token) {
+                self.pos += token.len();
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    // Consumes `keyword` only if it is followed by whitespace or a range bracket.
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        let rest = &self.input[self.pos..];
+        if !rest.starts_with(keyword) {
+            return false;
+        }
+        match rest[keyword.len()..].chars().next() {
+            Some(c) if c.is_whitespace() || c == '[' || c == '(' => {
+                self.pos += keyword.len();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Parses `a..b`, `a..=b`, `a..`, `..b` or interval notation such as `[a, b)`. Bounds must be numeric.
+    fn parse_range(&mut self, key: &Key) -> Result<(Bound<String>, Bound<String>)> {
+        if self.peek_is('[') || self.peek_is('(') {
+            let lower_inclusive = self.consume_char('[');
+            if !lower_inclusive {
+                self.advance();
+            }
+            self.consume_whitespace();
+            let lower = self.parse_bound(key)?;
+            self.consume_whitespace();
+            if !self.consume_char(',') {
+                syntax_error!(self.pos, "Expected ',' in range");
+            }
+            self.consume_whitespace();
+            let upper = self.parse_bound(key)?;
+            self.consume_whitespace();
+            let upper_inclusive = if self.consume_char(']') {
+                true
+            } else if self.consume_char(')') {
+                false
+            } else {
+                syntax_error!(self.pos, "Expected ']' or ')' to close range");
+            };
+            let lower = if lower_inclusive {
+                Bound::Included(lower)
+            } else {
+                Bound::Excluded(lower)
+            };
+            let upper = if upper_inclusive {
+                Bound::Included(upper)
+            } else {
+                Bound::Excluded(upper)
+            };
+            return Ok((lower, upper));
+        }
+
+        let lower = if self.input[self.pos..].starts_with("..") {
+            Bound::Unbounded
+        } else {
+            Bound::Included(self.parse_bound(key)?)
+        };
+        if !self.input[self.pos..].starts_with("..") {
+            syntax_error!(self.pos, "Expected a range such as 'a..b'");
+        }
+        self.pos += 2;
+        let inclusive = self.consume_char('=');
+        let upper = match self.peek() {
+            Some(c) if !is_value_end(c) => {
+                let value = self.parse_bound(key)?;
+                if inclusive {
+                    Bound::Included(value)
+                } else {
+                    Bound::Excluded(value)
+                }
+            }
+            _ if inclusive => syntax_error!(self.pos, "Expected an upper bound after '..='"),
+            _ => Bound::Unbounded,
+        };
+        if lower == Bound::Unbounded && upper == Bound::Unbounded {
+            syntax_error!(self.pos, "Range needs at least one bound");
+        }
+        Ok((lower, upper))
+    }
+
+    fn parse_value(&mut self, key: &Key) -> Result<String> {
+        let start = self.pos;
+        if let Some(value) = self.parse_placeholder()? {
+            if self.is_kept(&value) {
+                return Ok(value);
+            }
+            return self.check_value(key, &value, start);
+        }
+        if self.peek_is('"') {
+            let value = self.parse_quoted()?;
+            return self.check_value(key, &value, start);
+        }
+        while let Some(c) = self.peek() {
+            if is_value_end(c) || self.input[self.pos..].starts_with("..") {
+                break;
+            }
+            self.advance();
+        }
+        self.check_value(key, &self.input[start..self.pos], start)
+    }
+
+    fn check_value(&self, key: &Key, value: &str, pos: usize) -> Result<String> {
+        if value.is_empty() {
+            syntax_error!(pos, "Missing comparison value");
+        }
+        match key {
+            Key::Line if value.parse::<usize>().is_err() => {
+                syntax_error!(pos, "Invalid line number '{}'", value)
+            }
+            // The zone only shifts the result, so validating in UTC is enough here
+            Key::Time if TimeBound::parse(value, Zone::Utc).is_none() => {
+                syntax_error!(pos, "Invalid time '{}'", value)
+            }
+            _ => Ok(value.to_string()),
+        }
+    }
+
+    // Like `parse_value`, but the value must be typed (used for range bounds).
+    fn parse_bound(&mut self, key: &Key) -> Result<String> {
+        let pos = self.pos;
+        let value = self.parse_value(key)?;
+        if !self.is_kept(&value) {
+            check_ordered(key, &value, pos)?;
+        }
+        Ok(value)
+    }
+
+    // Whether `value` is a placeholder kept for `print_template`.
+    fn is_kept(&self, value: &str) -> bool {
+        self.kept.iter().flatten().any(|(token, _)| token == value)
+    }
+
     fn peek(&self) -> Option<char> {
         self.input[self.pos..].chars().next()
     }
@@ -147,6 +1004,51 @@ impl<'a> Parser<'a> {
     }
 }
 
+// `0.01` or `1%`; must be in (0, 1].
+fn parse_sample_rate(text: &str) -> Option<f64> {
+    let rate = match text.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok()? / 100.0,
+        None => text.parse::<f64>().ok()?,
+    };
+    (rate > 0.0 && rate <= 1.0).then_some(rate)
+}
+
+// `COUNT/PERIOD`, where the period is a duration and a bare unit means one of it (`10/s`, `100/5m`).
+fn parse_limit_rate(text: &str) -> Option<Thinning> {
+    let (count, period) = text.split_once('/')?;
+    let count = count.parse::<u64>().ok().filter(|&n| n > 0)?;
+    let seconds = parse_as(period, Unit::Duration)
+        .or_else(|| parse_as(&format!("1{period}"), Unit::Duration))
+        .filter(|&s| s > 0.0)?;
+    Some(Thinning::Limit {
+        count,
+        per: Duration::from_secs_f64(seconds),
+    })
+}
+
+// Ordering comparisons and ranges need a number, duration (`100ms`), size (`2MiB`) or severity (`warning`).
+// Times and line numbers are validated by `check_value`.
+fn check_ordered(key: &Key, value: &str, pos: usize) -> Result<()> {
+    if !matches!(key, Key::Time) && parse_scalar(value).is_none() {
+        syntax_error!(
+            pos,
+            "Expected a number, duration, size or severity, found '{}'",
+            value
+        );
+    }
+    Ok(())
+}
+
+// `svc` in `$svc`, or `HOME` in `${HOME}`: not starting with a digit, so `$3` stays a field.
+fn is_placeholder_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_value_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '&' | '|' | ')' | ']' | ',')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -264,4 +1166,432 @@ mod tests {
             _ => panic!("Expected an AndNode at the top level"),
         }
     }
+
+    #[test]
+    fn test_line_comparison() {
+        let ast = parse_query("line<=20").unwrap();
+        assert!(matches!(
+            ast,
+            AstNode::Match(Pattern::Compare(Key::Line, CompareOp::Le, ref v)) if v == "20"
+        ));
+    }
+
+    #[test]
+    fn test_line_range() {
+        let ast = parse_query("line in 1000..2000 | line in [5, 9]").unwrap();
+        match ast {
+            AstNode::OrNode(lhs, rhs) => {
+                assert!(matches!(
+                    *lhs,
+                    AstNode::Match(Pattern::InRange(Key::Line, Bound::Included(ref a), Bound::Excluded(ref b)))
+                        if a == "1000" && b == "2000"
+                ));
+                assert!(matches!(
+                    *rhs,
+                    AstNode::Match(Pattern::InRange(Key::Line, Bound::Included(ref a), Bound::Included(ref b)))
+                        if a == "5" && b == "9"
+                ));
+            }
+            _ => panic!("Expected an OrNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_line_without_operator_is_literal() {
+        let ast = parse_query("line & lines").unwrap();
+        assert_eq!(
+            format!("{:?}", ast),
+            format!(
+                "{:?}",
+                AstNode::AndNode(Box::new(literal("line")), Box::new(literal("lines")))
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_line_number() {
+        let err = parse_query("line <= abc").unwrap_err();
+        assert!(err.to_string().contains("Invalid line number"));
+
+        // Written as one word, it is a literal as before
+        assert_eq!(parse_query("line<=abc").unwrap(), literal("line<=abc"));
+        assert_eq!(
+            parse_query("line=foo | line=3").unwrap(),
+            AstNode::OrNode(
+                Box::new(literal("line=foo")),
+                Box::new(AstNode::Match(Pattern::Compare(
+                    Key::Line,
+                    CompareOp::Eq,
+                    "3".into()
+                )))
+            )
+        );
+        assert_eq!(parse_query("line~foo").unwrap(), literal("line~foo"));
+        assert_eq!(parse_query("line~3").unwrap(), literal("line~3"));
+        let err = parse_query("line ~ foo").unwrap_err();
+        assert!(err.to_string().contains("'~' is not supported"));
+    }
+
+    #[test]
+    fn test_invalid_time_is_a_literal_as_one_word() {
+        for query in ["time=foo", "time<now", "time~10:00"] {
+            assert_eq!(parse_query(query).unwrap(), literal(query));
+        }
+        let err = parse_query("time = foo").unwrap_err();
+        assert!(err.to_string().contains("Invalid time 'foo'"));
+        assert!(matches!(
+            parse_query("time>=10:00").unwrap(),
+            AstNode::Match(Pattern::Compare(Key::Time, CompareOp::Ge, _))
+        ));
+    }
+
+    #[test]
+    fn test_quoted_literal_keeps_spaces_and_operators() {
+        let ast = parse_query(r#""BEGIN CERT" | "a & \"b\"""#).unwrap();
+        assert_eq!(
+            format!("{:?}", ast),
+            format!(
+                "{:?}",
+                AstNode::OrNode(
+                    Box::new(literal("BEGIN CERT")),
+                    Box::new(literal("a & \"b\""))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let err = parse_query(r#""abc"#).unwrap_err();
+        assert!(err.to_string().contains("Unterminated string"));
+    }
+
+    #[test]
+    fn test_between() {
+        let ast = parse_query(r#"between("BEGIN CERT", END, exclusive) & x"#).unwrap();
+        match ast {
+            AstNode::AndNode(lhs, _) => match *lhs {
+                AstNode::Between(start, end, BlockBounds::Exclusive) => {
+                    assert_eq!(
+                        format!("{:?}", *start),
+                        format!("{:?}", literal("BEGIN CERT"))
+                    );
+                    assert_eq!(format!("{:?}", *end), format!("{:?}", literal("END")));
+                }
+                _ => panic!("Left-hand side is not an exclusive Between"),
+            },
+            _ => panic!("Expected an AndNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_between_requires_two_arguments() {
+        let err = parse_query("between(BEGIN)").unwrap_err();
+        assert!(err.to_string().contains("Expected ','"));
+    }
+
+    #[test]
+    fn test_field_comparisons() {
+        let ast = parse_query(r#"$3 ~ "error" & col(status) = 500"#).unwrap();
+        match ast {
+            AstNode::AndNode(lhs, rhs) => {
+                assert!(matches!(
+                    *lhs,
+                    AstNode::Match(Pattern::Compare(Key::Index(3), CompareOp::Contains, ref v)) if v == "error"
+                ));
+                assert!(matches!(
+                    *rhs,
+                    AstNode::Match(Pattern::Compare(Key::Column(ref c), CompareOp::Eq, ref v))
+                        if c == "status" && v == "500"
+                ));
+            }
+            _ => panic!("Expected an AndNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_dollar_without_operator_is_literal() {
+        let ast = parse_query("$5").unwrap();
+        assert_eq!(format!("{:?}", ast), format!("{:?}", literal("$5")));
+    }
+
+    #[test]
+    fn test_col_requires_name() {
+        let err = parse_query("col() = x").unwrap_err();
+        assert!(err.to_string().contains("Expected a column name"));
+    }
+
+    #[test]
+    fn test_json_path_comparison() {
+        let ast = parse_query(r#".http.status >= 500 & !has(.trace_id)"#).unwrap();
+        match ast {
+            AstNode::AndNode(lhs, rhs) => {
+                assert!(matches!(
+                    *lhs,
+                    AstNode::Match(Pattern::Compare(Key::Path(ref p), CompareOp::Ge, ref v))
+                        if p == &["http", "status"] && v == "500"
+                ));
+                match *rhs {
+                    AstNode::NotNode(inner) => assert!(matches!(
+                        *inner,
+                        AstNode::Match(Pattern::Has(Key::Path(ref p))) if p == &["trace_id"]
+                    )),
+                    _ => panic!("Right-hand side is not a NotNode"),
+                }
+            }
+            _ => panic!("Expected an AndNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_path_without_operator_is_literal() {
+        let ast = parse_query(".log").unwrap();
+        assert_eq!(format!("{:?}", ast), format!("{:?}", literal(".log")));
+    }
+
+    #[test]
+    fn test_ordering_requires_number() {
+        let err = parse_query(".level > verbose").unwrap_err();
+        assert!(err.to_string().contains("Expected a number"));
+    }
+
+    #[test]
+    fn test_typed_comparison_values() {
+        let ast = parse_query_for("dur>100ms & size in 1KB..2MiB", Format::Logfmt).unwrap();
+        assert!(matches!(ast, AstNode::AndNode(_, _)));
+
+        let err = parse_query_for("dur in 1s..2KB", Format::Logfmt).unwrap_err();
+        assert!(err.to_string().contains("same unit"));
+    }
+
+    #[test]
+    fn test_named_fields_in_structured_format() {
+        let ast = parse_query_for("level=debug & !has(user)", Format::Logfmt).unwrap();
+        match ast {
+            AstNode::AndNode(lhs, rhs) => {
+                assert!(matches!(
+                    *lhs,
+                    AstNode::Match(Pattern::Compare(Key::Name(ref k), CompareOp::Eq, ref v))
+                        if k == "level" && v == "debug"
+                ));
+                assert!(matches!(*rhs, AstNode::NotNode(_)));
+            }
+            _ => panic!("Expected an AndNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_named_comparison_is_literal_in_plain_text() {
+        let ast = parse_query("level=debug").unwrap();
+        assert_eq!(
+            format!("{:?}", ast),
+            format!("{:?}", literal("level=debug"))
+        );
+    }
+
+    #[test]
+    fn test_bare_word_stays_literal_in_structured_format() {
+        let ast = parse_query_for("timeout", Format::Logfmt).unwrap();
+        assert_eq!(format!("{:?}", ast), format!("{:?}", literal("timeout")));
+    }
+
+    #[test]
+    fn test_time_window() {
+        let ast = parse_query("time in [14:00, 14:30) | time >= 2024-05-01T00:00:00Z").unwrap();
+        match ast {
+            AstNode::OrNode(lhs, rhs) => {
+                assert!(matches!(
+                    *lhs,
+                    AstNode::Match(Pattern::InRange(Key::Time, _, _))
+                ));
+                assert!(matches!(
+                    *rhs,
+                    AstNode::Match(Pattern::Compare(Key::Time, CompareOp::Ge, _))
+                ));
+            }
+            _ => panic!("Expected an OrNode at the top level"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        let err = parse_query("time > noon").unwrap_err();
+        assert!(err.to_string().contains("Invalid time"));
+
+        let err = parse_query("time in [14:00, 2024-05-01)").unwrap_err();
+        assert!(err.to_string().contains("same unit"));
+    }
+
+    #[test]
+    fn test_sample_and_limit() {
+        let ast = parse_query(r#"sample(1%, "cache miss") | limit(10/s, retry)"#).unwrap();
+        match ast {
+            AstNode::OrNode(lhs, rhs) => {
+                assert!(matches!(*lhs, AstNode::Thin(_, Thinning::Sample(r)) if r == 0.01));
+                assert!(matches!(
+                    *rhs,
+                    AstNode::Thin(_, Thinning::Limit { count: 10, per }) if per == Duration::from_secs(1)
+                ));
+            }
+            _ => panic!("Expected an OrNode at the top level"),
+        }
+
+        let ast = parse_query("limit(100/5m, a & b)").unwrap();
+        assert!(matches!(
+            ast,
+            AstNode::Thin(_, Thinning::Limit { count: 100, per }) if per == Duration::from_secs(300)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        let err = parse_query("sample(2, x)").unwrap_err();
+        assert!(err.to_string().contains("Expected a rate such as 0.01"));
+
+        let err = parse_query("limit(10, x)").unwrap_err();
+        assert!(err.to_string().contains("Expected a rate such as 10/s"));
+
+        let err = parse_query("limit(0/s, x)").unwrap_err();
+        assert!(err.to_string().contains("Expected a rate"));
+    }
+
+    #[test]
+    fn test_query_limits() {
+        let limits = QueryLimits {
+            max_depth: 4,
+            max_terms: 3,
+        };
+        assert!(parse_query_with("((a)) & !b", Format::Plain, limits).is_ok());
+
+        let err = parse_query_with("((((a))))", Format::Plain, limits).unwrap_err();
+        assert_eq!(err.to_string(), "Query nests too deeply (limit 4): pos=4");
+        let err = parse_query_with("!!!!a", Format::Plain, limits).unwrap_err();
+        assert!(err.to_string().contains("nests too deeply"));
+
+        let err = parse_query_with("a | b | c | d", Format::Plain, limits).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Query has too many terms (limit 3): pos=12"
+        );
+    }
+
+    #[test]
+    fn test_deep_query_is_rejected_by_default() {
+        let query = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        let err = parse_query(&query).unwrap_err();
+        assert!(err.to_string().contains("nests too deeply (limit 256)"));
+    }
+
+    #[test]
+    fn test_profile_reference() {
+        let ast = parse_query("@profile(k8s-noise) | heartbeat").unwrap();
+        assert_eq!(
+            ast,
+            AstNode::OrNode(
+                Box::new(AstNode::Profile("k8s-noise".to_string())),
+                Box::new(AstNode::Match(Pattern::Literal("heartbeat".to_string())))
+            )
+        );
+        assert_eq!(ast.profiles(), ["k8s-noise"]);
+
+        // Other words with `@` stay literals
+        let ast = parse_query("user@example.com").unwrap();
+        assert!(matches!(ast, AstNode::Match(Pattern::Literal(s)) if s == "user@example.com"));
+
+        let err = parse_query("@profile()").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected a profile name in @profile(...): pos=9"
+        );
+        let err = parse_query("@profile(a b)").unwrap_err();
+        assert!(err.to_string().contains("Unmatched parenthesis"));
+    }
+
+    fn parse_params(query: &str) -> Result<AstNode> {
+        let params = QueryParams::new()
+            .with_param("svc", "api & (web")
+            .with_param("min", "500")
+            .with_env([("STAGE", "prod"), ("EMPTY", "")]);
+        parse_query_with_params(query, Format::Logfmt, QueryLimits::default(), &params)
+    }
+
+    #[test]
+    fn test_placeholders() {
+        // Values are literals, whatever they contain
+        let ast = parse_params("$svc & ${STAGE} | status >= $min").unwrap();
+        assert_eq!(
+            ast,
+            AstNode::OrNode(
+                Box::new(AstNode::AndNode(
+                    Box::new(literal("api & (web")),
+                    Box::new(literal("prod"))
+                )),
+                Box::new(AstNode::Match(Pattern::Compare(
+                    Key::Name("status".to_string()),
+                    CompareOp::Ge,
+                    "500".to_string()
+                )))
+            )
+        );
+        assert_eq!(ast.to_string(), "\"api & (web\" & prod | status >= 500");
+
+        // Fields, quoted text and `$` inside words are not placeholders
+        assert_eq!(
+            parse_params("$2 ~ x").unwrap(),
+            parse_query("$2 ~ x").unwrap()
+        );
+        assert_eq!(parse_params("\"$svc\"").unwrap(), literal("$svc"));
+        assert_eq!(parse_params("a$svc").unwrap(), literal("a$svc"));
+        assert_eq!(literal("$svc").to_string(), "\"$svc\"");
+    }
+
+    #[test]
+    fn test_unresolved_placeholders() {
+        let message = |query| parse_params(query).unwrap_err().to_string();
+        assert_eq!(
+            message("x | $nope"),
+            "No value for parameter '$nope': pos=4"
+        );
+        assert_eq!(
+            message("x | ${NOPE}"),
+            "Environment variable 'NOPE' is not set: pos=4"
+        );
+        assert_eq!(message("${EMPTY}"), "Empty value for '${EMPTY}': pos=0");
+        assert_eq!(message("${STAGE"), "Unterminated '${': pos=0");
+        assert_eq!(message("${}"), "Expected a variable name in ${...}: pos=0");
+        assert_eq!(
+            message("$svc api"),
+            "Expected an operator after '$svc': pos=5"
+        );
+        assert!(message("line <= $svc").starts_with("Invalid line number 'api & (web'"));
+
+        // Without explicit values, the environment is not read
+        assert_eq!(
+            parse_query("${PATH}").unwrap_err().to_string(),
+            "Environment variable 'PATH' is not available to this query: pos=0"
+        );
+    }
+
+    #[test]
+    fn test_print_template() {
+        let print = |query| {
+            print_template(query, Format::Logfmt, QueryLimits::default(), |ast| {
+                ast.to_string()
+            })
+        };
+        assert_eq!(
+            print("service=$svc&status>=$min|${NOISE}").unwrap(),
+            "service = $svc & status >= $min | ${NOISE}"
+        );
+        assert_eq!(
+            print("line in $from..$to | \"$svc\"").unwrap(),
+            "line in $from..$to | \"$svc\""
+        );
+        // Placeholders in the query's own text are not confused with the tokens
+        assert_eq!(
+            print("\u{e000}\u{e000} | $a").unwrap(),
+            "\u{e000}\u{e000} | $a"
+        );
+        assert!(print("$svc api").is_err());
+    }
 }
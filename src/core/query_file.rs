@@ -0,0 +1,238 @@
+use crate::core::parser::{print_template, QueryLimits};
+use crate::core::record::Format;
+use crate::error::Error;
+use std::path::Path;
+
+// A query read from a file. Lines whose first non-blank character is `#` are comments; a `#`
+// anywhere else is part of the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryFile {
+    text: String,
+    // The file with comment lines blanked out, so byte positions in syntax errors still point
+    // into the file.
+    query: String,
+    comments: Vec<Comment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    // 1-based line number in the file
+    pub line: usize,
+    // The comment after `#`, without surrounding whitespace
+    pub text: String,
+}
+
+// An example line a query file expects the filter to keep or drop: `# keep: ...` / `# drop: ...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    // 1-based line number of the comment
+    pub line: usize,
+    pub keep: bool,
+    pub text: String,
+}
+
+impl QueryFile {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        std::fs::read_to_string(path).map(|text| Self::new(&text))
+    }
+
+    pub fn new(text: &str) -> Self {
+        let mut query = String::with_capacity(text.len());
+        let mut comments = Vec::new();
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            match line.trim_start().strip_prefix('#') {
+                Some(comment) => {
+                    comments.push(Comment {
+                        line: i + 1,
+                        text: comment.trim().to_string(),
+                    });
+                    let body = line.trim_end_matches(['\n', '\r']);
+                    query.extend(std::iter::repeat_n(' ', body.len()));
+                    query.push_str(&line[body.len()..]);
+                }
+                None => query.push_str(line),
+            }
+        }
+        Self {
+            text: text.to_string(),
+            query,
+            comments,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
+    pub fn examples(&self) -> Vec<Example> {
+        self.comments
+            .iter()
+            .filter_map(|c| {
+                let (keep, text) = match c.text.split_once(':')? {
+                    ("keep", text) => (true, text),
+                    ("drop", text) => (false, text),
+                    _ => return None,
+                };
+                Some(Example {
+                    line: c.line,
+                    keep,
+                    text: text.trim().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    // The byte range of the lines the query spans, if the file has a query.
+    fn query_span(&self) -> Option<(usize, usize)> {
+        let first = self.query.find(|c: char| !c.is_whitespace())?;
+        let last = self.query.trim_end().len();
+        let start = self.query[..first].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.query[last..]
+            .find('\n')
+            .map_or(self.query.len(), |i| last + i + 1);
+        Some((start, end))
+    }
+
+    // The file printed canonically: the query with lines wrapped at `width`, and the text around it
+    // as it is. Placeholders are printed as they are.
+    pub fn canonical(
+        &self,
+        format: Format,
+        limits: QueryLimits,
+        width: usize,
+    ) -> crate::Result<String> {
+        let query = print_template(&self.query, format, limits, |ast| ast.pretty(width))?;
+        self.with_query(&query)
+    }
+
+    // The file with its query replaced by `query`, keeping the text before and after it as it is;
+    // a file without a query gets it at the end. Fails if comments sit between lines of the
+    // query, as they would have nowhere to go.
+    pub fn with_query(&self, query: &str) -> crate::Result<String> {
+        let Some((start, end)) = self.query_span() else {
+            let mut out = self.text.clone();
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            return Ok(out + query + "\n");
+        };
+        let first = self.text[..start].matches('\n').count() + 1;
+        let last = first + self.text[start..end].trim_end().matches('\n').count();
+        if let Some(c) = self
+            .comments
+            .iter()
+            .find(|c| (first..last).contains(&c.line))
+        {
+            return Err(Error::Unsupported(format!(
+                "Line {}: a comment inside the query would move; put it above or below the query",
+                c.line
+            )));
+        }
+        Ok(format!(
+            "{}{}\n{}",
+            &self.text[..start],
+            query,
+            &self.text[end..]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_are_blanked() {
+        let file = QueryFile::new("# noise\nDEBUG\n  # health\n| healthcheck\n");
+        assert_eq!(file.query(), "       \nDEBUG\n          \n| healthcheck\n");
+        assert_eq!(
+            file.comments(),
+            [
+                Comment {
+                    line: 1,
+                    text: "noise".into()
+                },
+                Comment {
+                    line: 3,
+                    text: "health".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_inside_query() {
+        let file = QueryFile::new("\"#1\" | a#b\r\n");
+        assert_eq!(file.query(), "\"#1\" | a#b\r\n");
+        assert!(file.comments().is_empty());
+    }
+
+    #[test]
+    fn test_examples() {
+        let file = QueryFile::new("DEBUG\n# keep: ERROR x\n# drop:DEBUG y\n# note: z\n");
+        assert_eq!(
+            file.examples(),
+            [
+                Example {
+                    line: 2,
+                    keep: true,
+                    text: "ERROR x".into()
+                },
+                Example {
+                    line: 3,
+                    keep: false,
+                    text: "DEBUG y".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonical() {
+        let file = QueryFile::new("#head\n\n  ( DEBUG )|\n(INFO &x)  \n\n#  tail  \n");
+        let text = file
+            .canonical(Format::Plain, QueryLimits::default(), 80)
+            .unwrap();
+        assert_eq!(text, "#head\n\nDEBUG | INFO & x\n\n#  tail  \n");
+        assert_eq!(
+            QueryFile::new(&text)
+                .canonical(Format::Plain, QueryLimits::default(), 80)
+                .unwrap(),
+            text
+        );
+        let template = QueryFile::new("$svc|x\n");
+        assert_eq!(
+            template
+                .canonical(Format::Plain, QueryLimits::default(), 80)
+                .unwrap(),
+            "$svc | x\n"
+        );
+    }
+
+    #[test]
+    fn test_with_query() {
+        let file = QueryFile::new("# head\nDEBUG\n| INFO\n# keep: ERROR x\n");
+        assert_eq!(
+            file.with_query("DEBUG | TRACE").unwrap(),
+            "# head\nDEBUG | TRACE\n# keep: ERROR x\n"
+        );
+        assert_eq!(
+            QueryFile::new("# empty").with_query("DEBUG").unwrap(),
+            "# empty\nDEBUG\n"
+        );
+
+        // A comment in the middle of the query has no place in the new one
+        let file = QueryFile::new("DEBUG\n# health checks\n| healthz\n");
+        assert_eq!(
+            file.with_query("DEBUG | healthz").unwrap_err().to_string(),
+            "Line 2: a comment inside the query would move; put it above or below the query"
+        );
+        assert!(file
+            .canonical(Format::Plain, QueryLimits::default(), 80)
+            .is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use crate::core::ast::Key;
+use crate::core::formats::{
+    parse_combined, parse_journal_export, parse_journal_json, parse_logfmt, parse_syslog, Pair,
+};
+use crate::core::time::{extract, TimeOptions};
+use crate::error::{Error, Result};
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell};
+use std::sync::OnceLock;
+
+/// How each line is parsed for field predicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Plain text split into `$N` / `col(name)` fields by the delimiter
+    #[default]
+    Plain,
+    /// One JSON document per line, addressed with paths such as `.http.status`
+    Jsonl,
+    /// `key=value` pairs, addressed by name such as `level`
+    Logfmt,
+    /// RFC 3164 / RFC 5424 syslog (`host`, `program`, `pid`, `severity`, `message`, ...)
+    Syslog,
+    /// Apache/nginx combined or common access log (`method`, `path`, `status`, `bytes`, ...)
+    Combined,
+    /// `journalctl -o json`, one entry per line
+    Journal,
+    /// `journalctl -o export`, entries of `FIELD=value` lines separated by a blank line
+    JournalExport,
+}
+
+impl Format {
+    // Whether bare names (`level=debug`) address fields in this format.
+    pub fn has_named_fields(self) -> bool {
+        !matches!(self, Format::Plain)
+    }
+
+    // Whether a record spans several lines, up to and including a blank line.
+    pub fn is_multiline(self) -> bool {
+        matches!(self, Format::JournalExport)
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "plain" | "text" => Ok(Format::Plain),
+            "jsonl" | "ndjson" => Ok(Format::Jsonl),
+            "logfmt" => Ok(Format::Logfmt),
+            "syslog" | "rfc3164" | "rfc5424" => Ok(Format::Syslog),
+            "combined" | "common" | "apache" | "nginx" => Ok(Format::Combined),
+            "journal" | "journald" | "journal-json" => Ok(Format::Journal),
+            "journal-export" => Ok(Format::JournalExport),
+            _ => Err(Error::InvalidOption(format!("Unknown format '{}' (expected: plain, jsonl, logfmt, syslog, combined, journal, journal-export)",
+                s))),
+        }
+    }
+}
+
+/// What to do with a line that cannot be parsed in the selected format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidPolicy {
+    /// Field predicates are false; literals still match the raw line
+    #[default]
+    Raw,
+    /// Always output the line
+    Keep,
+    /// Always exclude the line
+    Drop,
+}
+
+impl std::str::FromStr for InvalidPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(InvalidPolicy::Raw),
+            "keep" => Ok(InvalidPolicy::Keep),
+            "drop" => Ok(InvalidPolicy::Drop),
+            _ => Err(Error::InvalidOption(format!(
+                "Unknown policy '{}' (expected: raw, keep, drop)",
+                s
+            ))),
+        }
+    }
+}
+
+/// How fields are separated within a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delimiter {
+    /// Runs of whitespace separate fields, like awk
+    #[default]
+    Whitespace,
+    /// A single character separates fields; fields may be quoted as in RFC 4180 (CSV/TSV)
+    Char(char),
+}
+
+impl std::str::FromStr for Delimiter {
+    type Err = Error;
+
+    // Accepts a single character, or one of `whitespace`, `tab`, `\t`, `comma`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "whitespace" | "ws" => Ok(Delimiter::Whitespace),
+            "tab" | "\\t" => Ok(Delimiter::Char('\t')),
+            "comma" => Ok(Delimiter::Char(',')),
+            _ => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(Delimiter::Char(c)),
+                    _ => Err(Error::InvalidOption(format!(
+                        "Delimiter must be a single character: '{}'",
+                        s
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+// Describes how a line is interpreted as a record for field predicates.
+//   `delimiter`   – Separator used to split a line into fields.
+//   `header`      – If `true`, the first line names the columns used by `col(name)`.
+//   `format`      – How lines are parsed for path predicates.
+//   `on_invalid`  – What to do with lines that fail to parse in `format`.
+//   `time`        – How timestamps are found for `time` predicates.
+//   `seed`        – Seed for `sample(...)`; the same seed samples the same lines.
+//   `ignore_case` – If `true`, literals and text comparisons ignore case.
+#[derive(Debug, Default)]
+pub struct RecordOptions {
+    pub delimiter: Delimiter,
+    pub header: bool,
+    pub format: Format,
+    pub on_invalid: InvalidPolicy,
+    pub time: TimeOptions,
+    pub seed: u64,
+    pub ignore_case: bool,
+    columns: OnceLock<Vec<String>>,
+}
+
+impl RecordOptions {
+    pub fn new(delimiter: Delimiter, header: bool) -> Self {
+        Self {
+            delimiter,
+            header,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_format(mut self, format: Format, on_invalid: InvalidPolicy) -> Self {
+        self.format = format;
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    pub fn with_time(mut self, time: TimeOptions) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    // Lowercases `text` if matching ignores case. Patterns are folded once when the query is
+    // compiled; lines and field values as they are compared.
+    pub fn fold<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.ignore_case {
+            Cow::Owned(text.to_lowercase())
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    // Records the column names from the header line. Only the first call has an effect.
+    pub fn set_header(&self, line: &str) {
+        let names = split_fields(trim_newline(line), self.delimiter)
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .collect();
+        let _ = self.columns.set(names);
+    }
+
+    fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.get()?.iter().position(|c| c == name)
+    }
+}
+
+// A line parsed according to a structured `Format`.
+enum Parsed<'a> {
+    Json(Value),
+    Pairs(Vec<Pair<'a>>),
+}
+
+// A single line together with its lazily split fields.
+//
+// Fields are only split (or the line parsed in its format) the first time a field predicate
+// asks for one, so queries made of plain literals never pay for parsing.
+pub struct Record<'a> {
+    pub text: &'a str,
+    options: &'a RecordOptions,
+    fields: OnceCell<Vec<Cow<'a, str>>>,
+    parsed: OnceCell<Option<Parsed<'a>>>,
+    timestamp: OnceCell<Option<DateTime<FixedOffset>>>,
+    folded: OnceCell<String>,
+    invalid: Cell<bool>,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(text: &'a str, options: &'a RecordOptions) -> Self {
+        Self {
+            text,
+            options,
+            fields: OnceCell::new(),
+            parsed: OnceCell::new(),
+            timestamp: OnceCell::new(),
+            folded: OnceCell::new(),
+            invalid: Cell::new(false),
+        }
+    }
+
+    // Returns the text literals are searched in: the line itself, or lowercased if matching ignores case.
+    pub fn haystack(&self) -> &str {
+        if !self.options.ignore_case {
+            return self.text;
+        }
+        self.folded.get_or_init(|| self.text.to_lowercase())
+    }
+
+    // Returns the value selected by `key` as compared with `=` and `~` (folded if matching ignores case).
+    pub fn field_text(&self, key: &Key) -> Option<Cow<'_, str>> {
+        let value = self.field(key)?;
+        Some(match self.options.fold(&value) {
+            Cow::Owned(folded) => Cow::Owned(folded),
+            Cow::Borrowed(_) => value,
+        })
+    }
+
+    // Returns the value selected by `key`, or `None` if the line has no such field.
+    //
+    // JSON strings are returned without quotes; other JSON values are returned as JSON text,
+    // and `null` counts as missing.
+    pub fn field(&self, key: &Key) -> Option<Cow<'_, str>> {
+        let index = match key {
+            Key::Index(0) => return Some(Cow::Borrowed(trim_newline(self.text))),
+            Key::Index(n) => n - 1,
+            Key::Column(name) => self.options.column_index(name)?,
+            Key::Path(path) => return self.lookup(path).and_then(json_text),
+            Key::Name(name) => {
+                return match self.parsed()? {
+                    Parsed::Json(value) => value.get(name).and_then(json_text),
+                    Parsed::Pairs(pairs) => pair_value(pairs, name).map(Cow::Borrowed),
+                }
+            }
+            Key::Line | Key::Time => return None,
+        };
+        self.fields
+            .get_or_init(|| split_fields(trim_newline(self.text), self.options.delimiter))
+            .get(index)
+            .map(|f| Cow::Borrowed(f.as_ref()))
+    }
+
+    // Returns `true` if `key` is present in the record (even when its value is `null` or empty).
+    pub fn has(&self, key: &Key) -> bool {
+        match key {
+            Key::Path(path) => self.lookup(path).is_some(),
+            Key::Name(name) => match self.parsed() {
+                Some(Parsed::Json(value)) => value.get(name).is_some(),
+                Some(Parsed::Pairs(pairs)) => pair_value(pairs, name).is_some(),
+                None => false,
+            },
+            Key::Time => self.timestamp().is_some(),
+            _ => self.field(key).is_some(),
+        }
+    }
+
+    // Returns the first timestamp found in the line, if any.
+    pub fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        *self
+            .timestamp
+            .get_or_init(|| extract(self.text, &self.options.time))
+    }
+
+    // Parses the line in the record's format, if not done yet.
+    // Returns `false` if the line is not valid in that format.
+    pub fn parse(&self) -> bool {
+        self.parsed().is_some()
+    }
+
+    // Returns `true` if parsing was attempted and failed.
+    pub fn is_invalid(&self) -> bool {
+        self.invalid.get()
+    }
+
+    fn parsed(&self) -> Option<&Parsed<'a>> {
+        self.parsed
+            .get_or_init(|| {
+                let text = trim_newline(self.text);
+                let parsed = match self.options.format {
+                    Format::Plain => return None,
+                    Format::Jsonl => serde_json::from_str(text).ok().map(Parsed::Json),
+                    Format::Logfmt => parse_logfmt(text).map(Parsed::Pairs),
+                    Format::Syslog => parse_syslog(text).map(Parsed::Pairs),
+                    Format::Combined => parse_combined(text).map(Parsed::Pairs),
+                    Format::Journal => parse_journal_json(text).map(Parsed::Pairs),
+                    Format::JournalExport => parse_journal_export(text).map(Parsed::Pairs),
+                };
+                self.invalid.set(parsed.is_none());
+                parsed
+            })
+            .as_ref()
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let root = match self.parsed()? {
+            Parsed::Json(value) => value,
+            Parsed::Pairs(_) => return None,
+        };
+        path.iter().try_fold(root, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+    }
+}
+
+fn json_text(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(Cow::Borrowed(s)),
+        other => Some(Cow::Owned(other.to_string())),
+    }
+}
+
+// The last occurrence wins when a key is repeated.
+fn pair_value<'p>(pairs: &'p [Pair<'_>], name: &str) -> Option<&'p str> {
+    pairs
+        .iter()
+        .rev()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.as_ref())
+}
+
+fn trim_newline(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+// Splits a line into fields. With a character delimiter, RFC 4180 quoting is honored:
+// a field starting with `"` runs to the closing quote, and `""` inside it stands for one `"`.
+pub fn split_fields(line: &str, delimiter: Delimiter) -> Vec<Cow<'_, str>> {
+    let delim = match delimiter {
+        Delimiter::Whitespace => return line.split_whitespace().map(Cow::Borrowed).collect(),
+        Delimiter::Char(c) => c,
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                if c != '"' {
+                    value.push(c);
+                } else if quoted[i + 1..].starts_with('"') {
+                    value.push('"');
+                    chars.next();
+                } else {
+                    end = i + 1;
+                    break;
+                }
+            }
+            // Anything between the closing quote and the next delimiter is kept verbatim
+            let after = &quoted[end..];
+            let next = after.find(delim);
+            value.push_str(&after[..next.unwrap_or(after.len())]);
+            fields.push(Cow::Owned(value));
+            match next {
+                Some(n) => rest = &after[n + delim.len_utf8()..],
+                None => break,
+            }
+        } else {
+            match rest.find(delim) {
+                Some(n) => {
+                    fields.push(Cow::Borrowed(&rest[..n]));
+                    rest = &rest[n + delim.len_utf8()..];
+                }
+                None => {
+                    fields.push(Cow::Borrowed(rest));
+                    break;
+                }
+            }
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str, delimiter: Delimiter) -> Vec<String> {
+        split_fields(line, delimiter)
+            .into_iter()
+            .map(|f| f.into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_whitespace() {
+        assert_eq!(
+            split("  a  b\tc ", Delimiter::Whitespace),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_split_csv_with_quotes() {
+        let fields = split(
+            r#"1,"hello, world","say ""hi""",,end"#,
+            Delimiter::Char(','),
+        );
+        assert_eq!(fields, vec!["1", "hello, world", r#"say "hi""#, "", "end"]);
+    }
+
+    #[test]
+    fn test_split_unterminated_quote() {
+        assert_eq!(split(r#"a,"b,c"#, Delimiter::Char(',')), vec!["a", "b,c"]);
+    }
+
+    #[test]
+    fn test_delimiter_from_str() {
+        assert_eq!("tab".parse::<Delimiter>().unwrap(), Delimiter::Char('\t'));
+        assert_eq!(";".parse::<Delimiter>().unwrap(), Delimiter::Char(';'));
+        assert!("ab".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn test_record_fields_by_index_and_column() {
+        let options = RecordOptions::new(Delimiter::Char(','), true);
+        options.set_header("id,status,message\n");
+
+        let record = Record::new("7,500,\"boom, again\"\n", &options);
+        assert_eq!(record.field(&Key::Index(2)).as_deref(), Some("500"));
+        assert_eq!(
+            record.field(&Key::Column("message".into())).as_deref(),
+            Some("boom, again")
+        );
+        assert_eq!(
+            record.field(&Key::Index(0)).as_deref(),
+            Some("7,500,\"boom, again\"")
+        );
+        assert_eq!(record.field(&Key::Index(9)), None);
+        assert_eq!(record.field(&Key::Column("missing".into())), None);
+    }
+
+    fn path(p: &[&str]) -> Key {
+        Key::Path(p.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn test_json_paths() {
+        let options = RecordOptions::default().with_format(Format::Jsonl, InvalidPolicy::Raw);
+        let record = Record::new(
+            r#"{"level":"debug","http":{"status":503},"tags":["a","b"],"trace_id":null}"#,
+            &options,
+        );
+        assert_eq!(record.field(&path(&["level"])).as_deref(), Some("debug"));
+        assert_eq!(
+            record.field(&path(&["http", "status"])).as_deref(),
+            Some("503")
+        );
+        assert_eq!(record.field(&path(&["tags", "1"])).as_deref(), Some("b"));
+        assert_eq!(record.field(&path(&["trace_id"])), None);
+        assert!(record.has(&path(&["trace_id"])));
+        assert!(!record.has(&path(&["span_id"])));
+        assert!(!record.is_invalid());
+    }
+
+    #[test]
+    fn test_invalid_json_is_flagged_lazily() {
+        let options = RecordOptions::default().with_format(Format::Jsonl, InvalidPolicy::Raw);
+        let record = Record::new("not json", &options);
+        assert!(!record.is_invalid()); // Nothing parsed yet
+        assert_eq!(record.field(&path(&["level"])), None);
+        assert!(record.is_invalid());
+    }
+
+    #[test]
+    fn test_logfmt_names() {
+        let options = RecordOptions::default().with_format(Format::Logfmt, InvalidPolicy::Raw);
+        let record = Record::new("level=warn msg=\"disk low\" flag\n", &options);
+        assert_eq!(
+            record.field(&Key::Name("msg".into())).as_deref(),
+            Some("disk low")
+        );
+        assert_eq!(record.field(&Key::Name("flag".into())).as_deref(), Some(""));
+        assert!(record.has(&Key::Name("flag".into())));
+        assert!(!record.has(&Key::Name("user".into())));
+        assert!(!record.is_invalid());
+    }
+}
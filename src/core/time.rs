@@ -0,0 +1,417 @@
+// Timestamp extraction for time-window predicates (`time in [a, b)`, `--since`, `--until`).
+//
+// Without an explicit pattern, the first timestamp in the line is auto-detected among:
+// - RFC 3339 / ISO 8601 (`2024-05-01T14:03:07.123Z`, `2024-05-01 14:03:07`)
+// - Apache/nginx common log format (`10/Oct/2000:13:55:36 -0700`)
+// - Syslog (`Oct 10 13:55:36`, the year is taken from the reference date)
+// - Epoch seconds or milliseconds (a standalone 10- or 13-digit number)
+//
+// Timestamps without an offset are interpreted in the configured time zone.
+
+use crate::error::{Error, Result};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+    Timelike, Utc,
+};
+
+// Only the beginning of a line is searched when auto-detecting.
+const SCAN_LIMIT: usize = 128;
+
+// Time zone used for timestamps and bounds that carry no offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Zone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl std::str::FromStr for Zone {
+    type Err = Error;
+
+    // Accepts `local`, `UTC`/`Z`, or an offset such as `+09:00` / `-0500`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => return Ok(Zone::Local),
+            "UTC" | "utc" | "Z" => return Ok(Zone::Utc),
+            _ => {}
+        }
+        match parse_offset(s) {
+            Some((offset, len)) if len == s.len() => Ok(Zone::Fixed(offset)),
+            _ => Err(Error::InvalidOption(format!(
+                "Invalid time zone '{}' (expected local, UTC or +HH:MM)",
+                s
+            ))),
+        }
+    }
+}
+
+impl Zone {
+    fn resolve(self, naive: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|t| t.fixed_offset()),
+            Zone::Utc => Some(Utc.from_utc_datetime(&naive).fixed_offset()),
+            Zone::Fixed(offset) => offset.from_local_datetime(&naive).single(),
+        }
+    }
+
+    fn time_of_day(self, time: &DateTime<FixedOffset>) -> NaiveTime {
+        match self {
+            Zone::Local => time.with_timezone(&Local).time(),
+            Zone::Utc => time.with_timezone(&Utc).time(),
+            Zone::Fixed(offset) => time.with_timezone(&offset).time(),
+        }
+    }
+
+    fn today(self) -> NaiveDate {
+        let now = Utc::now();
+        match self {
+            Zone::Local => now.with_timezone(&Local).date_naive(),
+            Zone::Utc => now.date_naive(),
+            Zone::Fixed(offset) => now.with_timezone(&offset).date_naive(),
+        }
+    }
+}
+
+// How timestamps are found in a line.
+//   `pattern` – A strftime pattern (e.g. `%d.%m.%Y %H:%M`); auto-detection is used when `None`.
+//   `zone`    – Zone for timestamps and bounds without an offset.
+#[derive(Debug, Clone, Default)]
+pub struct TimeOptions {
+    pub pattern: Option<String>,
+    pub zone: Zone,
+}
+
+// A bound of a time window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeBound {
+    // A point in time, in milliseconds since the Unix epoch
+    Instant(i64),
+    // A wall-clock time of day (`14:30`), compared in the configured zone
+    TimeOfDay(NaiveTime),
+}
+
+impl TimeBound {
+    // Parses a bound: a time of day (`HH:MM[:SS]`), a date (`2024-05-01`), or any timestamp
+    // accepted by auto-detection.
+    pub fn parse(text: &str, zone: Zone) -> Option<Self> {
+        for format in ["%H:%M:%S%.f", "%H:%M"] {
+            if let Ok(time) = NaiveTime::parse_from_str(text, format) {
+                return Some(TimeBound::TimeOfDay(time));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+            let start = zone.resolve(date.and_hms_opt(0, 0, 0)?)?;
+            return Some(TimeBound::Instant(start.timestamp_millis()));
+        }
+        let options = TimeOptions {
+            pattern: None,
+            zone,
+        };
+        match detect_at(text, &options) {
+            Some((time, len)) if len == text.len() => {
+                Some(TimeBound::Instant(time.timestamp_millis()))
+            }
+            _ => None,
+        }
+    }
+
+    // Bounds of different kinds cannot be mixed in one range.
+    pub fn same_kind(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    // Returns the value of `time` comparable with this bound.
+    pub fn key(&self, time: &DateTime<FixedOffset>, zone: Zone) -> i64 {
+        match self {
+            TimeBound::Instant(_) => time.timestamp_millis(),
+            TimeBound::TimeOfDay(_) => {
+                let t = zone.time_of_day(time);
+                t.num_seconds_from_midnight() as i64 * 1000 + (t.nanosecond() / 1_000_000) as i64
+            }
+        }
+    }
+
+    // The bound itself as a comparable value.
+    pub fn value(&self) -> i64 {
+        match self {
+            TimeBound::Instant(ms) => *ms,
+            TimeBound::TimeOfDay(t) => {
+                t.num_seconds_from_midnight() as i64 * 1000 + (t.nanosecond() / 1_000_000) as i64
+            }
+        }
+    }
+}
+
+// Finds the first timestamp in `line`.
+pub fn extract(line: &str, options: &TimeOptions) -> Option<DateTime<FixedOffset>> {
+    let limit = line.len().min(SCAN_LIMIT);
+    for (i, c) in line.char_indices() {
+        if i >= limit {
+            break;
+        }
+        // Timestamps start at a word boundary
+        let at_boundary = line[..i]
+            .chars()
+            .next_back()
+            .is_none_or(|p| !p.is_alphanumeric());
+        if !at_boundary || !c.is_alphanumeric() {
+            continue;
+        }
+        let found = match &options.pattern {
+            Some(pattern) => parse_with_pattern(&line[i..], pattern, options.zone),
+            None => detect_at(&line[i..], options).map(|(time, _)| time),
+        };
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+fn parse_with_pattern(text: &str, pattern: &str, zone: Zone) -> Option<DateTime<FixedOffset>> {
+    if let Ok((time, _)) = DateTime::parse_and_remainder(text, pattern) {
+        return Some(time);
+    }
+    let (naive, _) = NaiveDateTime::parse_and_remainder(text, pattern).ok()?;
+    zone.resolve(naive)
+}
+
+// Tries each known format at the start of `text`. Returns the time and the matched length.
+fn detect_at(text: &str, options: &TimeOptions) -> Option<(DateTime<FixedOffset>, usize)> {
+    detect_iso(text, options.zone)
+        .or_else(|| detect_clf(text))
+        .or_else(|| detect_syslog(text, options.zone))
+        .or_else(|| detect_epoch(text))
+}
+
+// `YYYY-MM-DD[T ]HH:MM:SS[.fff][Z|±HH:MM|±HHMM]`
+fn detect_iso(text: &str, zone: Zone) -> Option<(DateTime<FixedOffset>, usize)> {
+    let b = text.as_bytes();
+    if b.len() < 19 || b[4] != b'-' || b[7] != b'-' || !matches!(b[10], b'T' | b' ') {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(
+        digits(text, 0, 4)? as i32,
+        digits(text, 5, 2)?,
+        digits(text, 8, 2)?,
+    )?;
+    let (time, mut len) = parse_clock(&text[11..])?;
+    len += 11;
+    let naive = date.and_time(time);
+
+    let rest = &text[len..];
+    if rest.starts_with('Z') {
+        return Some((Utc.from_utc_datetime(&naive).fixed_offset(), len + 1));
+    }
+    if let Some((offset, n)) = parse_offset(rest) {
+        return Some((offset.from_local_datetime(&naive).single()?, len + n));
+    }
+    Some((zone.resolve(naive)?, len))
+}
+
+// `DD/Mon/YYYY:HH:MM:SS ±HHMM`
+fn detect_clf(text: &str) -> Option<(DateTime<FixedOffset>, usize)> {
+    let candidate = text.get(..26)?;
+    let time = DateTime::parse_from_str(candidate, "%d/%b/%Y:%H:%M:%S %z").ok()?;
+    Some((time, 26))
+}
+
+// `Mon DD HH:MM:SS` (day may be space-padded)
+fn detect_syslog(text: &str, zone: Zone) -> Option<(DateTime<FixedOffset>, usize)> {
+    let month = MONTHS.iter().position(|m| text.starts_with(m))? as u32 + 1;
+    let b = text.as_bytes();
+    if b.len() < 15 || b[3] != b' ' {
+        return None;
+    }
+    let day = text.get(4..6)?.trim_start().parse::<u32>().ok()?;
+    if b[6] != b' ' {
+        return None;
+    }
+    let (time, len) = parse_clock(&text[7..])?;
+    // Syslog omits the year; assume the most recent year that doesn't put the line in the future
+    let today = zone.today();
+    let mut date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+    if date > today + chrono::Days::new(1) {
+        date = NaiveDate::from_ymd_opt(today.year() - 1, month, day)?;
+    }
+    Some((zone.resolve(date.and_time(time))?, 7 + len))
+}
+
+// A standalone 10-digit (seconds, optionally with a fraction) or 13-digit (milliseconds) number
+fn detect_epoch(text: &str) -> Option<(DateTime<FixedOffset>, usize)> {
+    let n = text.bytes().take_while(u8::is_ascii_digit).count();
+    let mut len = n;
+    let millis = match n {
+        10 => {
+            let secs: i64 = text[..10].parse().ok()?;
+            let frac = text[10..].strip_prefix('.').map(|f| {
+                let d = f.bytes().take_while(u8::is_ascii_digit).count();
+                len += 1 + d;
+                format!("{:0<3}", &f[..d.min(3)])
+                    .parse::<i64>()
+                    .unwrap_or(0)
+            });
+            secs * 1000 + frac.unwrap_or(0)
+        }
+        13 => text[..13].parse().ok()?,
+        _ => return None,
+    };
+    if text[len..]
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphanumeric())
+    {
+        return None;
+    }
+    let time = Utc.timestamp_millis_opt(millis).single()?;
+    Some((time.fixed_offset(), len))
+}
+
+// `HH:MM:SS[.fff]` → (time, length)
+fn parse_clock(text: &str) -> Option<(NaiveTime, usize)> {
+    let b = text.as_bytes();
+    if b.len() < 8 || b[2] != b':' || b[5] != b':' {
+        return None;
+    }
+    let (h, m, s) = (
+        digits(text, 0, 2)?,
+        digits(text, 3, 2)?,
+        digits(text, 6, 2)?,
+    );
+    let mut len = 8;
+    let mut nanos = 0;
+    if let Some(frac) = text[8..].strip_prefix(['.', ',']) {
+        let d = frac.bytes().take_while(u8::is_ascii_digit).count();
+        if d > 0 {
+            let padded = format!("{:0<9}", &frac[..d.min(9)]);
+            nanos = padded.parse().ok()?;
+            len += 1 + d;
+        }
+    }
+    Some((NaiveTime::from_hms_nano_opt(h, m, s, nanos)?, len))
+}
+
+// `±HH:MM` or `±HHMM` → (offset, length)
+fn parse_offset(text: &str) -> Option<(FixedOffset, usize)> {
+    let sign = match text.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let hours = digits(text, 1, 2)? as i32;
+    let (minutes, len) = if text.as_bytes().get(3) == Some(&b':') {
+        (digits(text, 4, 2)? as i32, 6)
+    } else {
+        (digits(text, 3, 2)? as i32, 5)
+    };
+    let offset = FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?;
+    Some((offset.fix(), len))
+}
+
+fn digits(text: &str, start: usize, len: usize) -> Option<u32> {
+    let s = text.get(start..start + len)?;
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc() -> TimeOptions {
+        TimeOptions {
+            pattern: None,
+            zone: Zone::Utc,
+        }
+    }
+
+    fn millis(line: &str, options: &TimeOptions) -> Option<i64> {
+        extract(line, options).map(|t| t.timestamp_millis())
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let expected = Utc
+            .with_ymd_and_hms(2024, 5, 1, 14, 3, 7)
+            .unwrap()
+            .timestamp_millis();
+        assert_eq!(
+            millis("2024-05-01T14:03:07Z INFO up", &utc()),
+            Some(expected)
+        );
+        assert_eq!(
+            millis("[2024-05-01 16:03:07+02:00] up", &utc()),
+            Some(expected)
+        );
+        assert_eq!(
+            millis("level=info ts=2024-05-01T14:03:07.250", &utc()),
+            Some(expected + 250)
+        );
+    }
+
+    #[test]
+    fn test_clf_and_epoch() {
+        let expected = Utc
+            .with_ymd_and_hms(2000, 10, 10, 20, 55, 36)
+            .unwrap()
+            .timestamp_millis();
+        let clf = r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200"#;
+        assert_eq!(millis(clf, &utc()), Some(expected));
+        let epoch = Utc
+            .with_ymd_and_hms(2024, 5, 1, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis();
+        assert_eq!(millis("1714521600 event", &utc()), Some(epoch));
+        assert_eq!(millis("t=1714521600250", &utc()), Some(epoch + 250));
+        assert_eq!(millis("id 12345678901 x", &utc()), None); // 11 digits is not a timestamp
+    }
+
+    #[test]
+    fn test_syslog_uses_time_of_day() {
+        let time = extract("Oct 10 13:55:36 host sshd[1]: ok", &utc()).unwrap();
+        assert_eq!((time.month(), time.day()), (10, 10));
+        assert_eq!(time.time(), NaiveTime::from_hms_opt(13, 55, 36).unwrap());
+    }
+
+    #[test]
+    fn test_custom_pattern_and_zone() {
+        let options = TimeOptions {
+            pattern: Some("%d.%m.%Y %H:%M".to_string()),
+            zone: "+09:00".parse().unwrap(),
+        };
+        let expected = Utc
+            .with_ymd_and_hms(2024, 5, 1, 5, 0, 0)
+            .unwrap()
+            .timestamp_millis();
+        assert_eq!(millis("at 01.05.2024 14:00 done", &options), Some(expected));
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert_eq!(
+            TimeBound::parse("14:30", Zone::Utc),
+            Some(TimeBound::TimeOfDay(
+                NaiveTime::from_hms_opt(14, 30, 0).unwrap()
+            ))
+        );
+        let day = Utc
+            .with_ymd_and_hms(2024, 5, 1, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis();
+        assert_eq!(
+            TimeBound::parse("2024-05-01", Zone::Utc),
+            Some(TimeBound::Instant(day))
+        );
+        assert!(TimeBound::parse("soon", Zone::Utc).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+// Typed parsing of comparison values: plain numbers, durations, byte sizes and syslog severities.
+//
+// A comparison's value decides the unit; field values are parsed in that same unit,
+// and anything that doesn't parse simply doesn't match.
+
+use crate::core::formats::SEVERITIES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Number,   // eg: "500", "-1.5", "1e3"
+    Duration, // eg: "250ms", "1.5s", "1m30s" (normalized to seconds)
+    Bytes,    // eg: "512KB", "1.5MiB" (normalized to bytes)
+    Severity, // eg: "warning", "err" (syslog rank: emerg=0 … debug=7)
+}
+
+const DURATION_UNITS: &[(&str, f64)] = &[
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("µs", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("m", 60.0),
+    ("h", 3600.0),
+    ("d", 86400.0),
+];
+
+const BYTE_UNITS: &[(&str, f64)] = &[
+    ("b", 1.0),
+    ("kb", 1e3),
+    ("mb", 1e6),
+    ("gb", 1e9),
+    ("tb", 1e12),
+    ("kib", 1024.0),
+    ("mib", 1024.0 * 1024.0),
+    ("gib", 1024.0 * 1024.0 * 1024.0),
+    ("tib", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+];
+
+// Alternative spellings of the syslog severities in `formats::SEVERITIES`.
+const SEVERITY_ALIASES: &[(&str, usize)] = &[
+    ("emergency", 0),
+    ("panic", 0),
+    ("critical", 2),
+    ("error", 3),
+    ("warn", 4),
+    ("informational", 6),
+];
+
+// Parses `text` in whichever unit it is written in.
+pub fn parse_scalar(text: &str) -> Option<(Unit, f64)> {
+    [Unit::Number, Unit::Duration, Unit::Bytes, Unit::Severity]
+        .into_iter()
+        .find_map(|unit| parse_as(text, unit).map(|n| (unit, n)))
+}
+
+// Parses `text` as a value of `unit`, normalized to seconds for durations and bytes for sizes.
+pub fn parse_as(text: &str, unit: Unit) -> Option<f64> {
+    let text = text.trim();
+    match unit {
+        Unit::Number => text.parse::<f64>().ok().filter(|n| n.is_finite()),
+        Unit::Duration => parse_duration(text),
+        Unit::Bytes => {
+            let (number, suffix) = split_number(text)?;
+            let lower = suffix.to_ascii_lowercase();
+            let (_, factor) = BYTE_UNITS.iter().find(|(name, _)| *name == lower)?;
+            Some(number * factor)
+        }
+        Unit::Severity => {
+            let lower = text.to_ascii_lowercase();
+            let rank = SEVERITIES
+                .iter()
+                .position(|name| *name == lower)
+                .or_else(|| {
+                    SEVERITY_ALIASES
+                        .iter()
+                        .find(|(name, _)| *name == lower)
+                        .map(|&(_, rank)| rank)
+                })?;
+            Some(rank as f64)
+        }
+    }
+}
+
+// Durations may chain several components, as Go prints them (`1h2m3.5s`).
+fn parse_duration(mut text: &str) -> Option<f64> {
+    let mut total = 0.0;
+    if text.is_empty() {
+        return None;
+    }
+    while !text.is_empty() {
+        let (number, rest) = split_number(text)?;
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let (_, factor) = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == &rest[..unit_len])?;
+        total += number * factor;
+        text = &rest[unit_len..];
+    }
+    Some(total)
+}
+
+// Splits a leading decimal number from its suffix. The suffix must be non-empty.
+fn split_number(text: &str) -> Option<(f64, &str)> {
+    let end = text
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && c == '-')))
+        .map(|(i, _)| i)?;
+    let number = text[..end].parse::<f64>().ok()?;
+    Some((number, &text[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(parse_scalar("500"), Some((Unit::Number, 500.0)));
+        assert_eq!(parse_scalar("-1.5"), Some((Unit::Number, -1.5)));
+        assert_eq!(parse_scalar("abc"), None);
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_scalar("100ms"), Some((Unit::Duration, 0.1)));
+        assert_eq!(parse_scalar("2m"), Some((Unit::Duration, 120.0)));
+        assert_eq!(parse_as("1m30s", Unit::Duration), Some(90.0));
+        assert_eq!(parse_as("500", Unit::Duration), None);
+        assert_eq!(parse_as("12parsecs", Unit::Duration), None);
+    }
+
+    #[test]
+    fn test_byte_sizes() {
+        assert_eq!(parse_scalar("2KB"), Some((Unit::Bytes, 2000.0)));
+        assert_eq!(parse_scalar("1MiB"), Some((Unit::Bytes, 1048576.0)));
+        assert_eq!(parse_as("10b", Unit::Bytes), Some(10.0));
+        assert_eq!(parse_as("10", Unit::Bytes), None);
+    }
+
+    #[test]
+    fn test_severities() {
+        assert_eq!(parse_scalar("warning"), Some((Unit::Severity, 4.0)));
+        assert_eq!(parse_as("ERROR", Unit::Severity), Some(3.0));
+        assert_eq!(parse_as("err", Unit::Severity), Some(3.0));
+        assert_eq!(parse_as("3", Unit::Severity), None);
+        assert_eq!(parse_scalar("verbose"), None);
+    }
+}
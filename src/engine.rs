@@ -1,72 +1,459 @@
+mod dedup;
+mod mmap;
+mod parallel;
+
+use self::dedup::Dedup;
+pub use self::dedup::DedupOptions;
+pub use self::mmap::run_mmap;
+pub use self::parallel::run_parallel;
+use crate::core::evaluator::EvalContext;
+use anyhow::bail;
+use memchr::memchr;
 use std::io::{BufRead, Write};
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
+
+// Predicate used to end processing early (e.g. a shutdown marker). Returns `true` to stop.
+pub type StopCondition = Box<dyn Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error>>;
+
+// Options controlling how `run_filter_with` processes the stream.
+//   `report`    – If `true`, suppresses output and prints processing statistics to standard error.
+//   `max_count` – Stops after this many lines have been written to the output.
+//   `stop_when` – Stops at the first line for which the condition returns `true` (that line is not output).
+//   `multiline` – If `true`, each record runs up to and including the next blank line
+//                 (e.g. `journalctl -o export`); line numbers then count records.
+//   `dedup`      – Drops or folds repeated output lines (see `DedupOptions`).
+//   `line_limit` – Bounds the memory used by a single record (see `LineLimit`).
+#[derive(Default)]
+pub struct RunOptions {
+    pub report: bool,
+    pub max_count: Option<usize>,
+    pub stop_when: Option<StopCondition>,
+    pub multiline: bool,
+    pub dedup: DedupOptions,
+    pub line_limit: Option<LineLimit>,
+}
+
+// Records longer than `max` bytes (including the newline) are never buffered whole;
+// `policy` says what happens to them instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLimit {
+    pub max: usize,
+    pub policy: LongLinePolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongLinePolicy {
+    // Filter the first `max` bytes as if they were the whole line, and output them if kept
+    Truncate,
+    // Output the whole line without filtering it
+    Pass,
+    // Exclude the line without filtering it
+    Drop,
+    // Stop with an error
+    #[default]
+    Error,
+}
+
+impl std::str::FromStr for LongLinePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(LongLinePolicy::Truncate),
+            "pass" => Ok(LongLinePolicy::Pass),
+            "drop" => Ok(LongLinePolicy::Drop),
+            "error" => Ok(LongLinePolicy::Error),
+            _ => bail!(
+                "Unknown policy '{}' (expected: truncate, pass, drop, error)",
+                s
+            ),
+        }
+    }
+}
+
+// Why processing ended before the end of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    MaxCount,
+    StopCondition,
+}
+
+// Statistics collected while processing a stream.
+//   `duplicates` – Kept lines that were not output because of `dedup`.
+//   `long_lines` – Records longer than `line_limit` (counted in `total` too).
+//   `stopped`    – The line number and reason when processing ended before the end of the input.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunSummary {
+    pub total: usize,
+    pub skipped: usize,
+    pub written: usize,
+    pub duplicates: usize,
+    pub long_lines: usize,
+    pub stopped: Option<(usize, StopReason)>,
+}
 
 // Processes a text stream by applying a per-line filter function and writing matching lines to the output.
 //   `reader` – The input source (e.g., a file or standard input).
 //   `writer` – The output destination (e.g., standard output).
-//   `filter` – A function applied to each line. Returns `true` to include the line in the output (when `invert = false`).
+//   `filter` – A function applied to each line and its context (line number). Returns `true` to include the line in the output (when `invert = false`).
 //   `invert` – If `true`, reverses the filter logic (i.e., switches to inclusion instead of exclusion; grep-like behavior).
 //   `report` – If `true`, suppresses output and prints processing statistics to standard error.
 //
 // Returns an error if reading from the input or writing to the output fails, or if the filter function itself returns an error.
 pub fn run_filter(
-    mut reader: Box<dyn BufRead>,
+    reader: impl BufRead,
     writer: &mut dyn Write,
-    filter: impl Fn(&[u8]) -> Result<bool, anyhow::Error>,
+    filter: impl Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error>,
     report: bool,
-) -> Result<(), anyhow::Error> {
-    let mut buffer = Vec::with_capacity(4096);
+) -> Result<RunSummary, anyhow::Error> {
+    let options = RunOptions {
+        report,
+        ..Default::default()
+    };
+    run_filter_with(reader, writer, filter, &options)
+}
 
-    let mut total = 0usize;
-    let mut skipped = 0usize;
-    let mut written = 0usize;
+// Same as `run_filter`, but with early termination controlled by `options`.
+//
+// The reader is dropped as soon as processing stops, so the input is closed without reading the rest of it.
+pub fn run_filter_with(
+    mut reader: impl BufRead,
+    writer: &mut dyn Write,
+    filter: impl Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error>,
+    options: &RunOptions,
+) -> Result<RunSummary, anyhow::Error> {
+    let mut buffer = Vec::with_capacity(4096);
+    let mut processor = Processor::new(writer, filter, options);
+    let limit = options.line_limit.map_or(usize::MAX, |limit| limit.max);
 
-    loop {
+    while !processor.is_stopped() {
         buffer.clear();
-        let n = reader.read_until(b'\n', &mut buffer)?;
+        let (n, cut) = read_record(&mut reader, &mut buffer, options.multiline, limit)?;
+        if cut {
+            processor.process_long(&buffer, |out| {
+                skip_record(&mut reader, out, options.multiline)
+            })?;
+            continue;
+        }
         if n == 0 {
             break;
         }
-        total += 1;
+        processor.process(&buffer)?;
+    }
+    drop(reader);
 
-        // Handle CRLF: convert \r\n to \n
-        if buffer.ends_with(b"\r\n") {
-            buffer.truncate(buffer.len() - 2);
-            buffer.push(b'\n');
+    processor.finish()
+}
+
+// Applies the filter, stop conditions and deduplication to records in input order.
+// Shared by the engines that see every line in sequence.
+struct Processor<'a, F> {
+    writer: &'a mut dyn Write,
+    filter: F,
+    options: &'a RunOptions,
+    dedup: Dedup,
+    summary: RunSummary,
+}
+
+impl<'a, F> Processor<'a, F>
+where
+    F: Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error>,
+{
+    fn new(writer: &'a mut dyn Write, filter: F, options: &'a RunOptions) -> Self {
+        let mut summary = RunSummary::default();
+        if options.max_count == Some(0) {
+            summary.stopped = Some((0, StopReason::MaxCount));
+        }
+        Self {
+            writer,
+            filter,
+            options,
+            dedup: Dedup::new(options.dedup),
+            summary,
         }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.summary.stopped.is_some()
+    }
 
-        match filter(&buffer) {
+    // Handles the next record (a line including its newline, if any).
+    fn process(&mut self, record: &[u8]) -> std::io::Result<()> {
+        let options = self.options;
+        let report = options.report;
+        let total = self.summary.total + 1;
+        if self.stops_at(record, total) {
+            return Ok(());
+        }
+        let summary = &mut self.summary;
+        summary.total = total;
+        let ctx = EvalContext::new(total);
+
+        let keep = match (self.filter)(record, &ctx) {
             Ok(matched) => {
-                if matched {
-                    if !report {
-                        writer.write_all(&buffer)?;
-                    }
-                    written += 1;
-                } else {
-                    skipped += 1;
-                }
                 trace!("Line {}: matched={} → output={}", total, matched, matched);
+                matched
             }
             Err(e) => {
                 error!("Filter error at line {}: {}", total, e);
-                if !report {
-                    writer.write_all(&buffer)?;
+                true
+            }
+        };
+
+        if !keep {
+            summary.skipped += 1;
+            return Ok(());
+        }
+        if options.dedup.is_enabled() {
+            let admission = self.dedup.admit(record);
+            write_repeats(self.writer, admission.folded, report)?;
+            if !admission.keep {
+                summary.duplicates += 1;
+                return Ok(());
+            }
+        }
+        if !report {
+            self.writer.write_all(record)?;
+        }
+        summary.written += 1;
+
+        if options.max_count == Some(summary.written) {
+            summary.stopped = Some((total, StopReason::MaxCount));
+        }
+        Ok(())
+    }
+
+    // Whether `options.stop_when` ends the run at the record, which is then neither counted nor
+    // output.
+    fn stops_at(&mut self, record: &[u8], line_no: usize) -> bool {
+        let Some(stop_when) = &self.options.stop_when else {
+            return false;
+        };
+        match stop_when(record, &EvalContext::new(line_no)) {
+            Ok(true) => {
+                self.summary.stopped = Some((line_no, StopReason::StopCondition));
+                true
+            }
+            Ok(false) => false,
+            Err(e) => {
+                error!("Stop condition error at line {}: {}", line_no, e);
+                false
+            }
+        }
+    }
+
+    // Handles a record longer than `options.line_limit`, of which `prefix` holds the first `max`
+    // bytes. `rest` must consume the remainder of the record, copying it to the writer if given one.
+    fn process_long(
+        &mut self,
+        prefix: &[u8],
+        rest: impl FnOnce(Option<&mut dyn Write>) -> std::io::Result<()>,
+    ) -> Result<(), anyhow::Error> {
+        let Some(limit) = self.options.line_limit else {
+            unreachable!("a record can only be cut at a line limit");
+        };
+        let line_no = self.summary.total + 1;
+        if limit.policy == LongLinePolicy::Error {
+            bail!(
+                "Line {} is longer than the limit of {} bytes",
+                line_no,
+                limit.max
+            );
+        }
+        if self.summary.long_lines == 0 {
+            warn!(
+                "Line {} is longer than {} bytes: {:?}",
+                line_no, limit.max, limit.policy
+            );
+        }
+        self.summary.long_lines += 1;
+        // Unlike a truncated record, a passed or dropped one is not filtered, but the stop
+        // condition still sees its first `max` bytes
+        if matches!(limit.policy, LongLinePolicy::Pass | LongLinePolicy::Drop)
+            && self.stops_at(prefix, line_no)
+        {
+            return Ok(());
+        }
+
+        match limit.policy {
+            LongLinePolicy::Truncate => {
+                rest(None)?;
+                let mut line = prefix.to_vec();
+                line.push(b'\n');
+                self.process(&line)?;
+            }
+            LongLinePolicy::Drop => {
+                rest(None)?;
+                self.summary.total += 1;
+                self.summary.skipped += 1;
+            }
+            LongLinePolicy::Pass => {
+                if self.options.report {
+                    rest(None)?;
+                } else {
+                    self.writer.write_all(prefix)?;
+                    rest(Some(&mut *self.writer))?;
+                }
+                self.summary.total += 1;
+                self.summary.written += 1;
+                if self.options.max_count == Some(self.summary.written) {
+                    self.summary.stopped = Some((line_no, StopReason::MaxCount));
                 }
-                written += 1;
             }
+            LongLinePolicy::Error => unreachable!(),
         }
+        Ok(())
     }
 
-    if report {
+    fn finish(mut self) -> Result<RunSummary, anyhow::Error> {
+        write_repeats(self.writer, self.dedup.finish(), self.options.report)?;
+        finish(&self.summary, self.options);
+        Ok(self.summary)
+    }
+}
+
+// Reports on stderr where processing stopped early, and prints the report if requested.
+fn finish(summary: &RunSummary, options: &RunOptions) {
+    if let Some((line, reason)) = summary.stopped {
+        info!("Stopped at line {}: {:?}", line, reason);
+        match reason {
+            StopReason::MaxCount => eprintln!("Stopped at line {line}: max count reached"),
+            StopReason::StopCondition => {
+                eprintln!("Stopped at line {line}: stop condition matched")
+            }
+        }
+    }
+
+    if options.report {
         info!(
             "Done. Total={}, Skipped={}, Output={}",
-            total, skipped, written
+            summary.total, summary.skipped, summary.written
         );
         eprintln!(
             "Processed lines: {}\nExcluded lines: {}\nOutput lines: {}",
-            total, skipped, written
+            summary.total, summary.skipped, summary.written
         );
+        if options.dedup.is_enabled() {
+            eprintln!("Duplicate lines: {}", summary.duplicates);
+        }
+        if summary.long_lines > 0 {
+            eprintln!("Long lines: {}", summary.long_lines);
+        }
     }
+}
 
+// Writes the marker for a line that was repeated `count` more times after being output.
+fn write_repeats(writer: &mut dyn Write, count: usize, report: bool) -> std::io::Result<()> {
+    if count > 0 && !report {
+        let plural = if count == 1 { "" } else { "s" };
+        writeln!(writer, "(repeated {count} time{plural})")?;
+    }
     Ok(())
 }
+
+// Reads the next line (or blank-line-terminated record) into `buffer`, but no more than `limit` bytes.
+// Returns the number of bytes read (0 at the end of the input), and whether the record was cut
+// at the limit, leaving the rest of it unread.
+pub(crate) fn read_record(
+    reader: &mut impl BufRead,
+    buffer: &mut Vec<u8>,
+    multiline: bool,
+    limit: usize,
+) -> std::io::Result<(usize, bool)> {
+    let mut total = 0;
+    loop {
+        let start = buffer.len();
+        let (n, cut) = read_line(reader, buffer, limit)?;
+        total += n;
+        if cut {
+            return Ok((total, true));
+        }
+        if n == 0 {
+            return Ok((total, false));
+        }
+
+        // Handle CRLF: convert \r\n to \n
+        if buffer.ends_with(b"\r\n") {
+            buffer.truncate(buffer.len() - 2);
+            buffer.push(b'\n');
+        }
+
+        if !multiline || buffer[start..] == *b"\n" {
+            return Ok((total, false));
+        }
+    }
+}
+
+// Like `read_until(b'\n', ...)`, but stops once `buffer` holds `limit` bytes.
+fn read_line(
+    reader: &mut impl BufRead,
+    buffer: &mut Vec<u8>,
+    limit: usize,
+) -> std::io::Result<(usize, bool)> {
+    let mut total = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok((total, false));
+        }
+        let (len, done) = match memchr(b'\n', available) {
+            Some(i) => (i + 1, true),
+            None => (available.len(), false),
+        };
+        let room = limit.saturating_sub(buffer.len());
+        if len > room {
+            buffer.extend_from_slice(&available[..room]);
+            reader.consume(room);
+            return Ok((total + room, true));
+        }
+        buffer.extend_from_slice(&available[..len]);
+        reader.consume(len);
+        total += len;
+        if done {
+            return Ok((total, false));
+        }
+    }
+}
+
+// Consumes the rest of a record cut by `read_record`, copying it to `out` if given.
+fn skip_record(
+    reader: &mut impl BufRead,
+    mut out: Option<&mut dyn Write>,
+    multiline: bool,
+) -> std::io::Result<()> {
+    let mut first = true;
+    loop {
+        let mut blank = true;
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                return Ok(());
+            }
+            let (n, done) = match memchr(b'\n', available) {
+                Some(i) => (i + 1, true),
+                None => (available.len(), false),
+            };
+            blank &= available[..n].iter().all(|&b| b == b'\r' || b == b'\n');
+            if let Some(out) = out.as_deref_mut() {
+                out.write_all(&available[..n])?;
+            }
+            reader.consume(n);
+            if done {
+                break;
+            }
+        }
+        // The first line is the tail of the cut line; a record ends at the next blank line
+        if !multiline || (!first && blank) {
+            return Ok(());
+        }
+        first = false;
+    }
+}
@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+
+// How kept lines are deduplicated.
+//   `uniq`      – Drops lines already output, remembering at most this many distinct lines.
+//   `collapse`  – Folds consecutive repeats into the first line and a `(repeated N times)` marker.
+//   `normalize` – Masks numbers, UUIDs and hex IDs before comparing lines.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DedupOptions {
+    pub uniq: Option<usize>,
+    pub collapse: bool,
+    pub normalize: bool,
+}
+
+impl DedupOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.uniq.is_some() || self.collapse
+    }
+}
+
+// The outcome of offering a kept line to `Dedup`.
+//   `keep`   – Whether the line should be output.
+//   `folded` – Repeats of the previous line that ended with this one; report them before the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admission {
+    pub keep: bool,
+    pub folded: usize,
+}
+
+// Tracks lines already output.
+//
+// Only 64-bit hashes are kept for `uniq`, and once `cap` distinct lines have been seen the oldest
+// are forgotten, so memory stays bounded on endless streams at the cost of letting a duplicate
+// through after a long gap.
+#[derive(Debug, Default)]
+pub struct Dedup {
+    options: DedupOptions,
+    seen: HashSet<u64>,
+    order: VecDeque<u64>,
+    last: Option<Vec<u8>>,
+    repeats: usize,
+}
+
+impl Dedup {
+    pub fn new(options: DedupOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    pub fn admit(&mut self, line: &[u8]) -> Admission {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let key = if self.options.normalize {
+            normalize(line)
+        } else {
+            Cow::Borrowed(line)
+        };
+
+        let mut folded = 0;
+        if self.options.collapse {
+            if self.last.as_deref() == Some(key.as_ref()) {
+                self.repeats += 1;
+                return Admission {
+                    keep: false,
+                    folded: 0,
+                };
+            }
+            folded = self.finish();
+            self.last = Some(key.to_vec());
+        }
+
+        let keep = match self.options.uniq {
+            Some(cap) => self.remember(&key, cap),
+            None => true,
+        };
+        if !keep {
+            // Only repeats of a line that was output get a marker
+            self.last = None;
+        }
+        Admission { keep, folded }
+    }
+
+    // Returns the repeats of the last line not reported yet. Call once at the end of the input.
+    pub fn finish(&mut self) -> usize {
+        std::mem::take(&mut self.repeats)
+    }
+
+    // Returns `true` if `key` was not seen before.
+    fn remember(&mut self, key: &[u8], cap: usize) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if !self.seen.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > cap {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+// Masks the parts of a line that usually differ between otherwise identical messages:
+// UUIDs become `<uuid>`, `0x…` and hex words of 8+ digits become `<hex>`, digit runs become `<num>`.
+pub fn normalize(line: &[u8]) -> Cow<'_, [u8]> {
+    if !line.iter().any(u8::is_ascii_digit) {
+        return Cow::Borrowed(line);
+    }
+
+    let mut out = Vec::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        let at_boundary = i == 0 || !line[i - 1].is_ascii_alphanumeric();
+        if at_boundary {
+            if let Some(len) = uuid_len(&line[i..]) {
+                out.extend_from_slice(b"<uuid>");
+                i += len;
+                continue;
+            }
+            if let Some(len) = hex_len(&line[i..]) {
+                out.extend_from_slice(b"<hex>");
+                i += len;
+                continue;
+            }
+        }
+        if line[i].is_ascii_digit() {
+            i += line[i..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit() || **b == b'.')
+                .count();
+            out.extend_from_slice(b"<num>");
+            continue;
+        }
+        out.push(line[i]);
+        i += 1;
+    }
+    Cow::Owned(out)
+}
+
+// `8-4-4-4-12` hex digits
+fn uuid_len(text: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    for (n, group) in [8, 4, 4, 4, 12].into_iter().enumerate() {
+        if n > 0 {
+            if text.get(pos) != Some(&b'-') {
+                return None;
+            }
+            pos += 1;
+        }
+        if !text
+            .get(pos..pos + group)?
+            .iter()
+            .all(u8::is_ascii_hexdigit)
+        {
+            return None;
+        }
+        pos += group;
+    }
+    ends_word(text, pos)
+}
+
+// `0x1f`, or a word of at least 8 hex digits that contains a digit (`3f2a9c1e`, a git SHA)
+fn hex_len(text: &[u8]) -> Option<usize> {
+    let hex_digits = |t: &[u8]| t.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if let Some(rest) = text.strip_prefix(b"0x") {
+        return match hex_digits(rest) {
+            0 => None,
+            n => ends_word(text, 2 + n),
+        };
+    }
+    let n = hex_digits(text);
+    if n < 8 || !text[..n].iter().any(u8::is_ascii_digit) {
+        return None;
+    }
+    ends_word(text, n)
+}
+
+fn ends_word(text: &[u8], len: usize) -> Option<usize> {
+    match text.get(len) {
+        Some(b) if b.is_ascii_alphanumeric() => None,
+        _ => Some(len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(line: &str) -> String {
+        String::from_utf8(normalize(line.as_bytes()).into_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalized("req 550e8400-e29b-41d4-a716-446655440000 took 12.5ms"),
+            "req <uuid> took <num>ms"
+        );
+        assert_eq!(
+            normalized("addr=0x7ffd3a commit 9fceb02d0ae598e95dc970b74767f19372d61af8"),
+            "addr=<hex> commit <hex>"
+        );
+        assert_eq!(
+            normalized("worker-3 deadbeef ok"),
+            "worker-<num> deadbeef ok"
+        );
+        assert_eq!(normalized("no digits here"), "no digits here");
+    }
+
+    #[test]
+    fn test_uniq_with_cap() {
+        let mut dedup = Dedup::new(DedupOptions {
+            uniq: Some(2),
+            ..Default::default()
+        });
+        let kept: Vec<bool> = ["a\n", "b\n", "a", "c\n", "b\n", "a\n"]
+            .iter()
+            .map(|line| dedup.admit(line.as_bytes()).keep)
+            .collect();
+        // "a" is forgotten once "c" fills the cap
+        assert_eq!(kept, vec![true, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn test_collapse_consecutive() {
+        let mut dedup = Dedup::new(DedupOptions {
+            collapse: true,
+            normalize: true,
+            ..Default::default()
+        });
+        let admissions: Vec<(bool, usize)> = ["retry 1", "retry 2", "retry 3", "ok", "retry 4"]
+            .iter()
+            .map(|line| {
+                let a = dedup.admit(line.as_bytes());
+                (a.keep, a.folded)
+            })
+            .collect();
+        assert_eq!(
+            admissions,
+            vec![(true, 0), (false, 0), (false, 0), (true, 2), (true, 0)]
+        );
+        assert_eq!(dedup.finish(), 0);
+    }
+}
@@ -0,0 +1,65 @@
+use super::{run_filter_with, Processor, RunOptions, RunSummary};
+use crate::core::evaluator::EvalContext;
+use memchr::memchr;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use tracing::debug;
+
+// Same as `run_filter_with`, but reads a regular file through a memory map.
+//
+// Lines are found with a vectorized `memchr` and handed to the filter (and the writer) as slices
+// of the mapped region, so nothing is copied per line except to turn `\r\n` into `\n`.
+//
+// Falls back to `run_filter_with` for empty or special files (pipes, `/proc`), which cannot be
+// mapped, and for `multiline` records.
+pub fn run_mmap(
+    file: File,
+    writer: &mut dyn Write,
+    filter: impl Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error>,
+    options: &RunOptions,
+) -> Result<RunSummary, anyhow::Error> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() == 0 || options.multiline {
+        debug!("Input cannot be memory-mapped, streaming instead");
+        return run_filter_with(BufReader::new(file), writer, filter, options);
+    }
+
+    // SAFETY: the map is only read. If another process truncates the file while it is mapped,
+    // reading the lost pages faults (SIGBUS), which is why the command line only maps files with
+    // `--mmap` or `-j`. Bytes appended after mapping are simply not seen.
+    let map = unsafe { Mmap::map(&file)? };
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let data = &map[..];
+    let mut processor = Processor::new(writer, filter, options);
+    let mut crlf = Vec::new();
+    let mut start = 0;
+
+    while start < data.len() && !processor.is_stopped() {
+        let end = memchr(b'\n', &data[start..]).map_or(data.len(), |i| start + i + 1);
+        let line = &data[start..end];
+        start = end;
+        if let Some(limit) = options.line_limit.filter(|limit| line.len() > limit.max) {
+            let (prefix, rest) = line.split_at(limit.max);
+            processor.process_long(prefix, |out| match out {
+                Some(out) => out.write_all(rest),
+                None => Ok(()),
+            })?;
+            continue;
+        }
+        match line.strip_suffix(b"\r\n") {
+            // Handle CRLF: convert \r\n to \n
+            Some(body) => {
+                crlf.clear();
+                crlf.extend_from_slice(body);
+                crlf.push(b'\n');
+                processor.process(&crlf)?;
+            }
+            None => processor.process(line)?,
+        }
+    }
+
+    processor.finish()
+}
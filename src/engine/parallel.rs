@@ -0,0 +1,235 @@
+use super::{finish, run_mmap, LineLimit, LongLinePolicy, RunOptions, RunSummary, StopReason};
+use crate::core::evaluator::EvalContext;
+use anyhow::bail;
+use memchr::{memchr, memchr_iter};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::Write;
+use std::thread;
+use tracing::{debug, error};
+
+// Input is handed to the workers in chunks of about this size, cut at line boundaries.
+const CHUNK_SIZE: usize = 4 << 20;
+
+// Same as `run_mmap`, but filters the file on `jobs` threads.
+//
+// The mapped file is cut into large chunks on line boundaries; each batch of `jobs` chunks is
+// filtered concurrently and written in the original order, so the output is identical to the
+// streaming engine's. The filter must not depend on previous lines (no `between(...)`, `time`,
+// `sample(...)`, header rows): the caller is responsible for checking that.
+//
+// Falls back to `run_mmap` for a single job, for files that cannot be mapped, and for options
+// that need lines in order (`stop_when`, `dedup`, `multiline`).
+pub fn run_parallel(
+    file: File,
+    writer: &mut dyn Write,
+    filter: impl Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error> + Sync,
+    options: &RunOptions,
+    jobs: usize,
+) -> Result<RunSummary, anyhow::Error> {
+    let metadata = file.metadata()?;
+    if jobs <= 1
+        || !metadata.is_file()
+        || metadata.len() == 0
+        || options.stop_when.is_some()
+        || options.dedup.is_enabled()
+        || options.multiline
+    {
+        debug!("Parallel processing not possible, reading line by line instead");
+        return run_mmap(file, writer, filter, options);
+    }
+
+    // SAFETY: as in `run_mmap`, the map is only read
+    let map = unsafe { Mmap::map(&file)? };
+    #[cfg(unix)]
+    let _ = map.advise(memmap2::Advice::Sequential);
+
+    let data = &map[..];
+    let mut summary = RunSummary::default();
+    let mut next_line = 1;
+    let mut start = 0;
+
+    if options.max_count == Some(0) {
+        summary.stopped = Some((0, StopReason::MaxCount));
+    }
+
+    while start < data.len() && summary.stopped.is_none() {
+        let mut batch = Vec::with_capacity(jobs);
+        while batch.len() < jobs && start < data.len() {
+            let end = chunk_end(data, start, CHUNK_SIZE);
+            let chunk = &data[start..end];
+            batch.push((chunk, next_line));
+            next_line += count_lines(chunk);
+            start = end;
+        }
+
+        let report = options.report;
+        let limit = options.line_limit;
+        let results: Vec<Result<Chunk, anyhow::Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|&(chunk, first_line)| {
+                    let filter = &filter;
+                    scope.spawn(move || filter_chunk(chunk, first_line, filter, report, limit))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("filter thread panicked"))
+                .collect()
+        });
+
+        for chunk in results {
+            let chunk = chunk?;
+            let remaining = options.max_count.map(|max| max - summary.written);
+            match remaining {
+                Some(remaining) if remaining <= chunk.kept.len() => {
+                    // Cut the chunk after the last line allowed by `max_count` (never 0 here)
+                    let (line, end) = chunk.kept[remaining - 1];
+                    writer.write_all(&chunk.output[..end])?;
+                    summary.total = line;
+                    summary.skipped += line + 1 - chunk.first_line - remaining;
+                    summary.written += remaining;
+                    summary.long_lines += chunk.long_lines.iter().filter(|&&n| n <= line).count();
+                    summary.stopped = Some((line, StopReason::MaxCount));
+                    break;
+                }
+                _ => {
+                    writer.write_all(&chunk.output)?;
+                    summary.total += chunk.total;
+                    summary.skipped += chunk.total - chunk.kept.len();
+                    summary.written += chunk.kept.len();
+                    summary.long_lines += chunk.long_lines.len();
+                }
+            }
+        }
+    }
+    drop(map);
+
+    finish(&summary, options);
+    Ok(summary)
+}
+
+// The result of filtering one chunk.
+//   `output`     – The kept lines, concatenated (empty in report mode).
+//   `kept`       – The line number of each kept line and the end of that line in `output`.
+//   `first_line` – The line number of the chunk's first line.
+//   `total`      – The number of lines in the chunk.
+//   `long_lines` – The line number of each line longer than the line limit.
+struct Chunk {
+    output: Vec<u8>,
+    kept: Vec<(usize, usize)>,
+    first_line: usize,
+    total: usize,
+    long_lines: Vec<usize>,
+}
+
+fn filter_chunk(
+    chunk: &[u8],
+    first_line: usize,
+    filter: &(impl Fn(&[u8], &EvalContext) -> Result<bool, anyhow::Error> + Sync),
+    report: bool,
+    limit: Option<LineLimit>,
+) -> Result<Chunk, anyhow::Error> {
+    let mut result = Chunk {
+        output: Vec::new(),
+        kept: Vec::new(),
+        first_line,
+        total: 0,
+        long_lines: Vec::new(),
+    };
+    let mut line_buf = Vec::new();
+
+    let mut start = 0;
+    while start < chunk.len() {
+        let end = memchr(b'\n', &chunk[start..]).map_or(chunk.len(), |i| start + i + 1);
+        let mut line = &chunk[start..end];
+        start = end;
+        let line_no = first_line + result.total;
+        result.total += 1;
+
+        let mut filtered = true;
+        if let Some(limit) = limit.filter(|limit| line.len() > limit.max) {
+            // The same policies as `Processor::process_long`
+            result.long_lines.push(line_no);
+            match limit.policy {
+                LongLinePolicy::Error => bail!(
+                    "Line {} is longer than the limit of {} bytes",
+                    line_no,
+                    limit.max
+                ),
+                LongLinePolicy::Drop => continue,
+                LongLinePolicy::Pass => filtered = false,
+                LongLinePolicy::Truncate => {
+                    line_buf.clear();
+                    line_buf.extend_from_slice(&line[..limit.max]);
+                    line_buf.push(b'\n');
+                    line = &line_buf;
+                }
+            }
+        } else if let Some(body) = line.strip_suffix(b"\r\n") {
+            // Handle CRLF: convert \r\n to \n, as the streaming engine does
+            line_buf.clear();
+            line_buf.extend_from_slice(body);
+            line_buf.push(b'\n');
+            line = &line_buf;
+        }
+
+        let keep = !filtered
+            || match filter(line, &EvalContext::new(line_no)) {
+                Ok(matched) => matched,
+                Err(e) => {
+                    error!("Filter error at line {}: {}", line_no, e);
+                    true
+                }
+            };
+        if keep {
+            if !report {
+                result.output.extend_from_slice(line);
+            }
+            result.kept.push((line_no, result.output.len()));
+        }
+    }
+    Ok(result)
+}
+
+// Returns the end of the chunk that starts at `start`: just after the first line break at or after
+// `start + size - 1`, so a chunk holds at least `size` bytes and only whole lines.
+fn chunk_end(data: &[u8], start: usize, size: usize) -> usize {
+    let min = start + size;
+    if min >= data.len() {
+        return data.len();
+    }
+    memchr(b'\n', &data[min - 1..]).map_or(data.len(), |i| min + i)
+}
+
+fn count_lines(chunk: &[u8]) -> usize {
+    let newlines = memchr_iter(b'\n', chunk).count();
+    newlines + usize::from(!chunk.ends_with(b"\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(input: &str, size: usize) -> Vec<&str> {
+        let mut result = Vec::new();
+        let mut start = 0;
+        while start < input.len() {
+            let end = chunk_end(input.as_bytes(), start, size);
+            result.push(&input[start..end]);
+            start = end;
+        }
+        result
+    }
+
+    #[test]
+    fn test_chunks_end_at_line_boundaries() {
+        assert_eq!(chunks("aa\nbb\ncc\ndd", 4), vec!["aa\nbb\n", "cc\ndd"]);
+        assert_eq!(chunks("aa\nbb\ncc\n", 3), vec!["aa\n", "bb\n", "cc\n"]);
+        // A line longer than the chunk size stays whole
+        assert_eq!(chunks("abcdefgh\nx\n", 3), vec!["abcdefgh\n", "x\n"]);
+        assert_eq!(count_lines(b"a\nb"), 2);
+        assert_eq!(count_lines(b"a\nb\n"), 2);
+    }
+}
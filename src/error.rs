@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// The errors returned by chaf.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The query is not valid; `pos` is the byte offset in the query where the problem was found.
+    Syntax { message: String, pos: usize },
+    /// The query cannot be used with the chosen options, such as `col(...)` without a header row.
+    Unsupported(String),
+    /// An option value could not be understood, such as an unknown format name.
+    InvalidOption(String),
+    /// A line is not valid UTF-8.
+    InvalidUtf8,
+    /// Reading the input or writing the output failed.
+    Io(std::io::Error),
+}
+
+/// A `Result` with chaf's [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub(crate) fn syntax(pos: usize, message: impl Into<String>) -> Self {
+        Error::Syntax {
+            message: message.into(),
+            pos,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax { message, pos } => write!(f, "{}: pos={}", message, pos),
+            Error::Unsupported(message) | Error::InvalidOption(message) => f.write_str(message),
+            Error::InvalidUtf8 => f.write_str("Input is not valid UTF-8"),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,230 @@
+mod reader;
+#[cfg(feature = "tokio")]
+mod stream;
+
+pub use self::reader::{FilteredReader, Lines};
+#[cfg(feature = "tokio")]
+pub use self::stream::LineStream;
+use crate::core::evaluator::EvalContext;
+use crate::core::filter::LineFilter;
+use crate::core::record::{Delimiter, Format, InvalidPolicy, RecordOptions};
+use crate::error::Result;
+use crate::query::Query;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A compiled [`Query`], applied to one line after another.
+///
+/// As on the command line, a line the query matches is dropped: [`Filter::is_kept`] is `false`
+/// for it, unless the filter is built with [`FilterBuilder::invert`].
+///
+/// ```
+/// use chaf::{Filter, Query};
+///
+/// let query = Query::parse("DEBUG | healthcheck")?;
+/// let filter = Filter::new(&query)?;
+///
+/// assert!(filter.is_kept(b"ERROR disk full")?);
+/// assert!(!filter.is_kept(b"DEBUG cache hit")?);
+/// assert!(filter.matches("GET /healthcheck 200"));
+/// # Ok::<(), chaf::Error>(())
+/// ```
+///
+/// Each call to [`is_kept`](Filter::is_kept) or [`matches`](Filter::matches) is the next line of
+/// the input, which is what `line` and stateful functions such as `between(...)` count on. Use a
+/// new filter for each input.
+pub struct Filter {
+    inner: LineFilter,
+    invert: bool,
+    multiline: bool,
+    line_no: AtomicUsize,
+}
+
+impl Filter {
+    /// Builds a filter with the default options.
+    pub fn new(query: &Query) -> Result<Self> {
+        Self::builder().build(query)
+    }
+
+    /// Returns a builder for a filter with non-default options.
+    pub fn builder() -> FilterBuilder {
+        FilterBuilder::default()
+    }
+
+    /// Returns `true` if the line is output. Fails if the line is not valid UTF-8.
+    pub fn is_kept(&self, line: &[u8]) -> Result<bool> {
+        let line_no = self.line_no.fetch_add(1, Ordering::Relaxed) + 1;
+        self.inner.keep(line, &EvalContext::new(line_no))
+    }
+
+    /// Returns `true` if the query matches the line, regardless of [`FilterBuilder::invert`].
+    pub fn matches(&self, line: &str) -> bool {
+        // `&str` is valid UTF-8, the only reason `keep` fails.
+        self.is_kept(line.as_bytes()).unwrap_or(true) == self.invert
+    }
+
+    /// The number of lines seen so far.
+    pub fn line_no(&self) -> usize {
+        self.line_no.load(Ordering::Relaxed)
+    }
+
+    /// Returns an iterator over the lines of `reader` the filter keeps, each with its newline.
+    /// `\r\n` line endings become `\n`, as in the output of the command line.
+    ///
+    /// ```
+    /// use chaf::{Filter, Query};
+    ///
+    /// let filter = Filter::new(&Query::parse("DEBUG")?)?;
+    /// let input = "INFO start\nDEBUG tick\nERROR stop\n".as_bytes();
+    ///
+    /// let kept = filter.lines(input).collect::<std::io::Result<Vec<_>>>()?;
+    /// assert_eq!(kept, [&b"INFO start\n"[..], b"ERROR stop\n"]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    /// A line that is not valid UTF-8 is an error of kind [`InvalidData`](std::io::ErrorKind::InvalidData);
+    /// iteration may go on past it.
+    pub fn lines<R: BufRead>(self, reader: R) -> Lines<R> {
+        Lines::new(self, reader)
+    }
+
+    /// Wraps `reader` so that reading from it yields only the lines the filter keeps, like
+    /// [`lines`](Filter::lines) joined together.
+    ///
+    /// ```
+    /// use chaf::{Filter, Query};
+    /// use std::io::Read;
+    ///
+    /// let filter = Filter::new(&Query::parse("DEBUG")?)?;
+    /// let mut reader = filter.reader("INFO start\nDEBUG tick\nERROR stop\n".as_bytes());
+    ///
+    /// let mut output = String::new();
+    /// reader.read_to_string(&mut output)?;
+    /// assert_eq!(output, "INFO start\nERROR stop\n");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn reader<R: BufRead>(self, reader: R) -> FilteredReader<R> {
+        FilteredReader::new(reader, self)
+    }
+
+    /// Returns a [`Stream`](futures_core::Stream) of the lines of an async `reader` the filter
+    /// keeps, like [`lines`](Filter::lines). Requires the `tokio` feature.
+    ///
+    /// ```
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// use chaf::{Filter, Query};
+    /// use std::future::poll_fn;
+    /// use std::pin::Pin;
+    /// use futures_core::Stream;
+    ///
+    /// let filter = Filter::new(&Query::parse("DEBUG")?)?;
+    /// let mut stream = filter.stream("INFO start\nDEBUG tick\n".as_bytes());
+    ///
+    /// let line = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await.unwrap()?;
+    /// assert_eq!(line, b"INFO start\n");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// # }).unwrap();
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn stream<R>(self, reader: R) -> LineStream<R>
+    where
+        R: tokio::io::AsyncBufRead + Unpin,
+    {
+        LineStream::new(self, reader)
+    }
+}
+
+/// Options for a [`Filter`].
+///
+/// ```
+/// use chaf::{Filter, Format, Query};
+///
+/// let query = Query::parse("level=error")?;
+/// let filter = Filter::builder()
+///     .invert(true)
+///     .ignore_case(true)
+///     .format(Format::Logfmt)
+///     .build(&query)?;
+///
+/// assert!(filter.is_kept(b"level=ERROR msg=\"disk full\"")?);
+/// assert!(!filter.is_kept(b"level=info msg=ok")?);
+/// # Ok::<(), chaf::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct FilterBuilder {
+    invert: bool,
+    ignore_case: bool,
+    format: Option<Format>,
+    delimiter: Delimiter,
+    header: bool,
+    on_invalid: InvalidPolicy,
+    seed: u64,
+}
+
+impl FilterBuilder {
+    /// Keeps the lines the query matches and drops the rest, like `grep`.
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    /// Makes literals and text comparisons ignore case.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    /// How lines are parsed for field predicates. Defaults to the format the query was parsed
+    /// for; a different format parses the query again.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// What to do with lines that cannot be parsed in the format.
+    pub fn on_invalid(mut self, on_invalid: InvalidPolicy) -> Self {
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    /// How plain text lines are split into `$N` fields.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// If `true`, the first line is always kept and names the columns used by `col(name)`.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Seed for `sample(...)`; the same seed samples the same lines.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Compiles `query` with these options. Fails if the query cannot be used with them, such as
+    /// `col(...)` without a header row.
+    pub fn build(self, query: &Query) -> Result<Filter> {
+        let format = self.format.unwrap_or(query.format());
+        let reparsed;
+        let query = if format == query.format() {
+            query
+        } else {
+            reparsed = query.reparse(format)?;
+            &reparsed
+        };
+        let options = RecordOptions::new(self.delimiter, self.header)
+            .with_format(format, self.on_invalid)
+            .with_seed(self.seed)
+            .with_ignore_case(self.ignore_case);
+        Ok(Filter {
+            inner: LineFilter::new(query.ast(), self.invert, options)?,
+            invert: self.invert,
+            multiline: format.is_multiline(),
+            line_no: AtomicUsize::new(0),
+        })
+    }
+}
@@ -0,0 +1,97 @@
+use super::Filter;
+use crate::engine::read_record;
+use std::io::{self, BufRead, Read};
+
+/// An iterator over the lines of a reader that a [`Filter`] keeps; see [`Filter::lines`].
+pub struct Lines<R> {
+    filter: Filter,
+    reader: R,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub(super) fn new(filter: Filter, reader: R) -> Self {
+        Self { filter, reader }
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut line = Vec::new();
+            match read_record(
+                &mut self.reader,
+                &mut line,
+                self.filter.multiline,
+                usize::MAX,
+            ) {
+                Ok((0, _)) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            match self.filter.is_kept(&line) {
+                Ok(true) => return Some(Ok(line)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+            }
+        }
+    }
+}
+
+/// A reader that yields only the lines a [`Filter`] keeps; see [`Filter::reader`].
+pub struct FilteredReader<R> {
+    lines: Lines<R>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> FilteredReader<R> {
+    /// Wraps `reader` so that reading from it yields only the lines `filter` keeps.
+    pub fn new(reader: R, filter: Filter) -> Self {
+        Self {
+            lines: Lines::new(filter, reader),
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// The filter, e.g. for [`Filter::line_no`].
+    pub fn filter(&self) -> &Filter {
+        &self.lines.filter
+    }
+
+    /// Returns the underlying reader. Buffered lines that were not read yet are lost.
+    pub fn into_inner(self) -> R {
+        self.lines.reader
+    }
+}
+
+impl<R: BufRead> BufRead for FilteredReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.line.len() {
+            match self.lines.next() {
+                Some(line) => {
+                    self.line = line?;
+                    self.pos = 0;
+                }
+                None => return Ok(&[]),
+            }
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+impl<R: BufRead> Read for FilteredReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,94 @@
+use super::Filter;
+use futures_core::Stream;
+use memchr::memchr;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncBufRead;
+
+/// A [`Stream`] of the lines of an async reader that a [`Filter`] keeps; see [`Filter::stream`].
+///
+/// The reader is only read while the stream is polled, so a slow consumer holds back the input,
+/// and dropping the stream drops the reader.
+pub struct LineStream<R> {
+    filter: Filter,
+    reader: R,
+    // The record being read; it survives `Poll::Pending` between polls.
+    buffer: Vec<u8>,
+    // Where the current line of a multi-line record starts in `buffer`.
+    line_start: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> LineStream<R> {
+    pub(super) fn new(filter: Filter, reader: R) -> Self {
+        Self {
+            filter,
+            reader,
+            buffer: Vec::new(),
+            line_start: 0,
+        }
+    }
+
+    /// The filter, e.g. for [`Filter::line_no`].
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns the underlying reader. A partly read line is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // Reads the rest of the next record into `buffer`, as `read_record` in the engine does.
+    // Returns `false` if the input ended before a record started.
+    fn poll_record(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        loop {
+            let available = ready!(Pin::new(&mut self.reader).poll_fill_buf(cx))?;
+            if available.is_empty() {
+                return Poll::Ready(Ok(!self.buffer.is_empty()));
+            }
+            let (len, done) = match memchr(b'\n', available) {
+                Some(i) => (i + 1, true),
+                None => (available.len(), false),
+            };
+            self.buffer.extend_from_slice(&available[..len]);
+            Pin::new(&mut self.reader).consume(len);
+            if !done {
+                continue;
+            }
+
+            // Handle CRLF: convert \r\n to \n
+            if self.buffer.ends_with(b"\r\n") {
+                self.buffer.truncate(self.buffer.len() - 2);
+                self.buffer.push(b'\n');
+            }
+            let line = &self.buffer[self.line_start..];
+            if !self.filter.multiline || line == b"\n" {
+                return Poll::Ready(Ok(true));
+            }
+            self.line_start = self.buffer.len();
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for LineStream<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if !ready!(this.poll_record(cx))? {
+                return Poll::Ready(None);
+            }
+            let record = std::mem::take(&mut this.buffer);
+            this.line_start = 0;
+            match this.filter.is_kept(&record) {
+                Ok(true) => return Poll::Ready(Some(Ok(record))),
+                Ok(false) => {}
+                Err(e) => {
+                    return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))))
+                }
+            }
+        }
+    }
+}
@@ -6,3 +6,18 @@ pub mod cli;
 pub mod core;
 #[doc(hidden)]
 pub mod engine;
+mod error;
+mod filter;
+mod query;
+#[cfg(feature = "tui")]
+#[doc(hidden)]
+pub mod tui;
+
+pub use crate::core::ast;
+pub use crate::core::parser::{QueryLimits, QueryParams};
+pub use crate::core::record::{Delimiter, Format, InvalidPolicy};
+pub use crate::error::{Error, Result};
+#[cfg(feature = "tokio")]
+pub use crate::filter::LineStream;
+pub use crate::filter::{Filter, FilterBuilder, FilteredReader, Lines};
+pub use crate::query::Query;
@@ -1,24 +1,93 @@
-mod cli;
-mod core;
-mod engine;
+mod commands;
 mod util;
 
-use crate::cli::parse_args;
-use crate::core::filter::build_filter;
-use crate::core::parser::parse_query;
 use crate::util::init_logging;
+use chaf::ast::AstNode;
+use chaf::cli::{parse_args, query_params, Command, FilterOptions};
+use chaf::core::bytecode::Program;
+use chaf::core::config::{resolve_profiles, Config};
+use chaf::core::filter::{build_filter_with, is_stateful};
+#[cfg(feature = "serde")]
+use chaf::core::parser::query_from_ast;
+use chaf::core::parser::{parse_query_with_params, with_time_window, QueryLimits, QueryParams};
+use chaf::core::query_file::QueryFile;
+use chaf::core::record::{Format, InvalidPolicy, RecordOptions};
+use chaf::core::time::TimeOptions;
+use chaf::engine;
 use std::fs::File;
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, BufWriter, Write};
 use tracing::{debug, info};
 
 fn main() {
     init_logging();
     info!("chaf started");
 
-    let opts = parse_args();
-    debug!("line options: {:?}", opts);
+    let command = parse_args();
+    debug!("line options: {:?}", command);
 
-    let ast = match parse_query(&opts.query) {
+    let status = match command {
+        Command::Filter(opts) => {
+            filter(*opts);
+            return;
+        }
+        Command::Explain(opts) => commands::explain(&opts),
+        Command::Fmt(opts) => commands::fmt(&opts),
+        Command::Check(opts) => commands::check(&opts),
+        Command::Test(opts) => commands::test(&opts),
+        Command::Completions(opts) => commands::completions(&opts),
+        #[cfg(feature = "tui")]
+        Command::Tui(opts) => commands::tui(&opts),
+    };
+    info!("chaf ended with status {status}");
+    std::process::exit(status);
+}
+
+// `chaf filter`, the default command.
+fn filter(mut opts: FilterOptions) {
+    let limits = QueryLimits {
+        max_depth: opts.max_depth,
+        max_terms: opts.max_terms,
+    };
+    let params = query_params(&opts.params);
+    let ast = match opts.profile.clone() {
+        Some(name) => load_profile(&mut opts, &name, limits, &params),
+        None => {
+            let format = opts.format.unwrap_or_default();
+            let ast = match read_query(&opts, format, limits, &params) {
+                Ok(ast) => ast,
+                Err(e) => {
+                    eprintln!("Syntax error: {e}");
+                    info!("chaf ended with error");
+                    std::process::exit(1);
+                }
+            };
+            resolve_profiles(ast, format, limits, &params)
+        }
+    };
+    let ast = match ast {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprintln!("Profile error: {e}");
+            info!("chaf ended with error");
+            std::process::exit(1);
+        }
+    };
+    let format = opts.format.unwrap_or_default();
+    #[cfg(feature = "serde")]
+    if opts.emit_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&ast).expect("a syntax tree is valid JSON")
+        );
+        info!("chaf ended successfully");
+        return;
+    }
+    let ast = match with_time_window(
+        ast,
+        opts.since.as_deref(),
+        opts.until.as_deref(),
+        opts.invert,
+    ) {
         Ok(ast) => ast,
         Err(e) => {
             eprintln!("Syntax error: {e}");
@@ -28,7 +97,26 @@ fn main() {
     };
     debug!("Parsed AST: {:?}", ast);
 
-    let filter = match build_filter(&ast, opts.invert) {
+    let time_options = TimeOptions {
+        pattern: opts.time_format.clone(),
+        zone: opts.timezone,
+    };
+    let seed = opts.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let record_options = RecordOptions::new(opts.delimiter, opts.header)
+        .with_format(format, opts.on_invalid)
+        .with_time(time_options.clone())
+        .with_seed(seed)
+        .with_ignore_case(opts.ignore_case);
+    if opts.dump_bytecode {
+        print!("{}", Program::compile(&ast, &record_options, opts.invert));
+        info!("chaf ended successfully");
+        return;
+    }
+    let filter = match build_filter_with(&ast, opts.invert, record_options) {
         Ok(filter) => filter,
         Err(e) => {
             eprintln!("Filter build error: {e}");
@@ -37,24 +125,125 @@ fn main() {
         }
     };
 
-    let reader: Box<dyn io::BufRead> = match &opts.input_file {
+    let stop_when = match &opts.stop_when {
+        Some(query) => {
+            let stop_ast = match parse_query_with_params(query, format, limits, &params) {
+                Ok(ast) => ast,
+                Err(e) => {
+                    eprintln!("Syntax error in --stop-when: {e}");
+                    info!("chaf ended with error");
+                    std::process::exit(1);
+                }
+            };
+            // invert=true makes the filter return the raw match result
+            let stop_options = RecordOptions::new(opts.delimiter, false)
+                .with_format(format, InvalidPolicy::Raw)
+                .with_time(time_options.clone())
+                .with_ignore_case(opts.ignore_case);
+            match build_filter_with(&stop_ast, true, stop_options) {
+                Ok(matcher) => Some(Box::new(matcher) as engine::StopCondition),
+                Err(e) => {
+                    eprintln!("Filter build error: {e}");
+                    info!("chaf ended with error");
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Buffer output for files; stdin stays line-buffered so `tail -f | chaf` shows lines as they come
+    let mut writer: Box<dyn Write> = match opts.input_file {
+        Some(_) => Box::new(BufWriter::with_capacity(1 << 16, io::stdout().lock())),
+        None => Box::new(io::stdout()),
+    };
+
+    let options = engine::RunOptions {
+        report: opts.report,
+        max_count: opts.max_count,
+        stop_when,
+        multiline: format.is_multiline(),
+        dedup: engine::DedupOptions {
+            uniq: opts.uniq.then_some(opts.uniq_cap),
+            collapse: opts.collapse,
+            normalize: opts.normalize,
+        },
+        line_limit: Some(engine::LineLimit {
+            max: opts.max_line_length,
+            policy: opts.on_long_line,
+        }),
+    };
+
+    let jobs = match opts.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let parallel = jobs > 1 && !opts.header && !is_stateful(&ast);
+
+    let result = match &opts.input_file {
         Some(path) => match File::open(path) {
-            Ok(file) => Box::new(BufReader::new(file)),
+            Ok(file) if parallel => engine::run_parallel(file, &mut writer, filter, &options, jobs),
+            Ok(file) if opts.mmap => engine::run_mmap(file, &mut writer, filter, &options),
+            // A mapped file that shrinks under us faults, so files are only mapped on request
+            Ok(file) => {
+                engine::run_filter_with(BufReader::new(file), &mut writer, filter, &options)
+            }
             Err(e) => {
                 eprintln!("Failed to open file: {e} at path: {}", path.display());
                 info!("chaf ended with error");
                 std::process::exit(1);
             }
         },
-        None => Box::new(BufReader::new(io::stdin())),
+        None => engine::run_filter_with(io::stdin().lock(), &mut writer, filter, &options),
     };
 
-    let mut writer = io::stdout();
-
-    if let Err(e) = engine::run_filter(reader, &mut writer, filter, opts.report) {
+    let result = result.and_then(|_| writer.flush().map_err(Into::into));
+    if let Err(e) = result {
         eprintln!("Runtime error: {e}");
         info!("chaf ended with error");
         std::process::exit(1);
     }
     info!("chaf ended successfully");
 }
+
+// The query of a profile. Its options apply where the command line leaves them unset.
+fn load_profile(
+    opts: &mut FilterOptions,
+    name: &str,
+    limits: QueryLimits,
+    params: &QueryParams,
+) -> Result<AstNode, chaf::Error> {
+    let config = Config::load(&std::env::current_dir()?)?;
+    let profile = config.profile(name)?;
+    opts.format = opts.format.or(profile.format);
+    opts.invert |= profile.invert;
+    opts.ignore_case |= profile.ignore_case;
+    config.profile_query(name, opts.format.unwrap_or_default(), limits, params)
+}
+
+// Parses QUERY or --query-file, or reads the syntax tree from --query-json.
+fn read_query(
+    opts: &FilterOptions,
+    format: Format,
+    limits: QueryLimits,
+    params: &QueryParams,
+) -> Result<AstNode, chaf::Error> {
+    #[cfg(feature = "serde")]
+    if let Some(path) = &opts.query_json {
+        let json = std::fs::read_to_string(path)?;
+        let ast: AstNode = serde_json::from_str(&json).map_err(|e| {
+            chaf::Error::InvalidOption(format!("Invalid JSON query in {}: {}", path.display(), e))
+        })?;
+        query_from_ast(&ast, format, limits)?;
+        return Ok(ast);
+    }
+    if let Some(path) = &opts.query_file {
+        let file = QueryFile::read(path)?;
+        return parse_query_with_params(file.query(), format, limits, params);
+    }
+    let query = opts
+        .query
+        .as_deref()
+        .expect("QUERY is required without --query-file or --query-json");
+    parse_query_with_params(query, format, limits, params)
+}
@@ -0,0 +1,136 @@
+use crate::core::ast::AstNode;
+use crate::core::filter::is_stateful;
+use crate::core::parser::{parse_query_with_params, query_from_ast, QueryLimits, QueryParams};
+use crate::core::record::Format;
+use crate::error::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed query.
+///
+/// ```
+/// use chaf::Query;
+///
+/// let query = Query::parse("ERROR & !healthcheck")?;
+/// assert_eq!(query.as_str(), "ERROR & !healthcheck");
+///
+/// let err = Query::parse("ERROR &").unwrap_err();
+/// assert!(matches!(err, chaf::Error::Syntax { .. }));
+/// # Ok::<(), chaf::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Query {
+    text: String,
+    format: Format,
+    limits: QueryLimits,
+    params: QueryParams,
+    ast: AstNode,
+}
+
+impl Query {
+    /// Parses a query for plain text lines.
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_for(text, Format::Plain)
+    }
+
+    /// Parses a query for lines in `format`. In formats with named fields, such as
+    /// [`Format::Logfmt`], `level=debug` compares the `level` field instead of matching the literal.
+    ///
+    /// ```
+    /// use chaf::ast::{AstNode, Pattern};
+    /// use chaf::{Format, Query};
+    ///
+    /// let query = Query::parse_for("level=debug", Format::Logfmt)?;
+    /// assert!(matches!(query.ast(), AstNode::Match(Pattern::Compare(..))));
+    /// # Ok::<(), chaf::Error>(())
+    /// ```
+    pub fn parse_for(text: &str, format: Format) -> Result<Self> {
+        Self::parse_with(text, format, QueryLimits::default())
+    }
+
+    /// Parses a query with explicit bounds on its size.
+    pub fn parse_with(text: &str, format: Format, limits: QueryLimits) -> Result<Self> {
+        Self::parse_with_params(text, format, limits, &QueryParams::default())
+    }
+
+    /// Parses a query whose `$name` and `${VAR}` placeholders take their values from `params`
+    /// (see [`QueryParams`]). With the other constructors, any placeholder is a syntax error.
+    pub fn parse_with_params(
+        text: &str,
+        format: Format,
+        limits: QueryLimits,
+        params: &QueryParams,
+    ) -> Result<Self> {
+        Ok(Self {
+            ast: parse_query_with_params(text, format, limits, params)?,
+            text: text.to_string(),
+            format,
+            limits,
+            params: params.clone(),
+        })
+    }
+
+    /// Builds a query from a syntax tree, such as one read from JSON with the `serde` feature.
+    /// Fails if the tree is not one a query in `format` parses into, e.g. a `sample(...)` rate
+    /// above 1.
+    ///
+    /// ```
+    /// use chaf::ast::{AstNode, Pattern};
+    /// use chaf::{Format, Query};
+    ///
+    /// let ast = AstNode::NotNode(Box::new(AstNode::Match(Pattern::Literal("a b".into()))));
+    /// let query = Query::from_ast(ast, Format::Plain)?;
+    /// assert_eq!(query.as_str(), r#"!"a b""#);
+    /// # Ok::<(), chaf::Error>(())
+    /// ```
+    pub fn from_ast(ast: AstNode, format: Format) -> Result<Self> {
+        let limits = QueryLimits::default();
+        Ok(Self {
+            text: query_from_ast(&ast, format, limits)?,
+            format,
+            limits,
+            params: QueryParams::default(),
+            ast,
+        })
+    }
+
+    /// The query as written.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The format the query was parsed for.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The syntax tree of the query.
+    pub fn ast(&self) -> &AstNode {
+        &self.ast
+    }
+
+    /// Returns `true` if the result for a line depends on previous lines (`between(...)`, `time`,
+    /// `sample(...)`, `limit(...)`), so lines must be filtered in order.
+    pub fn is_stateful(&self) -> bool {
+        is_stateful(&self.ast)
+    }
+
+    // The same query, parsed again for another format.
+    pub(crate) fn reparse(&self, format: Format) -> Result<Self> {
+        Self::parse_with_params(&self.text, format, self.limits, &self.params)
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
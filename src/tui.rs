@@ -0,0 +1,362 @@
+use crate::core::config::resolve_profiles;
+use crate::core::evaluator::EvalContext;
+use crate::core::filter::LineFilter;
+use crate::core::parser::{parse_query_with_params, QueryLimits, QueryParams};
+use crate::core::query_file::QueryFile;
+use crate::core::record::{Delimiter, Format, InvalidPolicy, RecordOptions};
+use crate::error::Error;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Lines filtered between two looks at the keyboard, so typing stays responsive on large previews.
+const CHUNK: usize = 10_000;
+
+// How the query of `chaf tui` is parsed and run, as the options of `chaf filter`.
+#[derive(Debug, Clone, Default)]
+pub struct TuiSettings {
+    pub format: Format,
+    pub invert: bool,
+    pub ignore_case: bool,
+    pub params: QueryParams,
+}
+
+// `chaf tui`: a query editor over a preview of the lines it keeps. The query is run again on
+// every keystroke; the last query that parsed stays in effect while the current one has errors.
+//
+// Lines are filtered a chunk at a time by `step`, between key presses, so a keystroke never
+// waits for the whole preview.
+pub struct App {
+    lines: Vec<String>,
+    more: bool, // Whether the file has lines past the preview
+    settings: TuiSettings,
+    query: String,
+    cursor: usize, // Byte offset into `query`
+    // The filter of the last query that parsed, if any
+    filter: Option<LineFilter>,
+    // Whether each line filtered so far is kept, and how many of them are
+    kept: Vec<bool>,
+    kept_count: usize,
+    error: Option<Error>,
+    save_path: PathBuf,
+    message: Option<String>,
+    scroll: usize,
+    page: usize, // Rows in the preview, as last drawn
+    quit: bool,
+}
+
+impl App {
+    pub fn new(
+        lines: Vec<String>,
+        query: String,
+        save_path: PathBuf,
+        settings: TuiSettings,
+    ) -> Self {
+        let mut app = Self {
+            lines,
+            more: false,
+            settings,
+            cursor: query.len(),
+            query,
+            filter: None,
+            kept: Vec::new(),
+            kept_count: 0,
+            error: None,
+            save_path,
+            message: None,
+            scroll: 0,
+            page: 1,
+            quit: false,
+        };
+        app.refilter();
+        app
+    }
+
+    // Marks the preview as the first lines of a longer file.
+    pub fn truncated(mut self, more: bool) -> Self {
+        self.more = more;
+        self
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    // The numbers of kept and excluded lines among those filtered so far. Every line is kept
+    // until a query parses.
+    pub fn counts(&self) -> (usize, usize) {
+        match &self.filter {
+            Some(_) => (self.kept_count, self.kept.len() - self.kept_count),
+            None => (self.lines.len(), 0),
+        }
+    }
+
+    // Whether lines remain to be filtered with the current query.
+    pub fn is_filtering(&self) -> bool {
+        self.filter.is_some() && self.kept.len() < self.lines.len()
+    }
+
+    // Filters up to `n` more lines with the current query.
+    pub fn step(&mut self, n: usize) {
+        let Some(filter) = &self.filter else {
+            return;
+        };
+        let start = self.kept.len();
+        let end = (start + n).min(self.lines.len());
+        for (i, line) in self.lines[start..end].iter().enumerate() {
+            // A line the filter cannot evaluate is shown as kept, as it is not excluded
+            let kept = filter
+                .keep(line.as_bytes(), &EvalContext::new(start + i + 1))
+                .unwrap_or(true);
+            self.kept.push(kept);
+            self.kept_count += usize::from(kept);
+        }
+    }
+
+    // Lines not filtered yet are shown as kept.
+    fn is_kept(&self, i: usize) -> bool {
+        self.filter.is_none() || self.kept.get(i).is_none_or(|&kept| kept)
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        self.message = None;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let edited = match key.code {
+            KeyCode::Esc => {
+                self.quit = true;
+                false
+            }
+            KeyCode::Char('c') if ctrl => {
+                self.quit = true;
+                false
+            }
+            KeyCode::Char('s') if ctrl => {
+                self.save();
+                false
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+                true
+            }
+            KeyCode::Backspace => match self.query[..self.cursor].chars().next_back() {
+                Some(c) => {
+                    self.cursor -= c.len_utf8();
+                    self.query.remove(self.cursor);
+                    true
+                }
+                None => false,
+            },
+            KeyCode::Delete if self.cursor < self.query.len() => {
+                self.query.remove(self.cursor);
+                true
+            }
+            KeyCode::Left => {
+                if let Some(c) = self.query[..self.cursor].chars().next_back() {
+                    self.cursor -= c.len_utf8();
+                }
+                false
+            }
+            KeyCode::Right => {
+                if let Some(c) = self.query[self.cursor..].chars().next() {
+                    self.cursor += c.len_utf8();
+                }
+                false
+            }
+            KeyCode::Home => {
+                self.cursor = 0;
+                false
+            }
+            KeyCode::End => {
+                self.cursor = self.query.len();
+                false
+            }
+            KeyCode::Up => self.scroll_by(-1),
+            KeyCode::Down => self.scroll_by(1),
+            KeyCode::PageUp => self.scroll_by(-(self.page as isize)),
+            KeyCode::PageDown => self.scroll_by(self.page as isize),
+            _ => false,
+        };
+        if edited {
+            self.refilter();
+        }
+    }
+
+    fn scroll_by(&mut self, rows: isize) -> bool {
+        let last = self.lines.len().saturating_sub(self.page);
+        self.scroll = self.scroll.saturating_add_signed(rows).min(last);
+        false
+    }
+
+    fn refilter(&mut self) {
+        if self.query.trim().is_empty() {
+            self.filter = None;
+            self.error = None;
+            return;
+        }
+        let options = RecordOptions::new(Delimiter::default(), false)
+            .with_format(self.settings.format, InvalidPolicy::Raw)
+            .with_ignore_case(self.settings.ignore_case);
+        let limits = QueryLimits::default();
+        let (format, params) = (self.settings.format, &self.settings.params);
+        let filter = parse_query_with_params(&self.query, format, limits, params)
+            .and_then(|ast| resolve_profiles(ast, format, limits, params))
+            .and_then(|ast| LineFilter::new(&ast, self.settings.invert, options));
+        match filter {
+            Ok(filter) => {
+                self.filter = Some(filter);
+                self.kept.clear();
+                self.kept_count = 0;
+                self.error = None;
+                self.step(CHUNK);
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // Writes the query to the query file, keeping the comments already in it.
+    pub fn save(&mut self) {
+        if self.query.trim().is_empty() {
+            self.message = Some("Not saved: the query is empty".to_string());
+            return;
+        }
+        if let Some(e) = &self.error {
+            self.message = Some(format!("Not saved: {}", e));
+            return;
+        }
+        let text = match std::fs::read_to_string(&self.save_path) {
+            Ok(text) => QueryFile::new(&text).with_query(&self.query),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(format!("{}\n", self.query)),
+            Err(e) => Err(e.into()),
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                self.message = Some(format!("Not saved: {}", e));
+                return;
+            }
+        };
+        self.message = Some(match std::fs::write(&self.save_path, text) {
+            Ok(()) => format!("Saved to {}", self.save_path.display()),
+            Err(e) => format!("Not saved: {}", e),
+        });
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [editor, preview, status] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        // The query, and under it a caret at the position of a syntax error
+        let mut text = vec![Line::raw(self.query.as_str())];
+        if let Some(e) = &self.error {
+            let (caret, message) = match e {
+                Error::Syntax { message, pos } => {
+                    let column = self.query[..(*pos).min(self.query.len())].chars().count();
+                    (format!("{}^ ", " ".repeat(column)), message.clone())
+                }
+                e => (String::new(), e.to_string()),
+            };
+            text.push(Line::styled(
+                caret + &message,
+                Style::default().fg(Color::Red),
+            ));
+        }
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(" Query ")),
+            editor,
+        );
+        let column = self.query[..self.cursor].chars().count() as u16;
+        frame.set_cursor_position(Position::new(
+            (editor.x + 1 + column).min(editor.right().saturating_sub(2)),
+            editor.y + 1,
+        ));
+
+        // The lines in view, excluded ones dimmed
+        self.page = preview.height.max(1) as usize;
+        self.scroll = self.scroll.min(self.lines.len().saturating_sub(self.page));
+        let width = self.lines.len().to_string().len();
+        let rows = self
+            .lines
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(self.page)
+            .map(|(i, line)| {
+                let style = if self.is_kept(i) {
+                    Style::default()
+                } else {
+                    Style::default().add_modifier(Modifier::DIM)
+                };
+                Line::from(vec![
+                    Span::styled(
+                        format!("{:>width$} ", i + 1),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(line.as_str(), style),
+                ])
+            })
+            .collect::<Vec<_>>();
+        frame.render_widget(Paragraph::new(rows), preview);
+
+        let (kept, excluded) = self.counts();
+        let mut counts = format!("kept {} | excluded {}", kept, excluded);
+        if self.is_filtering() {
+            counts += " | filtering...";
+        } else if self.more {
+            counts += &format!(" of the first {} lines", self.lines.len());
+        }
+        let help = self
+            .message
+            .clone()
+            .unwrap_or_else(|| format!("Ctrl-S: save to {} | Esc: quit", self.save_path.display()));
+        frame.render_widget(
+            Paragraph::new(format!("{} | {}", counts, help))
+                .style(Style::default().add_modifier(Modifier::REVERSED)),
+            status,
+        );
+    }
+}
+
+// Runs `app` in the terminal until it quits, restoring the terminal afterwards.
+pub fn run(app: &mut App) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run_in(&mut terminal, app);
+    ratatui::restore();
+    result
+}
+
+fn run_in(terminal: &mut DefaultTerminal, app: &mut App) -> io::Result<()> {
+    while !app.should_quit() {
+        terminal.draw(|frame| app.draw(frame))?;
+        // Keys come first; lines are filtered while none is waiting
+        if app.is_filtering() && !event::poll(Duration::ZERO)? {
+            app.step(CHUNK);
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            app.handle_key(key);
+        }
+    }
+    Ok(())
+}
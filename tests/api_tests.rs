@@ -0,0 +1,149 @@
+use chaf::ast::{AstNode, Key, Pattern};
+use chaf::{Delimiter, Error, Filter, Format, InvalidPolicy, Query, QueryLimits};
+use std::io::{BufRead, ErrorKind, Read};
+
+#[test]
+fn test_query_parse_and_display() {
+    let query: Query = "ERROR & !healthcheck".parse().unwrap();
+    assert_eq!(query.to_string(), "ERROR & !healthcheck");
+    assert_eq!(query.format(), Format::Plain);
+    assert!(matches!(query.ast(), AstNode::AndNode(..)));
+    assert!(!query.is_stateful());
+    assert!(Query::parse("between(BEGIN, END)").unwrap().is_stateful());
+}
+
+#[test]
+fn test_query_errors() {
+    match Query::parse("(ERROR").unwrap_err() {
+        Error::Syntax { pos, .. } => assert_eq!(pos, 6),
+        e => panic!("unexpected error: {e}"),
+    }
+
+    let limits = QueryLimits {
+        max_depth: 2,
+        ..QueryLimits::default()
+    };
+    let err = Query::parse_with("(((a)))", Format::Plain, limits).unwrap_err();
+    assert!(err.to_string().starts_with("Query nests too deeply"));
+
+    let err = "json".parse::<Format>().unwrap_err();
+    assert!(matches!(err, Error::InvalidOption(_)));
+}
+
+#[test]
+fn test_filter_counts_lines() {
+    let query = Query::parse("line in 2..=3").unwrap();
+    let filter = Filter::new(&query).unwrap();
+
+    let kept: Vec<bool> = ["a", "b", "c", "d"]
+        .iter()
+        .map(|line| filter.is_kept(line.as_bytes()).unwrap())
+        .collect();
+    assert_eq!(kept, [true, false, false, true]);
+    assert_eq!(filter.line_no(), 4);
+}
+
+#[test]
+fn test_filter_invert_and_matches() {
+    let query = Query::parse("timeout").unwrap();
+    let filter = Filter::builder().invert(true).build(&query).unwrap();
+
+    assert!(filter.is_kept(b"upstream timeout").unwrap());
+    assert!(!filter.is_kept(b"ok").unwrap());
+    assert!(filter.matches("timeout again"));
+    assert!(!filter.matches("ok again"));
+    assert!(matches!(filter.is_kept(&[0xff]), Err(Error::InvalidUtf8)));
+}
+
+#[test]
+fn test_filter_ignore_case() {
+    let query = Query::parse("error | $2 = timeout").unwrap();
+    let exact = Filter::new(&query).unwrap();
+    let folded = Filter::builder().ignore_case(true).build(&query).unwrap();
+
+    for line in ["ERROR disk", "x TimeOut"] {
+        assert!(exact.is_kept(line.as_bytes()).unwrap());
+        assert!(!folded.is_kept(line.as_bytes()).unwrap());
+    }
+}
+
+#[test]
+fn test_filter_builder_format_reparses_query() {
+    let query = Query::parse("level=debug").unwrap();
+    assert!(matches!(query.ast(), AstNode::Match(Pattern::Literal(_))));
+
+    let filter = Filter::builder()
+        .format(Format::Logfmt)
+        .on_invalid(InvalidPolicy::Drop)
+        .build(&query)
+        .unwrap();
+    assert!(!filter.is_kept(b"msg=tick level=debug").unwrap());
+    assert!(filter.is_kept(b"msg=\"level=debug\" level=info").unwrap());
+}
+
+#[test]
+fn test_filter_builder_columns() {
+    let query = Query::parse("col(status) = 500").unwrap();
+    assert!(matches!(
+        query.ast(),
+        AstNode::Match(Pattern::Compare(Key::Column(_), ..))
+    ));
+    assert!(matches!(
+        Filter::new(&query).err().unwrap(),
+        Error::Unsupported(_)
+    ));
+
+    let filter = Filter::builder()
+        .delimiter(Delimiter::Char(','))
+        .header(true)
+        .build(&query)
+        .unwrap();
+    assert!(filter.is_kept(b"id,status").unwrap());
+    assert!(!filter.is_kept(b"1,500").unwrap());
+    assert!(filter.is_kept(b"2,200").unwrap());
+}
+
+#[test]
+fn test_lines_adapter() {
+    let filter = Filter::new(&Query::parse("line = 2 | DEBUG").unwrap()).unwrap();
+    let input = b"one\r\ntwo\nDEBUG\n\xff\nlast".as_slice();
+
+    let lines: Vec<_> = filter.lines(input).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0].as_ref().unwrap(), b"one\n");
+    assert_eq!(
+        lines[1].as_ref().unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+    assert_eq!(lines[2].as_ref().unwrap(), b"last");
+}
+
+#[test]
+fn test_lines_adapter_multiline_records() {
+    let query = Query::parse_for("PRIORITY=7", Format::JournalExport).unwrap();
+    let filter = Filter::new(&query).unwrap();
+    let input = b"MESSAGE=a\nPRIORITY=7\n\nMESSAGE=b\nPRIORITY=3\n\n".as_slice();
+
+    let records: Vec<Vec<u8>> = filter.lines(input).map(Result::unwrap).collect();
+    assert_eq!(records, [b"MESSAGE=b\nPRIORITY=3\n\n".to_vec()]);
+}
+
+#[test]
+fn test_filtered_reader() {
+    let filter = Filter::builder()
+        .invert(true)
+        .build(&Query::parse("ERROR").unwrap())
+        .unwrap();
+    let input = "INFO a\nERROR b\nINFO c\nERROR d\n".as_bytes();
+    let mut reader = filter.reader(input);
+
+    // Reads smaller than a line continue where the last one stopped
+    let mut chunk = [0u8; 3];
+    reader.read_exact(&mut chunk).unwrap();
+    assert_eq!(&chunk, b"ERR");
+
+    let rest: Vec<String> = reader.by_ref().lines().map(Result::unwrap).collect();
+    assert_eq!(rest, ["OR b", "ERROR d"]);
+    assert_eq!(reader.filter().line_no(), 4);
+    assert!(reader.into_inner().is_empty());
+}
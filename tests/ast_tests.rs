@@ -0,0 +1,236 @@
+use chaf::ast::{AstNode, BlockBounds, CompareOp, Key, Pattern, Thinning};
+use chaf::{Format, Query};
+use proptest::prelude::*;
+use std::ops::Bound;
+use std::time::Duration;
+
+fn parse(text: &str, format: Format) -> AstNode {
+    Query::parse_for(text, format)
+        .unwrap_or_else(|e| panic!("{e}: {text}"))
+        .ast()
+        .clone()
+}
+
+#[test]
+fn test_print_minimal_parentheses() {
+    for (query, printed) in [
+        ("a&b|c", "a & b | c"),
+        ("a & (b | c)", "a & (b | c)"),
+        ("a | (b | c)", "a | (b | c)"),
+        ("(a | b) | c", "a | b | c"),
+        ("!(a & b) & !!c", "!(a & b) & !!c"),
+        ("connect DB", "connectDB"),
+        (r#""a b" | "say \"hi\"""#, r#""a b" | "say \"hi\"""#),
+        (
+            "$3~error & col(\"http status\")>=500",
+            r#"$3 ~ error & col("http status") >= 500"#,
+        ),
+        (
+            ".\"a b\".c in [1, 2) | line in ..=20",
+            r#"."a b".c in 1..2 | line in ..=20"#,
+        ),
+        ("time in (14:00, 15:00]", "time in (14:00, 15:00]"),
+        (
+            "between(BEGIN, \"END,\", exclusive)",
+            r#"between(BEGIN, "END,", exclusive)"#,
+        ),
+        (
+            "sample(1%, limit(100/5m, x | y))",
+            "sample(0.01, limit(100/5m, x | y))",
+        ),
+        ("limit(10/s, has($2))", "limit(10/s, has($2))"),
+    ] {
+        let ast = parse(query, Format::Plain);
+        assert_eq!(ast.to_string(), printed, "{query}");
+        assert_eq!(parse(printed, Format::Plain), ast, "{query}");
+    }
+}
+
+fn text() -> impl Strategy<Value = String> {
+    "\\PC{1,8}"
+}
+
+// Typed values of one unit, so they can be used together as range bounds.
+fn typed() -> impl Strategy<Value = (String, String)> {
+    prop_oneof![
+        ("[0-9]{1,4}", "[0-9]{1,3}\\.[0-9]{1,2}"),
+        ("[1-9][0-9]{0,2}(ms|s|m|h)", "[1-9][0-9]{0,2}(us|d|m1s)"),
+        ("[1-9][0-9]{0,2}(KB|MiB)", "[1-9](b|gb|TiB)"),
+        ("(warning|err|debug)", "(emerg|notice|info)"),
+    ]
+}
+
+fn time() -> impl Strategy<Value = (String, String)> {
+    prop_oneof![
+        (
+            "(0[0-9]|1[0-9]|2[0-3]):[0-5][0-9]",
+            "(0[0-9]|1[0-9]):[0-5][0-9]:[0-5][0-9]"
+        ),
+        ("2024-0[1-9]-[12][0-9]", "2023-1[0-2]-0[1-9]T12:00:00Z"),
+    ]
+}
+
+fn field(named: bool) -> BoxedStrategy<Key> {
+    let mut keys = vec![
+        (0..20usize).prop_map(Key::Index).boxed(),
+        text().prop_map(Key::Column).boxed(),
+        prop::collection::vec(text(), 1..3)
+            .prop_map(Key::Path)
+            .boxed(),
+    ];
+    if named {
+        keys.push(
+            "[a-z_][a-z0-9_.-]{0,6}"
+                .prop_filter("reserved", |name| name != "line" && name != "time")
+                .prop_map(Key::Name)
+                .boxed(),
+        );
+    }
+    prop::strategy::Union::new(keys).boxed()
+}
+
+fn bounds((a, b): (String, String)) -> impl Strategy<Value = (Bound<String>, Bound<String>)> {
+    let pairs = [
+        (Bound::Included(a.clone()), Bound::Excluded(b.clone())),
+        (Bound::Included(a.clone()), Bound::Included(b.clone())),
+        (Bound::Included(a.clone()), Bound::Unbounded),
+        (Bound::Unbounded, Bound::Excluded(b.clone())),
+        (Bound::Unbounded, Bound::Included(b.clone())),
+        (Bound::Excluded(a.clone()), Bound::Excluded(b.clone())),
+        (Bound::Excluded(a), Bound::Included(b)),
+    ];
+    prop::sample::select(pairs.to_vec())
+}
+
+fn pattern(named: bool) -> impl Strategy<Value = Pattern> {
+    let ordering = prop::sample::select(vec![
+        CompareOp::Lt,
+        CompareOp::Le,
+        CompareOp::Gt,
+        CompareOp::Ge,
+    ]);
+    let equality = prop::sample::select(vec![CompareOp::Eq, CompareOp::Ne, CompareOp::Contains]);
+    let any_but_contains = prop::sample::select(vec![
+        CompareOp::Lt,
+        CompareOp::Le,
+        CompareOp::Gt,
+        CompareOp::Ge,
+        CompareOp::Eq,
+        CompareOp::Ne,
+    ]);
+    prop_oneof![
+        text().prop_map(Pattern::Literal),
+        (field(named), equality, text()).prop_map(|(k, op, v)| Pattern::Compare(k, op, v)),
+        (field(named), ordering, typed()).prop_map(|(k, op, (v, _))| Pattern::Compare(k, op, v)),
+        (any_but_contains.clone(), "[0-9]{1,5}").prop_map(|(op, v)| Pattern::Compare(
+            Key::Line,
+            op,
+            v
+        )),
+        (any_but_contains, time()).prop_map(|(op, (v, _))| Pattern::Compare(Key::Time, op, v)),
+        (field(named), typed().prop_flat_map(bounds))
+            .prop_map(|(k, (lo, hi))| Pattern::InRange(k, lo, hi)),
+        (("[0-9]{1,3}", "[0-9]{1,3}").prop_flat_map(bounds)).prop_map(|(lo, hi)| Pattern::InRange(
+            Key::Line,
+            lo,
+            hi
+        )),
+        time()
+            .prop_map(|(a, _)| (a.clone(), a))
+            .prop_flat_map(bounds)
+            .prop_map(|(lo, hi)| Pattern::InRange(Key::Time, lo, hi)),
+        field(named).prop_map(Pattern::Has),
+    ]
+}
+
+fn thinning() -> impl Strategy<Value = Thinning> {
+    prop_oneof![
+        (1..=1000u32).prop_map(|n| Thinning::Sample(f64::from(n) / 1000.0)),
+        (1..1000u64, 1..100_000u64).prop_map(|(count, secs)| Thinning::Limit {
+            count,
+            per: Duration::from_secs(secs),
+        }),
+        (1..1000u64, 1..100_000u64).prop_map(|(count, ms)| Thinning::Limit {
+            count,
+            per: Duration::from_millis(ms),
+        }),
+    ]
+}
+
+fn ast(named: bool) -> impl Strategy<Value = AstNode> {
+    pattern(named)
+        .prop_map(AstNode::Match)
+        .prop_recursive(5, 32, 2, |inner| {
+            prop_oneof![
+                (inner.clone(), inner.clone())
+                    .prop_map(|(a, b)| AstNode::AndNode(Box::new(a), Box::new(b))),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(a, b)| AstNode::OrNode(Box::new(a), Box::new(b))),
+                inner.clone().prop_map(|a| AstNode::NotNode(Box::new(a))),
+                (inner.clone(), inner.clone(), any::<bool>()).prop_map(|(a, b, exclusive)| {
+                    let bounds = if exclusive {
+                        BlockBounds::Exclusive
+                    } else {
+                        BlockBounds::Inclusive
+                    };
+                    AstNode::Between(Box::new(a), Box::new(b), bounds)
+                }),
+                (inner, thinning()).prop_map(|(a, t)| AstNode::Thin(Box::new(a), t)),
+            ]
+        })
+}
+
+proptest! {
+    #[test]
+    fn prop_print_round_trips(ast in ast(false)) {
+        let printed = ast.to_string();
+        prop_assert_eq!(parse(&printed, Format::Plain), ast, "{}", printed);
+    }
+
+    #[test]
+    fn prop_print_round_trips_with_names(ast in ast(true)) {
+        let printed = ast.to_string();
+        prop_assert_eq!(parse(&printed, Format::Logfmt), ast, "{}", printed);
+    }
+
+    #[test]
+    fn prop_pretty_round_trips(ast in ast(false), width in 0usize..40) {
+        let printed = ast.pretty(width);
+        prop_assert_eq!(parse(&printed, Format::Plain), ast, "{}", printed);
+    }
+}
+
+#[test]
+fn test_pretty_wraps_long_or_chains() {
+    let ast = parse(
+        "!(DEBUG | healthcheck) & (TRACE | \"cache hit\" | \"level=debug\") | heartbeat",
+        Format::Plain,
+    );
+    assert_eq!(
+        ast.pretty(80),
+        "!(DEBUG | healthcheck) & (TRACE | \"cache hit\" | \"level=debug\") | heartbeat"
+    );
+    assert_eq!(
+        ast.pretty(40),
+        "!(DEBUG | healthcheck) & (\n    TRACE\n    | \"cache hit\"\n    | \"level=debug\"\n)\n| heartbeat"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_round_trip() {
+    let ast = parse(
+        "!(a & $2 >= 500ms) | between(.x in 1..=2, limit(5/m, y))",
+        Format::Plain,
+    );
+    let json = serde_json::to_string(&ast).unwrap();
+    assert_eq!(serde_json::from_str::<AstNode>(&json).unwrap(), ast);
+
+    let json = r#"{"NotNode":{"Match":{"Literal":"DEBUG"}}}"#;
+    let query = Query::from_ast(serde_json::from_str(json).unwrap(), Format::Plain).unwrap();
+    assert_eq!(query.as_str(), "!DEBUG");
+
+    let invalid = r#"{"Thin":[{"Match":{"Literal":"x"}},{"Sample":2.0}]}"#;
+    let err = Query::from_ast(serde_json::from_str(invalid).unwrap(), Format::Plain).unwrap_err();
+    assert!(err.to_string().contains("not a valid query"), "{err}");
+}
@@ -1,48 +1,163 @@
-use chaf::cli::CliOptions;
+use chaf::cli::{CliOptions, Command};
+use chaf::core::record::{Delimiter, Format, InvalidPolicy};
+use chaf::core::time::Zone;
 use clap::Parser;
 use std::path::PathBuf;
 
 #[test]
 fn test_parse_minimum_args() {
     let args = ["chaf", "foo"];
-    let opts = CliOptions::parse_from(&args);
-    assert_eq!(opts.query, "foo");
-    assert_eq!(opts.input_file, None);
-    assert!(!opts.invert);
-    assert!(!opts.report);
+    let opts = CliOptions::parse_from(args);
+    assert_eq!(opts.filter.query.as_deref(), Some("foo"));
+    assert_eq!(opts.filter.input_file, None);
+    assert!(!opts.filter.invert);
+    assert!(!opts.filter.report);
 }
 
 #[test]
 fn test_parse_with_file() {
     let args = ["chaf", "ERROR", "log.txt"];
-    let opts = CliOptions::parse_from(&args);
-    assert_eq!(opts.query, "ERROR");
-    assert_eq!(opts.input_file, Some(PathBuf::from("log.txt")));
+    let opts = CliOptions::parse_from(args);
+    assert_eq!(opts.filter.query.as_deref(), Some("ERROR"));
+    assert_eq!(opts.filter.input_file, Some(PathBuf::from("log.txt")));
 }
 
 #[test]
 fn test_parse_with_flags() {
     let args = ["chaf", "-i", "-r", "query", "file.log"];
-    let opts = CliOptions::parse_from(&args);
-    assert!(opts.invert);
-    assert!(opts.report);
-    assert_eq!(opts.query, "query");
-    assert_eq!(opts.input_file, Some(PathBuf::from("file.log")));
+    let opts = CliOptions::parse_from(args);
+    assert!(opts.filter.invert);
+    assert!(opts.filter.report);
+    assert_eq!(opts.filter.query.as_deref(), Some("query"));
+    assert_eq!(opts.filter.input_file, Some(PathBuf::from("file.log")));
 }
 
 #[test]
 fn test_parse_long_flags() {
     let args = ["chaf", "--invert", "--report", "x & y"];
-    let opts = CliOptions::parse_from(&args);
-    assert!(opts.invert);
-    assert!(opts.report);
-    assert_eq!(opts.query, "x & y");
-    assert_eq!(opts.input_file, None);
+    let opts = CliOptions::parse_from(args);
+    assert!(opts.filter.invert);
+    assert!(opts.filter.report);
+    assert_eq!(opts.filter.query.as_deref(), Some("x & y"));
+    assert_eq!(opts.filter.input_file, None);
 }
 
 #[test]
 fn test_missing_query_should_fail() {
     let args = ["chaf"];
-    let result = CliOptions::try_parse_from(&args);
+    let result = CliOptions::try_parse_from(args);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_parse_stop_options() {
+    let args = ["chaf", "-m", "10", "--stop-when", "shutdown", "debug"];
+    let opts = CliOptions::parse_from(args);
+    assert_eq!(opts.filter.max_count, Some(10));
+    assert_eq!(opts.filter.stop_when.as_deref(), Some("shutdown"));
+    assert_eq!(opts.filter.query.as_deref(), Some("debug"));
+}
+
+#[test]
+fn test_parse_field_options() {
+    let opts = CliOptions::parse_from(["chaf", "foo"]);
+    assert_eq!(opts.filter.delimiter, Delimiter::Whitespace);
+    assert!(!opts.filter.header);
+
+    let opts = CliOptions::parse_from(["chaf", "-d", ",", "--header", "foo"]);
+    assert_eq!(opts.filter.delimiter, Delimiter::Char(','));
+    assert!(opts.filter.header);
+}
+
+#[test]
+fn test_parse_format_options() {
+    let opts = CliOptions::parse_from(["chaf", "--format=jsonl", "--on-invalid", "drop", "x"]);
+    assert_eq!(opts.filter.format, Some(Format::Jsonl));
+    assert_eq!(opts.filter.on_invalid, InvalidPolicy::Drop);
+
+    let result = CliOptions::try_parse_from(["chaf", "--format=yaml", "x"]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_time_options() {
+    let opts = CliOptions::parse_from(["chaf", "x"]);
+    assert_eq!(opts.filter.timezone, Zone::Local);
+    assert_eq!(opts.filter.since, None);
+
+    let opts = CliOptions::parse_from([
+        "chaf",
+        "--since",
+        "14:00",
+        "--until=2024-05-02",
+        "--timezone=UTC",
+        "x",
+    ]);
+    assert_eq!(opts.filter.since.as_deref(), Some("14:00"));
+    assert_eq!(opts.filter.until.as_deref(), Some("2024-05-02"));
+    assert_eq!(opts.filter.timezone, Zone::Utc);
+
+    let result = CliOptions::try_parse_from(["chaf", "--timezone=Mars", "x"]);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_jobs() {
+    let opts = CliOptions::parse_from(["chaf", "x"]);
+    assert_eq!(opts.filter.jobs, 1);
+
+    let opts = CliOptions::parse_from(["chaf", "-j", "8", "x", "big.log"]);
+    assert_eq!(opts.filter.jobs, 8);
+}
+
+#[test]
+fn test_parse_subcommands() {
+    let opts = CliOptions::parse_from(["chaf", "x", "app.log"]);
+    assert!(opts.command.is_none());
+
+    let opts = CliOptions::parse_from(["chaf", "filter", "-i", "x", "app.log"]);
+    let Some(Command::Filter(filter)) = opts.command else {
+        panic!("expected the filter command");
+    };
+    assert!(filter.invert);
+    assert_eq!(filter.query.as_deref(), Some("x"));
+    assert_eq!(filter.input_file, Some(PathBuf::from("app.log")));
+
+    let opts = CliOptions::parse_from(["chaf", "fmt", "--check", "-F", "a.chaf", "-F", "b.chaf"]);
+    let Some(Command::Fmt(fmt)) = opts.command else {
+        panic!("expected the fmt command");
+    };
+    assert!(fmt.check);
+    assert_eq!(fmt.input.query_files.len(), 2);
+
+    // After an option of the filter command, a subcommand name is just the query
+    let opts = CliOptions::parse_from(["chaf", "-i", "check", "x"]);
+    assert!(opts.command.is_none());
+    assert_eq!(opts.filter.query.as_deref(), Some("check"));
+    assert!(CliOptions::try_parse_from(["chaf", "test"]).is_err());
+}
+
+#[test]
+fn test_parse_params() {
+    let opts = CliOptions::parse_from(["chaf", "--param", "svc=api", "--param", "q=a=b", "$svc"]);
+    assert_eq!(
+        opts.filter.params,
+        [
+            ("svc".to_string(), "api".to_string()),
+            ("q".to_string(), "a=b".to_string())
+        ]
+    );
+    assert!(CliOptions::try_parse_from(["chaf", "--param", "=x", "$svc"]).is_err());
+}
+
+#[cfg(feature = "tui")]
+#[test]
+fn test_parse_tui() {
+    let opts = CliOptions::parse_from(["chaf", "tui", "-i", "app.log"]);
+    let Some(Command::Tui(tui)) = opts.command else {
+        panic!("expected the tui command");
+    };
+    assert!(tui.invert);
+    assert_eq!(tui.file, PathBuf::from("app.log"));
+    assert_eq!(tui.query_file, PathBuf::from("query.chaf"));
+}
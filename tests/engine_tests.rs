@@ -1,4 +1,9 @@
-use chaf::engine::run_filter;
+use chaf::core::evaluator::EvalContext;
+use chaf::engine::{
+    run_filter, run_filter_with, run_mmap, run_parallel, DedupOptions, LineLimit, LongLinePolicy,
+    RunOptions, RunSummary, StopReason,
+};
+use std::io::Write;
 use std::io::{BufReader, Cursor};
 
 fn make_reader(lines: &[&str]) -> Box<dyn std::io::BufRead> {
@@ -11,7 +16,7 @@ fn test_basic_output_logic() {
     let input = make_reader(&["foo", "bar", "baz"]);
     let mut output = Vec::new();
 
-    let filter = |line: &[u8]| Ok(!line.contains(&b'b')); // Output lines that do NOT contain 'b'
+    let filter = |line: &[u8], _: &EvalContext| Ok(!line.contains(&b'b')); // Output lines that do NOT contain 'b'
 
     run_filter(input, &mut output, filter, false).unwrap();
 
@@ -25,7 +30,7 @@ fn test_crlf_conversion() {
     let input = Box::new(BufReader::new(Cursor::new(crlf_input)));
     let mut output = Vec::new();
 
-    let filter = |_line: &[u8]| Ok(true); // Output all lines
+    let filter = |_line: &[u8], _: &EvalContext| Ok(true); // Output all lines
 
     run_filter(input, &mut output, filter, false).unwrap();
 
@@ -38,7 +43,7 @@ fn test_filter_error_outputs_line() {
     let input = make_reader(&["ok line", "bad line"]);
     let mut output = Vec::new();
 
-    let filter = |line: &[u8]| {
+    let filter = |line: &[u8], _: &EvalContext| {
         let text = std::str::from_utf8(line)?;
         if text.contains("bad") {
             Err(anyhow::anyhow!("mock error"))
@@ -58,9 +63,383 @@ fn test_report_mode_no_output() {
     let input = make_reader(&["a", "b", "c"]);
     let mut output = Vec::new();
 
-    let filter = |_line: &[u8]| Ok(true);
+    let filter = |_line: &[u8], _: &EvalContext| Ok(true);
 
     run_filter(input, &mut output, filter, true).unwrap();
 
     assert_eq!(output.len(), 0); // Output is suppressed when --report is enabled
 }
+
+#[test]
+fn test_max_count_stops_early() {
+    let input = make_reader(&["a", "b", "c", "d"]);
+    let mut output = Vec::new();
+
+    let options = RunOptions {
+        max_count: Some(2),
+        ..Default::default()
+    };
+    let summary = run_filter_with(
+        input,
+        &mut output,
+        |_line: &[u8], _: &EvalContext| Ok(true),
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "a\nb\n");
+    assert_eq!(summary.total, 2); // Remaining lines are never read
+    assert_eq!(summary.stopped, Some((2, StopReason::MaxCount)));
+}
+
+#[test]
+fn test_stop_when_ends_before_matching_line() {
+    let input = make_reader(&["start", "work", "SHUTDOWN", "after"]);
+    let mut output = Vec::new();
+
+    let options = RunOptions {
+        stop_when: Some(Box::new(|line: &[u8], _: &EvalContext| {
+            Ok(line.starts_with(b"SHUTDOWN"))
+        })),
+        ..Default::default()
+    };
+    let summary = run_filter_with(
+        input,
+        &mut output,
+        |_line: &[u8], _: &EvalContext| Ok(true),
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "start\nwork\n");
+    assert_eq!(summary.total, 2);
+    assert_eq!(summary.stopped, Some((3, StopReason::StopCondition)));
+}
+
+#[test]
+fn test_no_stop_reads_everything() {
+    let input = make_reader(&["a", "b"]);
+    let mut output = Vec::new();
+
+    let summary = run_filter(
+        input,
+        &mut output,
+        |_line: &[u8], _: &EvalContext| Ok(true),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(summary.total, 2);
+    assert_eq!(summary.stopped, None);
+}
+
+#[test]
+fn test_context_carries_line_numbers() {
+    let input = make_reader(&["a", "b", "c"]);
+    let mut output = Vec::new();
+
+    let filter = |_line: &[u8], ctx: &EvalContext| Ok(ctx.line_no != 2);
+
+    run_filter(input, &mut output, filter, false).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "a\nc");
+}
+
+#[test]
+fn test_multiline_records() {
+    let input = make_reader(&["A=1", "B=2", "", "A=3", "", "A=4"]);
+    let mut output = Vec::new();
+
+    let options = RunOptions {
+        multiline: true,
+        ..Default::default()
+    };
+    let summary = run_filter_with(
+        input,
+        &mut output,
+        |record: &[u8], ctx: &EvalContext| Ok(ctx.line_no != 2 && record.starts_with(b"A=")),
+        &options,
+    )
+    .unwrap();
+
+    // The blank separator belongs to the record before it
+    assert_eq!(String::from_utf8(output).unwrap(), "A=1\nB=2\n\nA=4");
+    assert_eq!(summary.total, 3);
+}
+
+#[test]
+fn test_collapse_and_uniq() {
+    let input = make_reader(&["up", "down", "down", "down", "up", "up", "gone"]);
+    let mut output = Vec::new();
+
+    let options = RunOptions {
+        dedup: DedupOptions {
+            uniq: Some(100),
+            collapse: true,
+            normalize: false,
+        },
+        ..Default::default()
+    };
+    let summary = run_filter_with(
+        input,
+        &mut output,
+        |_line: &[u8], _: &EvalContext| Ok(true),
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "up\ndown\n(repeated 2 times)\ngone"
+    );
+    assert_eq!(summary.written, 3);
+    assert_eq!(summary.duplicates, 4);
+}
+
+fn run_on_file(path: &std::path::Path, options: &RunOptions, jobs: usize) -> (String, RunSummary) {
+    let file = std::fs::File::open(path).unwrap();
+    let mut output = Vec::new();
+    let filter = |line: &[u8], ctx: &EvalContext| {
+        Ok(!ctx.line_no.is_multiple_of(7) && !line.ends_with(b"3\n"))
+    };
+    let summary = run_parallel(file, &mut output, filter, options, jobs).unwrap();
+    (String::from_utf8(output).unwrap(), summary)
+}
+
+#[test]
+fn test_parallel_matches_streaming() {
+    // Large enough to span several chunks
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let mut writer = std::io::BufWriter::new(file.as_file());
+    for i in 0..400_000 {
+        writeln!(writer, "request {i} handled in {}ms", i % 97).unwrap();
+    }
+    write!(writer, "last line\r\nwithout newline").unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+
+    let options = RunOptions::default();
+    let (streamed, streamed_summary) = run_on_file(file.path(), &options, 1);
+    let (parallel, parallel_summary) = run_on_file(file.path(), &options, 4);
+    assert_eq!(parallel, streamed);
+    assert_eq!(parallel_summary, streamed_summary);
+
+    let options = RunOptions {
+        max_count: Some(250_000),
+        ..Default::default()
+    };
+    let (streamed, streamed_summary) = run_on_file(file.path(), &options, 1);
+    let (parallel, parallel_summary) = run_on_file(file.path(), &options, 3);
+    assert_eq!(parallel, streamed);
+    assert_eq!(parallel_summary, streamed_summary);
+    assert!(parallel_summary.stopped.is_some());
+}
+
+#[test]
+fn test_mmap_matches_streaming() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, "keep 1\r\ndrop 2\nkeep 3\nSTOP\nkeep 5").unwrap();
+    let filter = |line: &[u8], _: &EvalContext| Ok(line.starts_with(b"keep"));
+    let stop = || -> RunOptions {
+        RunOptions {
+            stop_when: Some(Box::new(|line: &[u8], _: &EvalContext| {
+                Ok(line.starts_with(b"STOP"))
+            })),
+            ..Default::default()
+        }
+    };
+
+    for options in [RunOptions::default(), stop()] {
+        let mut mapped = Vec::new();
+        let file_handle = std::fs::File::open(file.path()).unwrap();
+        let mapped_summary = run_mmap(file_handle, &mut mapped, filter, &options).unwrap();
+
+        let mut streamed = Vec::new();
+        let reader = Box::new(std::io::BufReader::new(
+            std::fs::File::open(file.path()).unwrap(),
+        ));
+        let streamed_summary = run_filter_with(reader, &mut streamed, filter, &options).unwrap();
+
+        assert_eq!(mapped, streamed);
+        assert_eq!(mapped_summary, streamed_summary);
+    }
+
+    // Empty files cannot be mapped and are streamed instead
+    let empty = tempfile::NamedTempFile::new().unwrap();
+    let mut output = Vec::new();
+    let summary = run_mmap(
+        std::fs::File::open(empty.path()).unwrap(),
+        &mut output,
+        filter,
+        &RunOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(summary.total, 0);
+}
+
+#[test]
+fn test_long_line_policies() {
+    let long = "y".repeat(30);
+    let tail = "z".repeat(20);
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, "keep a\nkeep {long}\nkeep b\r\n{tail}").unwrap();
+    let filter = |line: &[u8], _: &EvalContext| Ok(line.starts_with(b"keep"));
+
+    let expected = [
+        (
+            LongLinePolicy::Truncate,
+            "keep a\nkeep yyyyyyyyyyy\nkeep b\n".to_string(),
+        ),
+        (
+            LongLinePolicy::Pass,
+            format!("keep a\nkeep {long}\nkeep b\n{tail}"),
+        ),
+        (LongLinePolicy::Drop, "keep a\nkeep b\n".to_string()),
+    ];
+    for (policy, output) in expected {
+        let options = RunOptions {
+            line_limit: Some(LineLimit { max: 16, policy }),
+            ..Default::default()
+        };
+        let open = || std::fs::File::open(file.path()).unwrap();
+
+        // A tiny buffer makes the streaming engine see long lines in pieces
+        let mut streamed = Vec::new();
+        let reader = Box::new(std::io::BufReader::with_capacity(4, open()));
+        let summary = run_filter_with(reader, &mut streamed, filter, &options).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), output, "{policy:?}");
+        assert_eq!((summary.total, summary.long_lines), (4, 2), "{policy:?}");
+
+        let mut mapped = Vec::new();
+        assert_eq!(
+            run_mmap(open(), &mut mapped, filter, &options).unwrap(),
+            summary
+        );
+        assert_eq!(String::from_utf8(mapped).unwrap(), output, "{policy:?}");
+
+        let mut parallel = Vec::new();
+        assert_eq!(
+            run_parallel(open(), &mut parallel, filter, &options, 2).unwrap(),
+            summary
+        );
+        assert_eq!(String::from_utf8(parallel).unwrap(), output, "{policy:?}");
+    }
+
+    // Stopped by `max_count`, only the long lines up to the stop are counted
+    for policy in [LongLinePolicy::Pass, LongLinePolicy::Drop] {
+        let options = RunOptions {
+            line_limit: Some(LineLimit { max: 16, policy }),
+            max_count: Some(2),
+            ..Default::default()
+        };
+        let open = || std::fs::File::open(file.path()).unwrap();
+        let streamed =
+            run_filter_with(BufReader::new(open()), &mut Vec::new(), filter, &options).unwrap();
+        assert_eq!(streamed.long_lines, 1, "{policy:?}");
+        let parallel = run_parallel(open(), &mut Vec::new(), filter, &options, 2).unwrap();
+        assert_eq!(parallel, streamed, "{policy:?}");
+    }
+
+    let options = RunOptions {
+        line_limit: Some(LineLimit {
+            max: 16,
+            policy: LongLinePolicy::Error,
+        }),
+        ..Default::default()
+    };
+    let reader = Box::new(std::io::BufReader::new(
+        std::fs::File::open(file.path()).unwrap(),
+    ));
+    let err = run_filter_with(reader, &mut Vec::new(), filter, &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Line 2 is longer than the limit of 16 bytes"
+    );
+}
+
+#[test]
+fn test_long_lines_see_the_stop_condition() {
+    let input = format!("a\nSHUTDOWN {}\nb\n", "y".repeat(30));
+    for policy in [LongLinePolicy::Pass, LongLinePolicy::Drop] {
+        let options = RunOptions {
+            line_limit: Some(LineLimit { max: 16, policy }),
+            stop_when: Some(Box::new(|line: &[u8], _: &EvalContext| {
+                Ok(line.starts_with(b"SHUTDOWN"))
+            })),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let reader = BufReader::new(Cursor::new(input.clone()));
+        let filter = |_: &[u8], _: &EvalContext| Ok(true);
+        let summary = run_filter_with(reader, &mut output, filter, &options).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "a\n", "{policy:?}");
+        assert_eq!(summary.total, 1, "{policy:?}");
+        assert_eq!(
+            summary.stopped,
+            Some((2, StopReason::StopCondition)),
+            "{policy:?}"
+        );
+    }
+}
+
+// Fails every other read with `Interrupted`, as a read cut short by a signal does.
+struct Interrupting<R> {
+    inner: R,
+    interrupt: bool,
+}
+
+impl<R: std::io::Read> std::io::Read for Interrupting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn test_interrupted_reads_are_retried() {
+    let input = format!("a\n{}\nb\n", "y".repeat(30));
+    for (policy, expected) in [
+        (LongLinePolicy::Pass, input.clone()),
+        (LongLinePolicy::Drop, "a\nb\n".to_string()),
+    ] {
+        let options = RunOptions {
+            line_limit: Some(LineLimit { max: 16, policy }),
+            ..Default::default()
+        };
+        let reader = Interrupting {
+            inner: Cursor::new(input.clone()),
+            interrupt: false,
+        };
+        let mut output = Vec::new();
+        let filter = |_: &[u8], _: &EvalContext| Ok(true);
+        let reader = BufReader::with_capacity(4, reader);
+        let summary = run_filter_with(reader, &mut output, filter, &options).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), expected, "{policy:?}");
+        assert_eq!((summary.total, summary.long_lines), (3, 1), "{policy:?}");
+    }
+}
+
+#[test]
+fn test_long_multiline_record_is_skipped_whole() {
+    let input = format!("A=1\nB={}\nC=3\n\nA=2\n\n", "x".repeat(100));
+    let reader = Box::new(BufReader::with_capacity(8, Cursor::new(input)));
+    let options = RunOptions {
+        multiline: true,
+        line_limit: Some(LineLimit {
+            max: 32,
+            policy: LongLinePolicy::Drop,
+        }),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let filter = |_: &[u8], _: &EvalContext| Ok(true);
+    let summary = run_filter_with(reader, &mut output, filter, &options).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), "A=2\n\n");
+    assert_eq!((summary.total, summary.long_lines), (2, 1));
+}
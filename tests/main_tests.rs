@@ -20,6 +20,18 @@ fn test_chaf_filters_output() {
         .stdout(predicate::str::contains("debug").not());
 }
 
+#[test]
+fn test_chaf_reads_files_with_and_without_mmap() {
+    let mut file = NamedTempFile::new().unwrap();
+    write!(file, "error: failed\r\ninfo: ok\ndebug: trace").unwrap();
+
+    for args in [&[][..], &["--mmap"]] {
+        let mut cmd = Command::cargo_bin("chaf").unwrap();
+        cmd.args(args).arg("debug").arg(file.path());
+        cmd.assert().success().stdout("error: failed\ninfo: ok\n");
+    }
+}
+
 #[test]
 fn test_chaf_with_invert_flag() {
     let mut file = NamedTempFile::new().unwrap();
@@ -54,3 +66,807 @@ fn test_chaf_with_report_flag() {
         .stderr(predicate::str::contains("Excluded lines: 2"))
         .stderr(predicate::str::contains("Output lines: 1"));
 }
+
+#[test]
+fn test_chaf_with_max_count() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "one").unwrap();
+    writeln!(file, "debug").unwrap();
+    writeln!(file, "two").unwrap();
+    writeln!(file, "three").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["-m", "2", "debug"]).arg(file.path());
+
+    // Where the run stopped is reported without --report too
+    cmd.assert()
+        .success()
+        .stdout("one\ntwo\n")
+        .stderr(predicate::str::contains(
+            "Stopped at line 3: max count reached",
+        ));
+}
+
+#[test]
+fn test_chaf_with_stop_when_reports_position() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "info: up").unwrap();
+    writeln!(file, "info: shutdown requested").unwrap();
+    writeln!(file, "info: after").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["-r", "--stop-when", "shutdown", "debug"])
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Processed lines: 1"))
+        .stderr(predicate::str::contains(
+            "Stopped at line 2: stop condition matched",
+        ));
+}
+
+#[test]
+fn test_chaf_with_line_predicates() {
+    let mut file = NamedTempFile::new().unwrap();
+    for i in 1..=6 {
+        writeln!(file, "row {i}").unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("line<=2 | line in 4..6").arg(file.path());
+
+    cmd.assert().success().stdout("row 3\nrow 6\n");
+}
+
+#[test]
+fn test_chaf_with_between_block() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "subject: example").unwrap();
+    writeln!(file, "-----BEGIN CERTIFICATE-----").unwrap();
+    writeln!(file, "MIIB...").unwrap();
+    writeln!(file, "-----END CERTIFICATE-----").unwrap();
+    writeln!(file, "issuer: example").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg(r#"between("BEGIN CERT", "END CERT")"#)
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout("subject: example\nissuer: example\n");
+}
+
+#[test]
+fn test_chaf_with_csv_columns() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "id,status,note").unwrap();
+    writeln!(file, "1,500,\"error, retried\"").unwrap();
+    writeln!(file, "2,200,\"no error here\"").unwrap();
+    writeln!(file, "3,404,ok").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["-d", ",", "--header", "col(status) = 500 | $1 = 3"])
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout("id,status,note\n2,200,\"no error here\"\n");
+}
+
+#[test]
+fn test_chaf_with_jsonl_paths() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, r#"{{"level":"debug","msg":"tick"}}"#).unwrap();
+    writeln!(
+        file,
+        r#"{{"level":"info","http":{{"status":503}},"trace_id":"t1"}}"#
+    )
+    .unwrap();
+    writeln!(file, r#"{{"level":"info","http":{{"status":200}}}}"#).unwrap();
+    writeln!(file, "plain text with debug").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args([
+        "--format=jsonl",
+        r#".level == "debug" | (.http.status >= 500 & has(.trace_id))"#,
+    ])
+    .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r#""status":200"#))
+        .stdout(predicate::str::contains("plain text with debug"))
+        .stdout(predicate::str::contains("tick").not())
+        .stdout(predicate::str::contains("t1").not());
+}
+
+#[test]
+fn test_chaf_with_logfmt_fields() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "level=debug msg=\"cache warm\"").unwrap();
+    writeln!(file, "level=info msg=\"login\" user=alice").unwrap();
+    writeln!(file, "level=info msg=\"healthcheck\"").unwrap();
+    writeln!(file, "level=error msg=\"request timeout\" user=bob").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--format=logfmt", "level=debug | !has(user) | timeout"])
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout("level=info msg=\"login\" user=alice\n");
+}
+
+#[test]
+fn test_chaf_with_duration_comparison() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "path=/a dur=12ms").unwrap();
+    writeln!(file, "path=/b dur=1.2s").unwrap();
+    writeln!(file, "path=/c dur=n/a").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--format=logfmt", "-i", "dur>100ms"])
+        .arg(file.path());
+
+    cmd.assert().success().stdout("path=/b dur=1.2s\n");
+}
+
+#[test]
+fn test_chaf_with_time_window() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "2024-05-01 13:59:00 INFO before").unwrap();
+    writeln!(file, "2024-05-01 14:05:00 ERROR boom").unwrap();
+    writeln!(file, "    at handler (app.js:10)").unwrap();
+    writeln!(file, "2024-05-01 14:10:00 DEBUG noise").unwrap();
+    writeln!(file, "2024-05-01 14:30:00 INFO after").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--timezone=UTC", "--since=14:00", "--until=14:30", "DEBUG"])
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout("2024-05-01 14:05:00 ERROR boom\n    at handler (app.js:10)\n");
+}
+
+#[test]
+fn test_chaf_rejects_invalid_time() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--since=yesterday", "x"]).write_stdin("x\n");
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid time 'yesterday' for --since",
+    ));
+}
+
+#[test]
+fn test_chaf_with_syslog_severity() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "<30>Oct 11 22:14:15 web1 nginx[80]: reloaded").unwrap();
+    writeln!(file, "<76>Oct 11 22:14:16 web1 CRON[99]: job failed").unwrap();
+    writeln!(file, "<27>Oct 11 22:14:17 web1 app[12]: disk full").unwrap();
+    writeln!(file, "<28>Oct 11 22:14:18 web1 app[12]: disk almost full").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--format=syslog", "-i", "severity<=warning & !program=CRON"])
+        .arg(file.path());
+
+    cmd.assert().success().stdout(
+        "<27>Oct 11 22:14:17 web1 app[12]: disk full\n\
+         <28>Oct 11 22:14:18 web1 app[12]: disk almost full\n",
+    );
+}
+
+#[test]
+fn test_chaf_with_combined_log() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, r#"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] "GET /health HTTP/1.1" 200 2 "-" "kube-probe/1.27""#).unwrap();
+    writeln!(
+        file,
+        r#"1.2.3.4 - - [10/Oct/2000:13:55:37 -0700] "POST /login HTTP/1.1" 500 512 "-" "curl/8.0""#
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--format=combined", "path=/health | status<500"])
+        .arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("POST /login"))
+        .stdout(predicate::str::contains("/health").not());
+}
+
+#[test]
+fn test_chaf_with_journal_export() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--format=journal-export", "severity>notice"])
+        .write_stdin("MESSAGE=started\nPRIORITY=6\n\nMESSAGE=oom\nPRIORITY=2\n\n");
+
+    cmd.assert().success().stdout("MESSAGE=oom\nPRIORITY=2\n\n");
+}
+
+#[test]
+fn test_chaf_collapse_with_normalize() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--collapse", "--normalize", "DEBUG"])
+        .write_stdin("conn 17 refused\nconn 18 refused\nDEBUG tick\nconn 19 refused\nready\n");
+
+    // The DEBUG line is excluded, so the refusals stay consecutive
+    cmd.assert()
+        .success()
+        .stdout("conn 17 refused\n(repeated 2 times)\nready\n");
+}
+
+#[test]
+fn test_chaf_with_rate_limit() {
+    let input = [
+        "2024-05-01T00:00:00.000Z retry",
+        "2024-05-01T00:00:00.100Z retry",
+        "2024-05-01T00:00:00.200Z ok",
+        "2024-05-01T00:00:00.300Z retry",
+        "2024-05-01T00:00:01.200Z retry",
+    ]
+    .join("\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--seed=1", "limit(1/s, retry)"])
+        .write_stdin(input);
+
+    cmd.assert().success().stdout(
+        "2024-05-01T00:00:00.000Z retry\n\
+         2024-05-01T00:00:00.200Z ok\n\
+         2024-05-01T00:00:01.200Z retry",
+    );
+}
+
+#[test]
+fn test_chaf_parallel_jobs() {
+    let mut file = NamedTempFile::new().unwrap();
+    for i in 0..1000 {
+        writeln!(
+            file,
+            "{} line {i}",
+            if i % 3 == 0 { "DEBUG" } else { "INFO" }
+        )
+        .unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["-j", "4", "-r", "DEBUG"]).arg(file.path());
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Processed lines: 1000"))
+        .stderr(predicate::str::contains("Output lines: 666"));
+}
+
+#[test]
+fn test_chaf_dump_bytecode() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--dump-bytecode", "DEBUG & !retry"]);
+
+    cmd.assert().success().stdout(
+        "0000  contains \"DEBUG\"\n\
+         0001  jump_if_false @0004\n\
+         0002  contains \"retry\"\n\
+         0003  not\n\
+         0004  return\n",
+    );
+}
+
+#[test]
+fn test_chaf_rejects_deeply_nested_query() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-depth=3", "((((a))))"]).write_stdin("a\n");
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Query nests too deeply (limit 3)"));
+}
+
+#[test]
+fn test_chaf_runs_queries_at_the_maximum_limits() {
+    // `!` and parentheses nested as deep as --max-depth allows; 500 `!` cancel out
+    let deep = format!("{}{}{}", "!(".repeat(499), "!a", ")".repeat(499));
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-depth=1000", &deep]).write_stdin("a\nb\n");
+    cmd.assert().success().stdout("b\n");
+
+    // As many terms as --max-terms allows, in one `|` chain
+    let mut query = NamedTempFile::new().unwrap();
+    let terms = (0..1_000_000).map(|i| format!("t{}", i));
+    write!(query, "{}", terms.collect::<Vec<_>>().join(" | ")).unwrap();
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-terms=1000000", "-F"])
+        .arg(query.path())
+        .write_stdin("t999999\nkeep\n");
+    cmd.assert().success().stdout("keep\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-depth=1001", "a"]).write_stdin("a\n");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("1001 is not in 1..=1000"));
+}
+
+#[test]
+fn test_chaf_long_line_policy() {
+    let input = format!("short\n{}\nend\n", "x".repeat(100));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-line-length=8", "--on-long-line=truncate", "end"])
+        .write_stdin(input.clone());
+    cmd.assert().success().stdout("short\nxxxxxxxx\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--max-line-length=8", "end"]).write_stdin(input);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Line 2 is longer than the limit of 8 bytes",
+    ));
+}
+
+#[test]
+fn test_chaf_ignore_case() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--ignore-case", "debug"])
+        .write_stdin("DEBUG tick\nError\nDebug: retry\n");
+    cmd.assert().success().stdout("Error\n");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_chaf_query_json_round_trip() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--emit-json", "debug | warn & !API"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+
+    let mut query = NamedTempFile::new().unwrap();
+    query.write_all(&output).unwrap();
+    let mut input = NamedTempFile::new().unwrap();
+    writeln!(
+        input,
+        "error: failed\nwarn: low disk\nwarn: API deprecated\ndebug: retry"
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("--query-json").arg(query.path()).arg(input.path());
+    cmd.assert()
+        .success()
+        .stdout("error: failed\nwarn: API deprecated\n");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_chaf_query_json_is_validated() {
+    let mut query = NamedTempFile::new().unwrap();
+    write!(
+        query,
+        r#"{{"Thin":[{{"Match":{{"Literal":"x"}}}},{{"Sample":2.0}}]}}"#
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("--query-json").arg(query.path()).write_stdin("x\n");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Expected a rate such as 0.01 or 1%",
+    ));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("--query-json")
+        .arg(query.path())
+        .args(["in.log", "extra"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with --query-json"));
+}
+
+#[test]
+fn test_chaf_query_file() {
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "# health checks and debug noise").unwrap();
+    writeln!(query, "DEBUG").unwrap();
+    writeln!(query, "| healthcheck").unwrap();
+    let mut input = NamedTempFile::new().unwrap();
+    writeln!(input, "GET /healthcheck 200").unwrap();
+    writeln!(input, "ERROR #42 failed").unwrap();
+    writeln!(input, "DEBUG tick").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("-F").arg(query.path()).arg(input.path());
+    cmd.assert().success().stdout("ERROR #42 failed\n");
+}
+
+#[test]
+fn test_chaf_fmt_query() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "((a)) |b&(c|d)"]);
+    cmd.assert().success().stdout("a | b & (c | d)\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "--width", "6", "alpha | beta & !gamma"]);
+    cmd.assert().success().stdout("alpha\n| beta & !gamma\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "a &"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Syntax error"));
+}
+
+#[test]
+fn test_chaf_fmt_query_file() {
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "#noise").unwrap();
+    writeln!(query, "  (DEBUG)|TRACE").unwrap();
+    writeln!(query, "  # see runbook").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("fmt").arg("-F").arg(query.path());
+    cmd.assert()
+        .success()
+        .stdout("#noise\nDEBUG | TRACE\n  # see runbook\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "--check", "-F"]).arg(query.path());
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Not formatted"));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "--write", "-F"]).arg(query.path());
+    cmd.assert().success().stdout("");
+    assert_eq!(
+        std::fs::read_to_string(query.path()).unwrap(),
+        "#noise\nDEBUG | TRACE\n  # see runbook\n"
+    );
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "--check", "-F"]).arg(query.path());
+    cmd.assert().success();
+
+    // A comment in the middle of the query is not moved; the file is left alone
+    let text = "DEBUG\n# health checks\n|healthz\n";
+    std::fs::write(query.path(), text).unwrap();
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "--write", "-F"]).arg(query.path());
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Line 2: a comment inside the query would move",
+    ));
+    assert_eq!(std::fs::read_to_string(query.path()).unwrap(), text);
+}
+
+#[test]
+fn test_chaf_filter_command() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["filter", "-i", "error"])
+        .write_stdin("error: failed\ninfo: ok\n");
+    cmd.assert().success().stdout("error: failed\n");
+}
+
+#[test]
+fn test_chaf_query_named_like_a_command() {
+    let input = "check passed\ncheck failed\nready\n";
+    // `check` is the command, with `failed` as its query
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "failed"]).write_stdin(input);
+    cmd.assert().success().stdout("query: ok\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["filter", "check"]).write_stdin(input);
+    cmd.assert().success().stdout("ready\n");
+}
+
+#[test]
+fn test_chaf_explain() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["explain", "DEBUG | healthcheck & !line <= 10"]);
+    cmd.assert().success().stdout(
+        "Drops the lines that match:
+  any of:
+    the line contains \"DEBUG\"
+    all of:
+      the line contains \"healthcheck\"
+      not:
+        the line number is at most \"10\"
+",
+    );
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["explain", "--invert", "-f", "logfmt", "level=error"]);
+    cmd.assert()
+        .success()
+        .stdout("Keeps only the lines that match:\n  field level is \"error\"\n");
+}
+
+#[test]
+fn test_chaf_check() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "DEBUG | healthcheck"]);
+    cmd.assert().success().stdout("query: ok\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "DEBUG |"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("query: Empty pattern"));
+
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "col(status) >= 500").unwrap();
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "-F"]).arg(query.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("requires a header row"));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "--header", "-F"]).arg(query.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::ends_with(": ok\n"));
+}
+
+#[test]
+fn test_chaf_test() {
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "# Debug noise").unwrap();
+    writeln!(query, "DEBUG | healthcheck").unwrap();
+    writeln!(query, "# keep: ERROR disk full").unwrap();
+    writeln!(query, "# drop: DEBUG cache hit").unwrap();
+    let path = query.path().display().to_string();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("test").arg(query.path());
+    cmd.assert().success().stdout(format!(
+        "PASS {path}:3 keep: ERROR disk full\n\
+         PASS {path}:4 drop: DEBUG cache hit\n\
+         2 passed, 0 failed\n"
+    ));
+
+    writeln!(query, "# drop: GET /health 200").unwrap();
+    writeln!(query, "# keep: GET /healthcheck 200").unwrap();
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("test").arg(query.path());
+    cmd.assert().failure().stdout(format!(
+        "PASS {path}:3 keep: ERROR disk full\n\
+         PASS {path}:4 drop: DEBUG cache hit\n\
+         FAIL {path}:5 drop: GET /health 200 (the line was kept)\n\
+         FAIL {path}:6 keep: GET /healthcheck 200 (the line was dropped)\n\
+         2 passed, 2 failed\n"
+    ));
+}
+
+#[test]
+fn test_chaf_test_invert() {
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "level=error | level=warn").unwrap();
+    writeln!(query, "# keep: level=ERROR msg=\"disk full\"").unwrap();
+    writeln!(query, "# drop: level=info msg=ok").unwrap();
+
+    // As a grep-like filter, the lines the query matches are the ones kept
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["test", "--invert", "-f", "logfmt"])
+        .arg(query.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::ends_with("2 passed, 0 failed\n"));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["test", "-f", "logfmt"]).arg(query.path());
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::ends_with("0 passed, 2 failed\n"));
+}
+
+#[test]
+fn test_chaf_test_bad_query() {
+    let mut query = NamedTempFile::new().unwrap();
+    writeln!(query, "DEBUG &").unwrap();
+    writeln!(query, "# keep: INFO").unwrap();
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.arg("test").arg(query.path());
+    cmd.assert()
+        .failure()
+        .stdout("0 passed, 0 failed\n")
+        .stderr(predicate::str::contains("Empty pattern"));
+}
+
+#[test]
+fn test_chaf_completions() {
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["completions", "bash"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("_chaf()"))
+        .stdout(predicate::str::contains("--query-file"));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["completions", "tcsh"]);
+    cmd.assert().failure();
+}
+
+// A project directory with a `chaf.toml`, and an empty user config directory.
+fn profile_dirs(config: &str) -> (tempfile::TempDir, tempfile::TempDir) {
+    let project = tempfile::tempdir().unwrap();
+    std::fs::write(project.path().join("chaf.toml"), config).unwrap();
+    (project, tempfile::tempdir().unwrap())
+}
+
+#[test]
+fn test_chaf_profile() {
+    let (project, user) = profile_dirs(
+        r#"
+[profiles.noise]
+query = "healthcheck | debug"
+ignore-case = true
+patterns = ["noise.txt"]
+
+[profiles.errors]
+query = "ERROR"
+invert = true
+"#,
+    );
+    std::fs::write(
+        project.path().join("noise.txt"),
+        "# known noise\nkube-probe\n\n",
+    )
+    .unwrap();
+    let input = "GET /healthcheck\nDEBUG tick\nkube-probe/1.29\nERROR boom\n";
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["-p", "noise"]);
+    cmd.write_stdin(input)
+        .assert()
+        .success()
+        .stdout("ERROR boom\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["--profile", "errors"]);
+    cmd.write_stdin(input)
+        .assert()
+        .success()
+        .stdout("ERROR boom\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .arg("@profile(noise) | boom");
+    // A reference brings in only the query, so `debug` stays case-sensitive
+    cmd.write_stdin(input)
+        .assert()
+        .success()
+        .stdout("DEBUG tick\n");
+}
+
+#[test]
+fn test_chaf_profile_from_user_config() {
+    let (project, user) = profile_dirs("[profiles.ticks]\nquery = \"tick\"\n");
+    std::fs::create_dir(user.path().join("chaf")).unwrap();
+    std::fs::write(
+        user.path().join("chaf/chaf.toml"),
+        "[profiles.ticks]\nquery = \"never\"\n[profiles.mine]\nquery = \"@profile(ticks) | GET\"\n",
+    )
+    .unwrap();
+
+    // The project's `ticks` replaces the user's
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["-p", "mine"]);
+    cmd.write_stdin("GET /\nDEBUG tick\nERROR boom\n")
+        .assert()
+        .success()
+        .stdout("ERROR boom\n");
+}
+
+#[test]
+fn test_chaf_profile_errors() {
+    let (project, user) = profile_dirs(
+        "[profiles.a]\nquery = \"x | @profile(b)\"\n[profiles.b]\nquery = \"@profile(a)\"\n\
+         [profiles.err]\nquery = \"level=error\"\nformat = \"logfmt\"\n",
+    );
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["-p", "a"]);
+    cmd.write_stdin("x\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Profiles refer to each other in a cycle: a -> b -> a",
+        ));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .arg("@profile(missing)");
+    cmd.write_stdin("x\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown profile 'missing'"));
+
+    // The query of `err` would never match records of another format
+    for args in [&["@profile(err)"][..], &["-f", "jsonl", "@profile(err)"]] {
+        let mut cmd = Command::cargo_bin("chaf").unwrap();
+        cmd.current_dir(project.path())
+            .env("XDG_CONFIG_HOME", user.path())
+            .args(args);
+        cmd.write_stdin("level=error\n")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Profile 'err' is for another format (run it with -p err)",
+            ));
+    }
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["-f", "logfmt", "@profile(err)"]);
+    cmd.write_stdin("level=error\nlevel=info\n")
+        .assert()
+        .success()
+        .stdout("level=info\n");
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.current_dir(project.path())
+        .env("XDG_CONFIG_HOME", user.path())
+        .args(["check", "@profile(a)"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "query: Profiles refer to each other",
+    ));
+}
+
+#[test]
+fn test_chaf_params() {
+    let input = "api: GET /\nweb: GET /\napi: ERROR boom\n";
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.env("CHAF_TEST_LEVEL", "ERROR")
+        .args(["--param", "svc=api:", "$svc & !${CHAF_TEST_LEVEL}"]);
+    cmd.write_stdin(input)
+        .assert()
+        .success()
+        .stdout("web: GET /\napi: ERROR boom\n");
+
+    // A value is a literal, not more query
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--param", "svc=api | web", "$svc"]);
+    cmd.write_stdin(input).assert().success().stdout(input);
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.env_remove("CHAF_TEST_UNSET")
+        .arg("x | ${CHAF_TEST_UNSET}");
+    cmd.write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Environment variable 'CHAF_TEST_UNSET' is not set: pos=4",
+        ));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["--param", "svc", "$svc"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("expected NAME=VALUE"));
+
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["check", "--param", "min=500", "$min | $max"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("No value for parameter '$max'"));
+
+    // Formatting keeps the placeholders
+    let mut cmd = Command::cargo_bin("chaf").unwrap();
+    cmd.args(["fmt", "$svc|line<=${CHAF_TEST_UNSET}"]);
+    cmd.assert()
+        .success()
+        .stdout("$svc | line <= ${CHAF_TEST_UNSET}\n");
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "tokio")]
+
+use chaf::{Filter, Format, LineStream, Query};
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use tokio::io::{duplex, AsyncWriteExt, BufReader, DuplexStream};
+
+type Lines = LineStream<BufReader<DuplexStream>>;
+
+async fn next(stream: &mut Lines) -> Option<Vec<u8>> {
+    poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx))
+        .await
+        .map(Result::unwrap)
+}
+
+fn stream(query: &str, capacity: usize) -> (Lines, DuplexStream) {
+    let filter = Filter::new(&Query::parse(query).unwrap()).unwrap();
+    let (writer, reader) = duplex(capacity);
+    (filter.stream(BufReader::new(reader)), writer)
+}
+
+#[tokio::test]
+async fn test_stream_lines_split_across_writes() {
+    let (mut lines, mut writer) = stream("DEBUG", 64);
+    tokio::spawn(async move {
+        for chunk in ["INFO st", "art\r\nDEBUG ti", "ck\nERROR ", "stop"] {
+            writer.write_all(chunk.as_bytes()).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+    });
+
+    assert_eq!(next(&mut lines).await.unwrap(), b"INFO start\n");
+    assert_eq!(next(&mut lines).await.unwrap(), b"ERROR stop");
+    assert_eq!(next(&mut lines).await, None);
+    assert_eq!(lines.filter().line_no(), 3);
+}
+
+#[tokio::test]
+async fn test_stream_waits_for_input() {
+    let (mut lines, mut writer) = stream("DEBUG", 64);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    assert!(Pin::new(&mut lines).poll_next(&mut cx).is_pending());
+    writer.write_all(b"DEBUG tick\n").await.unwrap();
+    assert!(Pin::new(&mut lines).poll_next(&mut cx).is_pending());
+    writer.write_all(b"INFO ok\n").await.unwrap();
+    match Pin::new(&mut lines).poll_next(&mut cx) {
+        Poll::Ready(Some(Ok(line))) => assert_eq!(line, b"INFO ok\n"),
+        other => panic!("unexpected poll result: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_stream_backpressure_and_cancellation() {
+    let (mut lines, mut writer) = stream("DEBUG", 16);
+    let producer = tokio::spawn(async move {
+        let mut written = 0;
+        for i in 0..1000 {
+            if writer
+                .write_all(format!("line {i}\n").as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+            written += 1;
+        }
+        written
+    });
+
+    assert_eq!(next(&mut lines).await.unwrap(), b"line 0\n");
+    drop(lines);
+    // The producer is held back by the small pipe, then fails once the stream is gone
+    let written = producer.await.unwrap();
+    assert!(written < 100, "wrote {written} lines");
+}
+
+#[tokio::test]
+async fn test_stream_multiline_records() {
+    let query = Query::parse_for("PRIORITY=7", Format::JournalExport).unwrap();
+    let (reader, mut writer) = duplex(64);
+    let mut lines = Filter::new(&query).unwrap().stream(BufReader::new(reader));
+    tokio::spawn(async move {
+        writer
+            .write_all(b"MESSAGE=a\nPRIORITY=7\n\nMESSAGE=b\nPRIORITY=3\n\n")
+            .await
+            .unwrap();
+    });
+
+    assert_eq!(
+        next(&mut lines).await.unwrap(),
+        b"MESSAGE=b\nPRIORITY=3\n\n"
+    );
+    assert_eq!(next(&mut lines).await, None);
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "tui")]
+
+use chaf::tui::{App, TuiSettings};
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::Modifier;
+use ratatui::Terminal;
+use std::path::PathBuf;
+
+fn app(query: &str, save_path: PathBuf) -> App {
+    let lines = ["GET /healthcheck 200", "ERROR disk full", "DEBUG cache hit"]
+        .map(String::from)
+        .to_vec();
+    App::new(lines, query.to_string(), save_path, TuiSettings::default())
+}
+
+fn draw(app: &mut App) -> Buffer {
+    let mut terminal = Terminal::new(TestBackend::new(50, 8)).unwrap();
+    terminal.draw(|frame| app.draw(frame)).unwrap();
+    terminal.backend().buffer().clone()
+}
+
+fn row(buffer: &Buffer, y: u16) -> String {
+    (0..buffer.area.width)
+        .map(|x| buffer[(x, y)].symbol())
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn type_text(app: &mut App, text: &str) {
+    for c in text.chars() {
+        app.handle_key(KeyEvent::from(KeyCode::Char(c)));
+    }
+}
+
+#[test]
+fn test_tui_preview() {
+    let mut app = app("DEBUG", PathBuf::from("query.chaf"));
+    type_text(&mut app, " | health");
+    assert_eq!(app.query(), "DEBUG | health");
+    assert_eq!(app.counts(), (1, 2));
+
+    let buffer = draw(&mut app);
+    assert_eq!(
+        row(&buffer, 1),
+        "│DEBUG | health                                  │"
+    );
+    assert_eq!(row(&buffer, 4), "1 GET /healthcheck 200");
+    assert_eq!(row(&buffer, 5), "2 ERROR disk full");
+    assert!(row(&buffer, 7).starts_with("kept 1 | excluded 2 | Ctrl-S: save to query.chaf"));
+    // Excluded lines are dimmed
+    assert!(buffer[(2, 4)].modifier.contains(Modifier::DIM));
+    assert!(!buffer[(2, 5)].modifier.contains(Modifier::DIM));
+}
+
+#[test]
+fn test_tui_shows_errors_inline() {
+    let mut app = app("DEBUG", PathBuf::from("query.chaf"));
+    type_text(&mut app, " &");
+    assert!(app.error().is_some());
+    // The last query that parsed stays in effect
+    assert_eq!(app.counts(), (2, 1));
+
+    let buffer = draw(&mut app);
+    assert_eq!(
+        row(&buffer, 1),
+        "│DEBUG &                                         │"
+    );
+    assert_eq!(
+        row(&buffer, 2),
+        "│       ^ Empty pattern                          │"
+    );
+
+    app.handle_key(KeyEvent::from(KeyCode::Backspace));
+    app.handle_key(KeyEvent::from(KeyCode::Backspace));
+    assert!(app.error().is_none());
+    assert_eq!(app.query(), "DEBUG");
+}
+
+#[test]
+fn test_tui_save() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("noise.chaf");
+    std::fs::write(&path, "# noise\nDEBUG\n# keep: ERROR x\n").unwrap();
+
+    let mut app = app("DEBUG", path.clone());
+    type_text(&mut app, " | health");
+    app.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "# noise\nDEBUG | health\n# keep: ERROR x\n"
+    );
+    let buffer = draw(&mut app);
+    assert!(row(&buffer, 7).starts_with("kept 1 | excluded 2 | Saved to "));
+
+    // A query with errors is not saved
+    type_text(&mut app, " |");
+    app.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("DEBUG | health\n"));
+
+    app.handle_key(KeyEvent::from(KeyCode::Esc));
+    assert!(app.should_quit());
+}
+
+#[test]
+fn test_tui_filters_large_previews_in_steps() {
+    let lines = (0..25_000).map(|i| format!("line {i}")).collect();
+    let settings = TuiSettings::default();
+    let mut app = App::new(lines, "0".into(), PathBuf::from("q.chaf"), settings).truncated(true);
+    // The first chunk is filtered right away, the rest in later steps
+    assert!(app.is_filtering());
+    assert_eq!(app.counts(), (7380, 2620));
+    assert!(row(&draw(&mut app), 7).starts_with("kept 7380 | excluded 2620 | filtering..."));
+    while app.is_filtering() {
+        app.step(10_000);
+    }
+    assert_eq!(app.counts(), (16_857, 8143));
+    assert!(row(&draw(&mut app), 7).starts_with("kept 16857 | excluded 8143 of the first 25000"));
+
+    // A new query starts over
+    type_text(&mut app, "0");
+    assert_eq!(app.counts(), (9820, 180));
+}